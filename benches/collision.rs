@@ -0,0 +1,162 @@
+//! Times cell by cell collision tests against the row masks of `bitboard`, on random stacks
+//! with holes. The tests of `bitboard` check both give the same answers.
+//!
+//! `cargo bench --bench collision`
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use tetris_bevy::bitboard::{Bitboard, CollisionMasks};
+use tetris_bevy::movegen::landings;
+use tetris_bevy::resource::{BoardMap, Tetromino};
+use tetris_bevy::ruleset::Ruleset;
+
+const BOARDS: usize = 64;
+const ROUNDS: u32 = 20;
+
+// how `BoardMap` tested a position before the masks
+fn cells_fit(rows: &[u16; 20], cells: &[(i8, i8)], (x, y): (i8, i8)) -> bool {
+    for &(offset_x, offset_y) in cells {
+        let col = x + offset_x;
+        let row = y + offset_y;
+        if !(0..10).contains(&col) || !(0..20).contains(&row) {
+            return false;
+        }
+        if (rows[row as usize] >> (9 - col)) & 1 == 1 {
+            return false;
+        }
+    }
+    true
+}
+
+fn cells_drop(rows: &[u16; 20], cells: &[(i8, i8)], (x, y): (i8, i8)) -> i8 {
+    let mut y = y;
+    while cells_fit(rows, cells, (x, y - 1)) {
+        y -= 1;
+    }
+    y
+}
+
+fn boards(rng: &mut StdRng) -> Vec<BoardMap> {
+    (0..BOARDS)
+        .map(|_| {
+            let mut board_map = BoardMap::new(&Ruleset::default());
+            for col in 0..10 {
+                for row in 0..rng.gen_range(0..12) {
+                    if rng.gen_bool(0.85) {
+                        board_map.tile_set(row, col, Some(Tetromino::X));
+                    }
+                }
+            }
+            board_map
+        })
+        .collect()
+}
+
+fn time(name: &str, count: usize, mut run: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        run();
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+    println!(
+        "{name:<24} {:>10.2?}  {:>7.2} ns each",
+        elapsed,
+        elapsed.as_nanos() as f64 / count as f64
+    );
+    elapsed
+}
+
+fn main() {
+    let ruleset = Ruleset::default();
+    let system = ruleset.rotation_system();
+    let masks = CollisionMasks::new(system);
+    let boards = boards(&mut StdRng::seed_from_u64(1));
+    let rows: Vec<[u16; 20]> = boards.iter().map(BoardMap::rows_get).collect();
+    let bitboards: Vec<Bitboard> = rows.iter().map(Bitboard::new).collect();
+    let positions: Vec<(Tetromino, u8, (i8, i8))> = (0..system.piece_count())
+        .flat_map(|piece| {
+            (0..4).flat_map(move |rotation| {
+                (-3..13)
+                    .flat_map(move |x| (-3..23).map(move |y| (Tetromino(piece), rotation, (x, y))))
+            })
+        })
+        .collect();
+
+    // the search hardly ever tries a piece outside the board
+    let inside: Vec<(Tetromino, u8, (i8, i8))> = positions
+        .iter()
+        .copied()
+        .filter(|&(tetromino, rotation, position)| {
+            cells_fit(&[0; 20], system.cells(tetromino, rotation), position)
+        })
+        .collect();
+    let count = rows.len() * inside.len();
+    println!("{count} collision tests inside the board");
+    let cells = time("cells", count, || {
+        for rows in &rows {
+            for &(tetromino, rotation, position) in &inside {
+                black_box(cells_fit(rows, system.cells(tetromino, rotation), position));
+            }
+        }
+    });
+    let bits = time("masks", count, || {
+        for bitboard in &bitboards {
+            for &(tetromino, rotation, position) in &inside {
+                black_box(masks.fits(bitboard, tetromino, rotation, position));
+            }
+        }
+    });
+    println!("{:.1}x faster", cells.as_secs_f64() / bits.as_secs_f64());
+    time("preparing the boards", rows.len(), || {
+        for rows in &rows {
+            black_box(Bitboard::new(rows));
+        }
+    });
+    println!();
+
+    // hard drops from everywhere the piece fits, under overhangs too
+    let drops: Vec<(usize, Tetromino, u8, (i8, i8))> = rows
+        .iter()
+        .enumerate()
+        .flat_map(|(board, rows)| {
+            positions
+                .iter()
+                .filter(|&&(tetromino, rotation, position)| {
+                    cells_fit(rows, system.cells(tetromino, rotation), position)
+                })
+                .map(move |&(tetromino, rotation, position)| (board, tetromino, rotation, position))
+        })
+        .collect();
+    println!("{} hard drops", drops.len());
+    let cells = time("cells, row by row", drops.len(), || {
+        for &(board, tetromino, rotation, position) in &drops {
+            black_box(cells_drop(
+                &rows[board],
+                system.cells(tetromino, rotation),
+                position,
+            ));
+        }
+    });
+    let bits = time("masks, column heights", drops.len(), || {
+        for &(board, tetromino, rotation, position) in &drops {
+            black_box(masks.drop_row(&bitboards[board], tetromino, rotation, position));
+        }
+    });
+    println!("{:.1}x faster\n", cells.as_secs_f64() / bits.as_secs_f64());
+
+    let spawned: Vec<BoardMap> = boards
+        .iter()
+        .map(|board_map| {
+            let mut board_map = board_map.clone();
+            board_map.player_spawn(Tetromino::T);
+            board_map
+        })
+        .collect();
+    time("move generator", spawned.len(), || {
+        for board_map in &spawned {
+            black_box(landings(board_map));
+        }
+    });
+}
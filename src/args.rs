@@ -0,0 +1,42 @@
+//! Command line helpers of the game and the tools, `--name value` pairs anywhere in the
+//! arguments.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The value after `name`.
+pub fn arg_get<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+}
+
+/// The value after `name` parsed, `None` without one.
+pub fn arg_parse_some<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>, String>
+where
+    T::Err: Display,
+{
+    arg_get(args, name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| format!("bad {name} {value}: {e}"))
+        })
+        .transpose()
+}
+
+/// The value after `name` parsed, `default` without one.
+pub fn arg_parse<T: FromStr>(args: &[String], name: &str, default: T) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    Ok(arg_parse_some(args, name)?.unwrap_or(default))
+}
+
+/// The value, or the error printed and the program ended, for the tools.
+pub fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(2)
+    })
+}
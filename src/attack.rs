@@ -0,0 +1,306 @@
+use crate::checksum::{rng_write, GameRng};
+use crate::resource::*;
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+// guideline attack for 0 to 4 lines, bigger clears from larger piece sets count as 4
+const ATTACK_TABLE: [usize; 5] = [0, 0, 1, 2, 4];
+const TSPIN_ATTACK_TABLE: [usize; 4] = [0, 2, 4, 6];
+const TSPIN_MINI_ATTACK_TABLE: [usize; 3] = [0, 0, 1];
+// extra lines by combo count, the first clear is combo 0
+const COMBO_TABLE: [usize; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+const B2B_BONUS: usize = 1;
+const PERFECT_CLEAR_BONUS: usize = 10;
+
+/// Lines a clear sends before cancelling, `combo` is the combo count including this clear and
+/// `b2b` whether the last difficult clear came right before this one.
+pub fn attack_lines(line_clear: LineClear, combo: u32, b2b: bool) -> usize {
+    let lines = line_clear.lines as usize;
+    if lines == 0 {
+        return 0;
+    }
+    let base = match line_clear.tspin {
+        TSpin::Full => TSPIN_ATTACK_TABLE[lines.min(3)],
+        TSpin::Mini => TSPIN_MINI_ATTACK_TABLE[lines.min(2)],
+        TSpin::None => ATTACK_TABLE[lines.min(4)],
+    };
+    let combo_bonus = COMBO_TABLE[(combo as usize).min(COMBO_TABLE.len() - 1)];
+    let b2b_bonus = if b2b && difficult(line_clear) {
+        B2B_BONUS
+    } else {
+        0
+    };
+    let perfect_bonus = if line_clear.perfect {
+        PERFECT_CLEAR_BONUS
+    } else {
+        0
+    };
+    base + combo_bonus + b2b_bonus + perfect_bonus
+}
+
+/// Tetrises and T-spin clears keep back-to-back going, other clears break it.
+pub fn difficult(line_clear: LineClear) -> bool {
+    line_clear.lines >= 4 || (line_clear.lines > 0 && line_clear.tspin != TSpin::None)
+}
+
+#[derive(Clone, Copy)]
+struct PendingGarbage {
+    lines: usize,
+    hole: u8,
+    ready_tick: u64,
+}
+
+/// Combo, back-to-back and incoming garbage of one versus board.
+#[derive(Component, Clone)]
+pub struct Garbage {
+    combo: Option<u32>,
+    b2b: bool,
+    pending: VecDeque<PendingGarbage>,
+    // picks the hole column of each incoming attack
+    rng: GameRng,
+}
+
+impl Garbage {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            combo: None,
+            b2b: false,
+            pending: VecDeque::new(),
+            rng: GameRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Updates combo and back-to-back for a put piece. Returns the lines left to send after
+    /// cancelling pending garbage, oldest first.
+    pub fn clear_apply(&mut self, line_clear: LineClear) -> usize {
+        if line_clear.lines == 0 {
+            self.combo = None;
+            return 0;
+        }
+        let combo = self.combo.map_or(0, |combo| combo + 1);
+        self.combo = Some(combo);
+        let mut lines = attack_lines(line_clear, combo, self.b2b);
+        self.b2b = difficult(line_clear);
+        while lines > 0 {
+            let Some(pending) = self.pending.front_mut() else {
+                break;
+            };
+            let cancelled = pending.lines.min(lines);
+            pending.lines -= cancelled;
+            lines -= cancelled;
+            if pending.lines == 0 {
+                self.pending.pop_front();
+            }
+        }
+        lines
+    }
+
+    /// Queues an attack that can rise from `ready_tick` on.
+    pub fn receive(&mut self, lines: usize, ready_tick: u64) {
+        let hole = self.rng.gen_range(0..10);
+        self.pending.push_back(PendingGarbage {
+            lines,
+            hole,
+            ready_tick,
+        });
+    }
+
+    /// Takes the attacks whose delay is over as `(lines, hole)`.
+    pub fn ready_take(&mut self, tick: u64) -> Vec<(usize, u8)> {
+        let ready = self
+            .pending
+            .iter()
+            .take_while(|pending| pending.ready_tick <= tick)
+            .count();
+        self.pending
+            .drain(..ready)
+            .map(|pending| (pending.lines, pending.hole))
+            .collect()
+    }
+
+    /// Pending lines as `(ready, total)`.
+    pub fn pending_get(&self, tick: u64) -> (usize, usize) {
+        let ready = self
+            .pending
+            .iter()
+            .filter(|pending| pending.ready_tick <= tick)
+            .map(|pending| pending.lines)
+            .sum();
+        let total = self.pending.iter().map(|pending| pending.lines).sum();
+        (ready, total)
+    }
+
+    pub fn combo_get(&self) -> Option<u32> {
+        self.combo
+    }
+
+    pub fn b2b_get(&self) -> bool {
+        self.b2b
+    }
+
+    pub fn checksum_write(&self, hasher: &mut impl Hasher) {
+        self.combo.hash(hasher);
+        self.b2b.hash(hasher);
+        for pending in &self.pending {
+            pending.lines.hash(hasher);
+            pending.hole.hash(hasher);
+            pending.ready_tick.hash(hasher);
+        }
+        rng_write(&self.rng, hasher);
+    }
+
+    pub fn dump(&self) -> String {
+        let pending: Vec<(usize, u8, u64)> = self
+            .pending
+            .iter()
+            .map(|pending| (pending.lines, pending.hole, pending.ready_tick))
+            .collect();
+        format!(
+            "combo {:?}, b2b {}, pending (lines, hole, tick) {:?}, rng word {}",
+            self.combo,
+            self.b2b,
+            pending,
+            self.rng.get_word_pos()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::attack::*;
+    use crate::ruleset::Ruleset;
+    use crate::versus::garbage_exchange;
+
+    // the I lands flat on cols 3 to 6
+    const I_ROW: u16 = 0b0001111000;
+
+    fn clear(lines: u32, tspin: TSpin) -> LineClear {
+        LineClear {
+            lines,
+            tspin,
+            perfect: false,
+        }
+    }
+
+    // a board that just dropped an I on the floor, which clears the bottom row or not. A block
+    // stays above the clear, so it is never a perfect clear
+    fn board_put(ruleset: &Ruleset, clearing: bool) -> BoardMap {
+        let mut board_map = BoardMap::new(ruleset);
+        if clearing {
+            for col in [0, 1, 2, 7, 8, 9] {
+                board_map.tile_set(0, col, Some(Tetromino::X));
+            }
+            board_map.tile_set(1, 0, Some(Tetromino::X));
+        }
+        board_map.player_spawn(Tetromino::I);
+        board_map.player_move_to_bottom();
+        board_map
+    }
+
+    #[test]
+    fn attacks_follow_the_guideline_table() {
+        let sent = |lines, tspin| attack_lines(clear(lines, tspin), 0, false);
+        assert_eq!([0, 1, 2, 3, 4].map(|lines| sent(lines, TSpin::None)), [0, 0, 1, 2, 4]);
+        assert_eq!([0, 1, 2, 3].map(|lines| sent(lines, TSpin::Full)), [0, 2, 4, 6]);
+        assert_eq!([0, 1, 2].map(|lines| sent(lines, TSpin::Mini)), [0, 0, 1]);
+        let combos: Vec<usize> = (0..13)
+            .map(|combo| attack_lines(clear(1, TSpin::None), combo, false))
+            .collect();
+        assert_eq!(combos, [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5, 5]);
+    }
+
+    #[test]
+    fn back_to_back_and_perfect_clears_add_bonuses() {
+        assert_eq!(attack_lines(clear(4, TSpin::None), 0, true), 5);
+        assert_eq!(attack_lines(clear(2, TSpin::Full), 0, true), 5);
+        assert_eq!(attack_lines(clear(1, TSpin::Mini), 0, true), 1);
+        // only difficult clears get the back-to-back bonus
+        assert_eq!(attack_lines(clear(3, TSpin::None), 0, true), 2);
+        let perfect = LineClear {
+            perfect: true,
+            ..clear(1, TSpin::None)
+        };
+        assert_eq!(attack_lines(perfect, 0, false), 10);
+        let perfect = LineClear {
+            perfect: true,
+            ..clear(4, TSpin::None)
+        };
+        assert_eq!(attack_lines(perfect, 2, true), 4 + 1 + 1 + 10);
+    }
+
+    #[test]
+    fn clears_keep_combo_and_back_to_back() {
+        let mut garbage = Garbage::new(0);
+        assert_eq!(garbage.clear_apply(clear(4, TSpin::None)), 4);
+        assert_eq!(garbage.clear_apply(clear(4, TSpin::None)), 4 + 1);
+        assert_eq!((garbage.combo_get(), garbage.b2b_get()), (Some(1), true));
+        assert_eq!(garbage.clear_apply(clear(2, TSpin::None)), 1 + 1);
+        assert!(!garbage.b2b_get());
+        assert_eq!(garbage.clear_apply(clear(0, TSpin::None)), 0);
+        assert_eq!(garbage.combo_get(), None);
+    }
+
+    #[test]
+    fn attacks_cancel_the_oldest_garbage_first() {
+        let mut garbage = Garbage::new(0);
+        garbage.receive(2, 10);
+        garbage.receive(3, 20);
+        assert_eq!(garbage.clear_apply(clear(4, TSpin::None)), 0);
+        assert_eq!(garbage.pending_get(20), (1, 1));
+        let ready: Vec<usize> = garbage.ready_take(20).iter().map(|&(lines, _)| lines).collect();
+        assert_eq!(ready, [1]);
+        garbage.receive(1, 30);
+        assert_eq!(garbage.clear_apply(clear(4, TSpin::None)), 4);
+        assert_eq!(garbage.pending_get(30), (0, 0));
+    }
+
+    #[test]
+    fn garbage_waits_for_the_delay_and_a_piece_that_clears_nothing() {
+        let ruleset = Ruleset::default();
+        let delay = ruleset.garbage_delay as u64;
+        let mut attacker_garbage = Garbage::new(0);
+        let mut target_garbage = Garbage::new(1);
+        for _ in 0..4 {
+            attacker_garbage.clear_apply(clear(1, TSpin::None));
+        }
+        // a single on combo 4 sends 2 lines
+        let mut attacker = board_put(&ruleset, true);
+        let mut target = board_put(&ruleset, false);
+        garbage_exchange(
+            100,
+            &ruleset,
+            &mut [
+                (0, &mut attacker, &mut attacker_garbage),
+                (1, &mut target, &mut target_garbage),
+            ],
+        );
+        assert_eq!(target_garbage.pending_get(100 + delay - 1), (0, 2));
+        assert_eq!(target_garbage.pending_get(100 + delay), (2, 2));
+        // too early, then under a clear, then under a piece that cleared nothing
+        let mut early = board_put(&ruleset, false);
+        garbage_exchange(
+            100 + delay - 1,
+            &ruleset,
+            &mut [(1, &mut early, &mut target_garbage)],
+        );
+        assert_eq!(early.rows_get()[0], I_ROW);
+        let mut clearing = board_put(&ruleset, true);
+        garbage_exchange(
+            100 + delay,
+            &ruleset,
+            &mut [(1, &mut clearing, &mut target_garbage)],
+        );
+        assert_eq!(clearing.rows_get()[..2], [1 << 9, 0]);
+        assert_eq!(target_garbage.pending_get(100 + delay), (2, 2));
+        let mut late = board_put(&ruleset, false);
+        garbage_exchange(
+            100 + delay,
+            &ruleset,
+            &mut [(1, &mut late, &mut target_garbage)],
+        );
+        assert_eq!(late.rows_get()[2], I_ROW);
+        assert_eq!(target_garbage.pending_get(100 + delay), (0, 0));
+    }
+}
@@ -0,0 +1,85 @@
+//! Lets the bot play every board without a window, until the boards top out, a versus round
+//! ends or `--ticks` pass (5 minutes by default), then prints how each board did.
+//!
+//! `tetris_autoplay [--seed <n>] [--players <n>] [--versus] [--pps <n>] [--ticks <n>]
+//! [--tbp "<command>"]` plus the ruleset flags of the game, `--tbp` plays a TBP bot program
+//! instead of the built-in bot.
+
+use bevy::prelude::*;
+use std::time::Instant;
+use tetris_bevy::args::*;
+use tetris_bevy::bot::{BotSettings, DEFAULT_PPS};
+use tetris_bevy::input::Tick;
+use tetris_bevy::resource::BoardMap;
+use tetris_bevy::ruleset::{Ruleset, FRAME_RATE};
+use tetris_bevy::tbp::TbpBots;
+use tetris_bevy::{Player, Screen, TetrisPlugin};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let seed = or_exit(arg_parse(&args, "--seed", rand::random()));
+    let versus = args.iter().any(|arg| arg == "--versus");
+    let players: u8 = or_exit(arg_parse(&args, "--players", if versus { 2 } else { 1 }));
+    let pps = or_exit(arg_parse(&args, "--pps", DEFAULT_PPS));
+    let ticks: u64 = or_exit(arg_parse(&args, "--ticks", 5 * 60 * FRAME_RATE as u64));
+    let ruleset = or_exit(Ruleset::args_parse(&args));
+    let bot_players: Vec<u8> = (0..players).collect();
+    let tbp = or_exit(TbpBots::args_parse(&args, &bot_players));
+    let mut app = TetrisPlugin {
+        ruleset,
+        seed,
+        players,
+        best_of: versus.then_some(1),
+        bot: Some(BotSettings {
+            players: bot_players,
+            pps,
+            tbp,
+            ..default()
+        }),
+        ..default()
+    }
+    .headless();
+    println!("seed {seed}, {players} boards at {pps} pieces per second");
+    let started = Instant::now();
+    loop {
+        app.update();
+        let world = app.world_mut();
+        let tick = world.resource::<Tick>().0;
+        let round_over = *world.resource::<State<Screen>>().get() == Screen::Result;
+        let topped_out = world
+            .query::<&BoardMap>()
+            .iter(world)
+            .all(|board_map| board_map.piece_get().is_none());
+        if round_over || topped_out || tick >= ticks {
+            break;
+        }
+    }
+    let world = app.world_mut();
+    let tick = world.resource::<Tick>().0;
+    let mut boards: Vec<(u8, u32, u32, bool)> = world
+        .query::<(&Player, &BoardMap)>()
+        .iter(world)
+        .map(|(player, board_map)| {
+            let alive = board_map.piece_get().is_some();
+            (
+                player.0,
+                board_map.score_get(),
+                board_map.lines_get(),
+                alive,
+            )
+        })
+        .collect();
+    boards.sort();
+    println!(
+        "{tick} ticks ({:.1} s of play) in {:.1} s",
+        tick as f32 / FRAME_RATE,
+        started.elapsed().as_secs_f32()
+    );
+    for (player, score, lines, alive) in boards {
+        let state = if alive { "playing" } else { "topped out" };
+        println!(
+            "board {}: score {score}, lines {lines}, {state}",
+            player + 1
+        );
+    }
+}
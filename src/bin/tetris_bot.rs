@@ -0,0 +1,37 @@
+//! The built-in bot as a TBP bot program, for other frontends and tournament harnesses. It
+//! plays the standard rules: SRS, hold and the previews it is sent.
+//!
+//! `tetris_bot [--weights <path>]`, a JSON message a line on stdin and stdout
+
+use tetris_bevy::args::or_exit;
+use tetris_bevy::bot::{best_landing, Weights};
+use tetris_bevy::ruleset::Ruleset;
+use tetris_bevy::tbp::*;
+
+// the bot's pick, or nothing when every placement tops out or the board is too tall
+fn suggest(state: &BotState, ruleset: &Ruleset, weights: &Weights) -> Vec<Move> {
+    let Some(board_map) = state.board_map(ruleset) else {
+        return Vec::new();
+    };
+    let next: Vec<_> = state
+        .queue
+        .iter()
+        .skip(1)
+        .map(|piece| piece.tetromino())
+        .collect();
+    best_landing(&board_map, &next, weights)
+        .and_then(|(_, landing)| Move::from_landing(&landing))
+        .into_iter()
+        .collect()
+}
+
+fn main() {
+    // frontends leave holding to the bot
+    let ruleset = Ruleset {
+        hold: true,
+        ..Ruleset::default()
+    };
+    let args: Vec<String> = std::env::args().collect();
+    let weights = or_exit(Weights::args_parse(&args));
+    bot_run("tetris_bevy", |state| suggest(state, &ruleset, &weights));
+}
@@ -0,0 +1,86 @@
+//! Plays the RL environment with random agents in both action spaces and prints how fast it
+//! steps, a starting point for training code. `--greedy` picks placements by the bot's board
+//! evaluation instead, one step ahead on a clone of the environment.
+//!
+//! `tetris_env [--episodes <n>] [--max-steps <n>] [--greedy]` plus the ruleset flags of the
+//! game.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+use tetris_bevy::args::*;
+use tetris_bevy::bot::{Features, Weights};
+use tetris_bevy::env::{ActionSpace, Env};
+use tetris_bevy::ruleset::Ruleset;
+
+// the placement leaving the board the bot likes best
+fn greedy_action(env: &Env, weights: &Weights) -> usize {
+    (0..env.action_count())
+        .map(|action| {
+            let mut next = env.clone();
+            let (observation, _, done, info) = next.step(action);
+            let rows = observation.grid.map(|cells| {
+                cells
+                    .iter()
+                    .fold(0_u16, |row, &cell| (row << 1) | cell as u16)
+            });
+            let features = Features::new(&rows, info.lines);
+            let score = if done {
+                f32::MIN
+            } else {
+                weights.score(&features)
+            };
+            (action, score)
+        })
+        .fold(
+            (0, f32::MIN),
+            |best, next| if next.1 > best.1 { next } else { best },
+        )
+        .0
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let episodes: u64 = or_exit(arg_parse(&args, "--episodes", 20));
+    let max_steps: u64 = or_exit(arg_parse(&args, "--max-steps", 100_000));
+    let ruleset = or_exit(Ruleset::args_parse(&args));
+    let greedy = args.iter().any(|arg| arg == "--greedy");
+    let weights = Weights::default();
+    let mut rng = StdRng::seed_from_u64(0);
+    let action_spaces = if greedy {
+        vec![ActionSpace::Placement]
+    } else {
+        vec![ActionSpace::Input, ActionSpace::Placement]
+    };
+    for action_space in action_spaces {
+        let mut env = Env::new(&ruleset, action_space);
+        let started = Instant::now();
+        let mut steps = 0;
+        let mut reward_sum = 0.;
+        let mut pieces = 0;
+        for seed in 0..episodes {
+            env.reset(seed);
+            for _ in 0..max_steps {
+                let action = if greedy {
+                    greedy_action(&env, &weights)
+                } else {
+                    rng.gen_range(0..env.action_count())
+                };
+                let (_, reward, done, info) = env.step(action);
+                steps += 1;
+                reward_sum += reward;
+                pieces = info.pieces;
+                if done {
+                    break;
+                }
+            }
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        println!(
+            "{action_space:?}: {episodes} episodes, {steps} steps, {:.0} steps a second, \
+             {:.2} reward an episode, {pieces} pieces in the last",
+            steps as f64 / elapsed,
+            reward_sum / episodes as f32
+        );
+    }
+}
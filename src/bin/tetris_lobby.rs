@@ -0,0 +1,34 @@
+//! The lobby without a window: prints the games announced on the network, once a second for
+//! `--seconds` seconds (5 by default).
+//!
+//! `tetris_lobby [--seconds <n>]`
+
+use std::time::{Duration, Instant};
+use tetris_bevy::lan::Discovery;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let seconds: u64 = args
+        .iter()
+        .position(|arg| arg == "--seconds")
+        .and_then(|i| args.get(i + 1))
+        .map_or(5, |seconds| {
+            seconds
+                .parse()
+                .unwrap_or_else(|e| panic!("bad seconds {seconds}: {e}"))
+        });
+    let mut discovery = Discovery::bind().unwrap_or_else(|e| panic!("{e}"));
+    println!(
+        "listening on port {}",
+        discovery.port_get().unwrap_or_default()
+    );
+    let end = Instant::now() + Duration::from_secs(seconds);
+    while Instant::now() < end {
+        std::thread::sleep(Duration::from_secs(1));
+        discovery.update();
+        println!("{} games", discovery.games_get().len());
+        for game in discovery.games_get() {
+            println!("  {}  {}", game.announcement.describe(), game.addr);
+        }
+    }
+}
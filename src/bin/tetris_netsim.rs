@@ -0,0 +1,292 @@
+//! Plays online versus rounds between two headless clients over a simulated network, both
+//! driven by random key presses, and checks that they agree on every round. The link delays
+//! each message by `--latency` ticks plus up to `--jitter` more, a lost message (`--loss`
+//! percent) arrives a timeout later and holds back the ones behind it, like TCP. Besides the
+//! rounds the clients compare a checksum of every confirmed tick.
+//!
+//! `tetris_netsim [--latency <ticks>] [--jitter <ticks>] [--loss <percent>] [--rounds <n>]
+//! [--seed <n>] [--input-delay <ticks>] [--rollback <ticks>]` plus the ruleset flags of the game.
+
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt::Write;
+use std::sync::mpsc::{self, Receiver, Sender};
+use tetris_bevy::args::*;
+use tetris_bevy::attack::Garbage;
+use tetris_bevy::input::Tick;
+use tetris_bevy::net::*;
+use tetris_bevy::resource::*;
+use tetris_bevy::rollback::RollbackStats;
+use tetris_bevy::ruleset::Ruleset;
+use tetris_bevy::{Player, Screen, TetrisPlugin};
+
+// frames without a finished round before giving up
+const FRAME_LIMIT: u64 = 200_000;
+const KEYS: [KeyCode; 8] = [
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::ArrowDown,
+    KeyCode::Space,
+    KeyCode::KeyQ,
+    KeyCode::KeyE,
+    KeyCode::KeyA,
+    KeyCode::KeyC,
+];
+
+enum Packet {
+    ToServer(usize, ClientMessage),
+    ToClient(usize, ServerMessage),
+}
+
+// one ordered stream per direction and client, like a TCP connection each way
+struct Link {
+    latency: u64,
+    jitter: u64,
+    loss: f64,
+    rng: StdRng,
+    queue: Vec<(u64, Packet)>,
+    last_due: [u64; 4],
+}
+
+impl Link {
+    fn post(&mut self, frame: u64, packet: Packet) {
+        let stream = match &packet {
+            Packet::ToServer(player, _) => *player,
+            Packet::ToClient(player, _) => 2 + player,
+        };
+        let mut delay = self.latency + self.rng.gen_range(0..=self.jitter);
+        if self.rng.gen_bool(self.loss) {
+            // sent again after a timeout of about two round trips
+            delay += self.latency * 4 + 12;
+        }
+        let due = (frame + delay).max(self.last_due[stream]);
+        self.last_due[stream] = due;
+        self.queue.push((due, packet));
+    }
+
+    fn due_take(&mut self, frame: u64) -> Vec<Packet> {
+        self.queue.sort_by_key(|(due, _)| *due);
+        let due = self.queue.partition_point(|(due, _)| *due <= frame);
+        self.queue.drain(..due).map(|(_, packet)| packet).collect()
+    }
+}
+
+struct Client {
+    app: App,
+    outgoing: Receiver<ClientMessage>,
+    incoming: Sender<ServerMessage>,
+    rng: StdRng,
+    // key held down and the frame it is let go
+    held: Option<(KeyCode, u64)>,
+    // state of each round as the result screen showed it
+    results: Vec<String>,
+}
+
+impl Client {
+    fn new(start: &MatchStart) -> Self {
+        let (outgoing_sender, outgoing) = mpsc::channel();
+        let (incoming, incoming_receiver) = mpsc::channel();
+        let net = NetClient::from_channels(start, outgoing_sender, incoming_receiver);
+        let app = TetrisPlugin {
+            ruleset: start.ruleset.clone(),
+            seed: start.seed,
+            players: 2,
+            best_of: Some(start.best_of),
+            net: Some(net),
+            ..default()
+        }
+        .headless();
+        Self {
+            app,
+            outgoing,
+            incoming,
+            rng: StdRng::seed_from_u64(start.seed ^ (start.player as u64 + 1)),
+            held: None,
+            results: Vec::new(),
+        }
+    }
+
+    fn key_send(&mut self, key_code: KeyCode, state: ButtonState) {
+        self.app.world_mut().send_event(KeyboardInput {
+            key_code,
+            logical_key: Key::Unidentified(bevy::input::keyboard::NativeKey::Unidentified),
+            state,
+            window: Entity::PLACEHOLDER,
+        });
+    }
+
+    fn screen_get(&self) -> Screen {
+        self.app.world().resource::<State<Screen>>().get().clone()
+    }
+
+    // random presses while playing, Enter on the result screen
+    fn input_update(&mut self, frame: u64) {
+        if let Some((key_code, release)) = self.held {
+            if frame >= release {
+                self.key_send(key_code, ButtonState::Released);
+                self.held = None;
+            }
+            return;
+        }
+        let key_code = match self.screen_get() {
+            Screen::Game if self.rng.gen_bool(0.25) => KEYS[self.rng.gen_range(0..KEYS.len())],
+            Screen::Result => KeyCode::Enter,
+            _ => return,
+        };
+        self.key_send(key_code, ButtonState::Pressed);
+        self.held = Some((key_code, frame + self.rng.gen_range(1..=14)));
+    }
+
+    fn update(&mut self) {
+        let screen = self.screen_get();
+        self.app.update();
+        // the boards as the round ended, before Enter spawns the next ones
+        if screen != Screen::Result && self.screen_get() == Screen::Result {
+            let result = self.result_get();
+            self.results.push(result);
+        }
+    }
+
+    // everything both clients have to agree on once a round is over
+    fn result_get(&mut self) -> String {
+        let world = self.app.world_mut();
+        let tick = world.resource::<Tick>().0;
+        let mut boards: Vec<String> = world
+            .query::<(&Player, &BoardMap, &TetrominoSupplier, Option<&Garbage>)>()
+            .iter(world)
+            .map(|(player, board_map, tetromino_supplier, garbage)| {
+                let mut text = format!("player {} score {}", player.0, board_map.score_get());
+                for row in 0..20 {
+                    text.push('\n');
+                    for col in 0..10 {
+                        let tile = board_map.tile_get(row, col);
+                        text.push(if tile.is_some() { '#' } else { '.' });
+                    }
+                }
+                let _ = write!(
+                    text,
+                    "\nhold {:?} next {:?}",
+                    board_map.hold_get(),
+                    tetromino_supplier.get(0)
+                );
+                if let Some(garbage) = garbage {
+                    let _ = write!(text, " garbage {:?}", garbage.pending_get(tick));
+                }
+                text
+            })
+            .collect();
+        boards.sort();
+        format!("tick {tick}\n{}", boards.join("\n"))
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let seed = or_exit(arg_parse(&args, "--seed", rand::random()));
+    let rounds: u64 = or_exit(arg_parse(&args, "--rounds", 3));
+    let mut link = Link {
+        latency: or_exit(arg_parse(&args, "--latency", 6)),
+        jitter: or_exit(arg_parse(&args, "--jitter", 2)),
+        loss: or_exit(arg_parse(&args, "--loss", 5.0)) / 100.,
+        rng: StdRng::seed_from_u64(seed),
+        queue: Vec::new(),
+        last_due: [0; 4],
+    };
+    let start = MatchStart {
+        player: 0,
+        seed,
+        best_of: 3,
+        input_delay: or_exit(arg_parse(&args, "--input-delay", DEFAULT_INPUT_DELAY)),
+        rollback_window: or_exit(arg_parse(&args, "--rollback", DEFAULT_ROLLBACK_WINDOW)),
+        ruleset: or_exit(Ruleset::args_parse(&args)),
+    };
+    println!(
+        "seed {seed}, latency {} + {} ticks, loss {}%, input delay {}, rollback window {}",
+        link.latency,
+        link.jitter,
+        link.loss * 100.,
+        start.input_delay,
+        start.rollback_window
+    );
+    let mut clients: Vec<Client> = (0..2)
+        .map(|player| {
+            Client::new(&MatchStart {
+                player,
+                ..start.clone()
+            })
+        })
+        .collect();
+    let mut referee = Referee::new(start.best_of);
+    let mut desynced = false;
+    let mut reports = 0;
+    let mut frame = 0;
+    while reports < rounds && frame < FRAME_LIMIT {
+        for (player, client) in clients.iter().enumerate() {
+            for message in client.outgoing.try_iter() {
+                link.post(frame, Packet::ToServer(player, message));
+            }
+        }
+        for packet in link.due_take(frame) {
+            match packet {
+                Packet::ToServer(player, message) => {
+                    for (to, message) in referee.message_handle(player, message) {
+                        link.post(frame, Packet::ToClient(to, message));
+                    }
+                }
+                Packet::ToClient(player, message) => {
+                    let _ = clients[player].incoming.send(message);
+                }
+            }
+        }
+        if let Some(report) = referee.report_take() {
+            let [first, second] = report.ticks;
+            println!(
+                "round {}: winner {:?}, ended on tick {first} / {second}",
+                report.round, report.winner
+            );
+            desynced |= report.disputed;
+            reports += 1;
+        }
+        for client in &mut clients {
+            client.input_update(frame);
+            client.update();
+        }
+        frame += 1;
+    }
+    for (round, (first, second)) in clients[0]
+        .results
+        .iter()
+        .zip(&clients[1].results)
+        .enumerate()
+    {
+        if first != second {
+            println!("round {round} desynced:\n{first}\n---\n{second}");
+            desynced = true;
+        }
+    }
+    for (player, client) in clients.iter().enumerate() {
+        let world = client.app.world();
+        let stats = world.resource::<RollbackStats>();
+        println!("player {player}: {stats:?}");
+        let checksum_log = world.resource::<ChecksumLog>();
+        println!(
+            "player {player}: {} ticks checksummed",
+            checksum_log.compared
+        );
+        if let Some(tick) = checksum_log.desync {
+            println!("player {player}: checksums differ from tick {tick}");
+            desynced = true;
+        }
+    }
+    if reports < rounds {
+        println!("gave up after {frame} frames");
+        std::process::exit(2);
+    }
+    if desynced {
+        std::process::exit(1);
+    }
+    println!("{reports} rounds in sync");
+}
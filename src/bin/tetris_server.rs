@@ -0,0 +1,161 @@
+//! Pairs clients two by two and runs a versus match between each pair. The server only relays
+//! inputs, garbage is worked out by both clients from them, and it decides every round from
+//! what the clients report about both boards, a draw when they disagree. With `--announce` the
+//! game shows up in the lobby of every client on the network, bind to an address they can reach.
+//!
+//! `tetris_server [--bind <addr>] [--seed <n>] [--best-of <n>] [--input-delay <ticks>]
+//! [--rollback <ticks>] [--announce [--name <name>] [--broadcast <ip>]]` plus the ruleset flags
+//! of the game.
+
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{mpsc, Arc};
+use tetris_bevy::args::*;
+use tetris_bevy::lan::*;
+use tetris_bevy::net::*;
+use tetris_bevy::ruleset::Ruleset;
+
+struct Options {
+    seed: Option<u64>,
+    best_of: u8,
+    input_delay: u64,
+    rollback_window: u64,
+    ruleset: Ruleset,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let addr = arg_get(&args, "--bind").map_or(DEFAULT_ADDR, String::as_str);
+    let options = Options {
+        seed: or_exit(arg_parse_some(&args, "--seed")),
+        best_of: or_exit(arg_parse(&args, "--best-of", 3)),
+        input_delay: or_exit(arg_parse(&args, "--input-delay", DEFAULT_INPUT_DELAY)),
+        rollback_window: or_exit(arg_parse(&args, "--rollback", DEFAULT_ROLLBACK_WINDOW)),
+        ruleset: or_exit(Ruleset::args_parse(&args)),
+    };
+    let listener = TcpListener::bind(addr).unwrap_or_else(|e| panic!("failed to bind {addr}: {e}"));
+    println!("listening on {addr}");
+    let waiting_count = Arc::new(AtomicU8::new(0));
+    if args.iter().any(|arg| arg == "--announce") {
+        let port = listener.local_addr().map_or(0, |addr| addr.port());
+        let announcement = Announcement {
+            name: arg_get(&args, "--name")
+                .map_or("tetris_server", String::as_str)
+                .to_string(),
+            port,
+            mode: Mode::Versus {
+                best_of: options.best_of,
+            },
+            ruleset: options.ruleset.clone(),
+            players: 0,
+            capacity: 2,
+        };
+        let broadcast: IpAddr = or_exit(arg_parse(&args, "--broadcast", DEFAULT_BROADCAST));
+        let announcer =
+            Announcer::new(broadcast).unwrap_or_else(|e| panic!("failed to announce: {e}"));
+        println!("announcing to {broadcast}");
+        let waiting_count = waiting_count.clone();
+        std::thread::spawn(move || loop {
+            let announcement = Announcement {
+                players: waiting_count.load(Ordering::Relaxed),
+                ..announcement.clone()
+            };
+            if let Err(e) = announcer.send(&announcement) {
+                eprintln!("announce failed: {e}");
+            }
+            std::thread::sleep(ANNOUNCE_INTERVAL);
+        });
+    }
+    let mut waiting: Option<TcpStream> = None;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {e}");
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        match waiting.take() {
+            None => waiting = Some(stream),
+            Some(first) => {
+                let seed = options.seed.unwrap_or_else(rand::random);
+                let start = MatchStart {
+                    player: 0,
+                    seed,
+                    best_of: options.best_of,
+                    input_delay: options.input_delay,
+                    rollback_window: options.rollback_window,
+                    ruleset: options.ruleset.clone(),
+                };
+                std::thread::spawn(move || match_run([first, stream], start));
+            }
+        }
+        waiting_count.store(waiting.is_some() as u8, Ordering::Relaxed);
+    }
+}
+
+fn match_run(streams: [TcpStream; 2], start: MatchStart) {
+    println!("match started, seed {}", start.seed);
+    let (sender, receiver) = mpsc::channel();
+    for (player, stream) in streams.iter().enumerate() {
+        let sender = sender.clone();
+        let Ok(stream) = stream.try_clone() else {
+            return;
+        };
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stream);
+            loop {
+                let message = message_read::<ClientMessage>(&mut reader).unwrap_or_else(|e| {
+                    eprintln!("player {player}: {e}");
+                    None
+                });
+                let closed = message.is_none();
+                if sender.send((player, message)).is_err() || closed {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sender);
+    let send = |player: usize, message: &ServerMessage| {
+        if let Err(e) = message_send(&streams[player], message) {
+            eprintln!("player {player}: {e}");
+        }
+    };
+    for player in 0..2 {
+        send(
+            player,
+            &ServerMessage::Start(MatchStart {
+                player: player as u8,
+                ..start.clone()
+            }),
+        );
+    }
+    let mut referee = Referee::new(start.best_of);
+    for (player, message) in receiver {
+        let Some(message) = message else {
+            println!("player {player} left");
+            send(1 - player, &ServerMessage::OpponentLeft);
+            return;
+        };
+        for (to, message) in referee.message_handle(player, message) {
+            send(to, &message);
+        }
+        if let Some(report) = referee.report_take() {
+            if report.disputed {
+                let [first, second] = report.ticks;
+                eprintln!(
+                    "round {} disputed, ended on tick {first} and {second}, counted as a draw",
+                    report.round
+                );
+            }
+            println!(
+                "round {}: {:?}, wins {:?}",
+                report.round, report.winner, report.wins
+            );
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Plays a batch of one board games with the bot, no window, audio or GPU needed, and writes
+//! how each went for comparing bots, randomizers and rule changes.
+//!
+//! `tetris_sim [--games <n>] [--seed <first>] [--pps <n>] [--ticks <n>] [--weights <path>]
+//! [--tbp "<command>"] [--out <path>]` plus the ruleset flags of the game. Game `i` is dealt
+//! from seed `first + i`, each runs until it tops out or `--ticks` pass (5 minutes by default).
+//! Results go to `--out` as JSON when it ends in `.json`, as CSV otherwise or on stdout.
+
+use bevy::prelude::*;
+use serde::Serialize;
+use std::time::Instant;
+use tetris_bevy::args::*;
+use tetris_bevy::bot::{BotSettings, Weights, DEFAULT_PPS};
+use tetris_bevy::input::Tick;
+use tetris_bevy::resource::{BoardMap, TopOut};
+use tetris_bevy::ruleset::{Ruleset, FRAME_RATE};
+use tetris_bevy::tbp::TbpBots;
+use tetris_bevy::TetrisPlugin;
+
+#[derive(Serialize)]
+struct GameResult {
+    seed: u64,
+    ticks: u64,
+    lines: u32,
+    score: u32,
+    pieces: u32,
+    /// `block_out`, `garbage_out`, or `none` when the game ran out of ticks.
+    top_out: &'static str,
+    /// Pieces per second of play.
+    pps: f32,
+    /// Wall clock time the game took.
+    seconds: f32,
+}
+
+impl GameResult {
+    const CSV_HEADER: &'static str = "seed,ticks,lines,score,pieces,top_out,pps,seconds";
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{:.3},{:.3}",
+            self.seed,
+            self.ticks,
+            self.lines,
+            self.score,
+            self.pieces,
+            self.top_out,
+            self.pps,
+            self.seconds
+        )
+    }
+}
+
+fn game_play(ruleset: &Ruleset, seed: u64, bot: &BotSettings, ticks: u64) -> GameResult {
+    let started = Instant::now();
+    let mut app = TetrisPlugin {
+        ruleset: ruleset.clone(),
+        seed,
+        players: 1,
+        bot: Some(bot.clone()),
+        ..default()
+    }
+    .headless();
+    loop {
+        app.update();
+        let world = app.world_mut();
+        let tick = world.resource::<Tick>().0;
+        let topped_out = world
+            .query::<&BoardMap>()
+            .iter(world)
+            .all(|board_map| board_map.top_out_get().is_some());
+        if topped_out || tick >= ticks {
+            break;
+        }
+    }
+    let world = app.world_mut();
+    let tick = world.resource::<Tick>().0;
+    let board_map = world
+        .query::<&BoardMap>()
+        .iter(world)
+        .next()
+        .expect("no board");
+    GameResult {
+        seed,
+        ticks: tick,
+        lines: board_map.lines_get(),
+        score: board_map.score_get(),
+        pieces: board_map.pieces_get(),
+        top_out: match board_map.top_out_get() {
+            Some(TopOut::BlockOut) => "block_out",
+            Some(TopOut::GarbageOut) => "garbage_out",
+            None => "none",
+        },
+        pps: board_map.pieces_get() as f32 * FRAME_RATE / tick.max(1) as f32,
+        seconds: started.elapsed().as_secs_f32(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let games: u64 = or_exit(arg_parse(&args, "--games", 10));
+    let first_seed: u64 = or_exit(arg_parse(&args, "--seed", 0));
+    let ticks: u64 = or_exit(arg_parse(&args, "--ticks", 5 * 60 * FRAME_RATE as u64));
+    let bot = BotSettings {
+        players: vec![0],
+        pps: or_exit(arg_parse(&args, "--pps", DEFAULT_PPS)),
+        tbp: None,
+        weights: or_exit(Weights::args_parse(&args)),
+    };
+    let ruleset = or_exit(Ruleset::args_parse(&args));
+    let results: Vec<GameResult> = (first_seed..first_seed + games)
+        .map(|seed| {
+            // a fresh bot program for every game
+            let bot = BotSettings {
+                tbp: or_exit(TbpBots::args_parse(&args, &bot.players)),
+                ..bot.clone()
+            };
+            let result = game_play(&ruleset, seed, &bot, ticks);
+            eprintln!(
+                "seed {seed}: {} lines, {} pieces, top out {}",
+                result.lines, result.pieces, result.top_out
+            );
+            result
+        })
+        .collect();
+
+    let out = arg_get(&args, "--out");
+    let text = if out.is_some_and(|path| path.ends_with(".json")) {
+        serde_json::to_string_pretty(&results).expect("results") + "\n"
+    } else {
+        std::iter::once(GameResult::CSV_HEADER.to_string())
+            .chain(results.iter().map(GameResult::csv_row))
+            .map(|line| line + "\n")
+            .collect()
+    };
+    match out {
+        Some(path) => {
+            std::fs::write(path, text).unwrap_or_else(|e| panic!("failed to write {path}: {e}"))
+        }
+        None => print!("{text}"),
+    }
+    let count = results.len().max(1) as f32;
+    eprintln!(
+        "{games} games: {:.1} lines, {:.1} score, {:.2} pps on average, {} topped out",
+        results
+            .iter()
+            .map(|result| result.lines as f32)
+            .sum::<f32>()
+            / count,
+        results
+            .iter()
+            .map(|result| result.score as f32)
+            .sum::<f32>()
+            / count,
+        results.iter().map(|result| result.pps).sum::<f32>() / count,
+        results
+            .iter()
+            .filter(|result| result.top_out != "none")
+            .count()
+    );
+}
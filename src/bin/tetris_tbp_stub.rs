@@ -0,0 +1,42 @@
+//! A trivial TBP bot for trying the protocol without a real one: it drops the falling piece
+//! flat wherever it ends up lowest, leftmost first, and never holds.
+//!
+//! `tetris_bevy --tbp tetris_tbp_stub`
+
+use tetris_bevy::tbp::*;
+
+// north facing hard drops from the top of every column
+fn suggest(state: &BotState) -> Vec<Move> {
+    let Some(&piece) = state.queue.first() else {
+        return Vec::new();
+    };
+    let mut moves: Vec<Move> = (0..10)
+        .filter_map(|x| {
+            let mut location = PieceLocation {
+                piece,
+                orientation: Orientation::North,
+                x,
+                y: BOARD_ROWS as i8 - 2,
+            };
+            if !state.fits(&location) {
+                return None;
+            }
+            while state.fits(&PieceLocation {
+                y: location.y - 1,
+                ..location
+            }) {
+                location.y -= 1;
+            }
+            Some(Move {
+                location,
+                spin: Spin::None,
+            })
+        })
+        .collect();
+    moves.sort_by_key(|mv| (mv.location.y, mv.location.x));
+    moves
+}
+
+fn main() {
+    bot_run("tetris_tbp_stub", suggest);
+}
@@ -0,0 +1,77 @@
+//! Evolves the bot's evaluation weights with a genetic algorithm. Every candidate plays the
+//! same seeds headless, in parallel, and scores the lines it clears in `--pieces` pieces. The
+//! best weights so far go to `--out` for `--weights` of the game, `tetris_bot` and
+//! `tetris_sim`, and each generation is written to `--checkpoint`, which a run resumes from.
+//!
+//! `tetris_tune [--generations <n>] [--population <n>] [--seeds <n>] [--pieces <n>]
+//! [--seed <n>] [--out <path>] [--checkpoint <path>]` plus the ruleset flags of the game.
+
+use std::time::Instant;
+use tetris_bevy::args::*;
+use tetris_bevy::ruleset::Ruleset;
+use tetris_bevy::tune::Checkpoint;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let generations: u32 = or_exit(arg_parse(&args, "--generations", 20));
+    let size: usize = or_exit(arg_parse(&args, "--population", 16));
+    let run_seed: u64 = or_exit(arg_parse(&args, "--seed", 0));
+    let out = arg_get(&args, "--out").map_or("weights.ron", String::as_str);
+    let checkpoint_path = arg_get(&args, "--checkpoint").map_or("tune.ron", String::as_str);
+    let ruleset = or_exit(Ruleset::args_parse(&args));
+
+    let resume = std::path::Path::new(checkpoint_path).exists();
+    let mut checkpoint = match resume.then(|| Checkpoint::load(checkpoint_path)) {
+        Some(Ok(checkpoint)) => {
+            or_exit(checkpoint.args_check(&args));
+            println!(
+                "resuming {checkpoint_path} after generation {}, {} seeds of {} pieces",
+                checkpoint.generation, checkpoint.seeds, checkpoint.pieces
+            );
+            checkpoint
+        }
+        Some(Err(e)) => panic!("failed to load checkpoint {checkpoint_path}: {e}"),
+        None => Checkpoint::new(
+            size,
+            or_exit(arg_parse(&args, "--seeds", 4)),
+            or_exit(arg_parse(&args, "--pieces", 300)),
+            run_seed,
+        ),
+    };
+
+    while checkpoint.generation < generations {
+        let started = Instant::now();
+        if checkpoint.generation_play(&ruleset) {
+            checkpoint
+                .best
+                .weights
+                .save(out)
+                .unwrap_or_else(|e| panic!("failed to write {out}: {e}"));
+        }
+        let fittest = checkpoint.population[0].fitness.unwrap_or(0.);
+        let mean = checkpoint
+            .population
+            .iter()
+            .filter_map(|candidate| candidate.fitness)
+            .sum::<f32>()
+            / checkpoint.population.len() as f32;
+        checkpoint.generation_next(run_seed);
+        println!(
+            "generation {}: best {:.1} lines, mean {:.1}, in {:.1} s, best so far {:.1} {:?}",
+            checkpoint.generation,
+            fittest,
+            mean,
+            started.elapsed().as_secs_f32(),
+            checkpoint.best.fitness.unwrap_or(0.),
+            checkpoint.best.weights
+        );
+        checkpoint
+            .save(checkpoint_path)
+            .unwrap_or_else(|e| panic!("failed to write {checkpoint_path}: {e}"));
+    }
+    println!(
+        "best {:.1} lines with {:?}, saved to {out}",
+        checkpoint.best.fitness.unwrap_or(0.),
+        checkpoint.best.weights
+    );
+}
@@ -0,0 +1,259 @@
+use crate::resource::Tetromino;
+use crate::rotation::RotationSystem;
+
+/// Rows a piece may span.
+pub const MAX_HEIGHT: usize = 8;
+
+// no cell of the piece in this column
+const NO_CELL: i8 = i8::MAX;
+
+/// A board prepared for many collision tests: every row with the three above it packed in a
+/// `u64`, and the height of each column.
+pub struct Bitboard {
+    // padded so a piece of `MAX_HEIGHT` rows can be read anywhere on the board
+    windows: [u64; 20 + MAX_HEIGHT / 2],
+    heights: [u8; 10],
+}
+
+impl Bitboard {
+    pub fn new(rows: &[u16; 20]) -> Self {
+        let mut windows = [0; 20 + MAX_HEIGHT / 2];
+        for (row, window) in windows.iter_mut().enumerate() {
+            for (i, &bits) in rows.iter().skip(row).take(4).enumerate() {
+                *window |= (bits as u64) << (16 * i);
+            }
+        }
+        let mut heights = [0; 10];
+        let mut covered = 0_u16;
+        for (row, &bits) in rows.iter().enumerate().rev() {
+            let new = bits & !covered;
+            for (col, height) in heights.iter_mut().enumerate() {
+                if (new >> (9 - col)) & 1 == 1 {
+                    *height = row as u8 + 1;
+                }
+            }
+            covered |= bits;
+        }
+        Self { windows, heights }
+    }
+
+    /// Rows up to the highest block of each column.
+    pub fn heights_get(&self) -> [u8; 10] {
+        self.heights
+    }
+}
+
+// one piece in one rotation
+#[derive(Clone)]
+struct RotationMasks {
+    // cell offsets from the piece position
+    left: i8,
+    right: i8,
+    bottom: i8,
+    top: i8,
+    // four rows, bottom first, then the four above, at every position from `-left` to
+    // `9 - right`
+    masks: [[u64; 2]; 10],
+    // lowest cell offset in each column from `left` to `right`
+    skirt: [i8; 10],
+}
+
+impl RotationMasks {
+    fn new(cells: &[(i8, i8)]) -> Self {
+        let left = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+        let right = cells.iter().map(|&(x, _)| x).max().unwrap_or(0);
+        let bottom = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+        let top = cells.iter().map(|&(_, y)| y).max().unwrap_or(0);
+        let mut masks = [[0; 2]; 10];
+        // wider than the board or taller than `MAX_HEIGHT`, the piece never fits
+        if right - left < 10 && top - bottom < MAX_HEIGHT as i8 {
+            for (column, masks) in masks
+                .iter_mut()
+                .enumerate()
+                .take((10 - (right - left)) as usize)
+            {
+                let x = column as i8 - left;
+                for &(offset_x, offset_y) in cells {
+                    let row = (offset_y - bottom) as usize;
+                    let bit = 9 - (x + offset_x);
+                    masks[row / 4] |= 1 << (16 * (row % 4) + bit as usize);
+                }
+            }
+        }
+        let mut skirt = [NO_CELL; 10];
+        for &(x, y) in cells {
+            if let Some(lowest) = skirt.get_mut((x - left) as usize) {
+                *lowest = (*lowest).min(y);
+            }
+        }
+        Self {
+            left,
+            right,
+            bottom,
+            top,
+            masks,
+            skirt,
+        }
+    }
+}
+
+/// Row masks of every piece and rotation of a rotation system at every column, so testing a
+/// position against a `Bitboard` takes two ANDs instead of a shift per cell.
+#[derive(Clone)]
+pub struct CollisionMasks {
+    // four rotations of each piece
+    rotations: Vec<RotationMasks>,
+}
+
+impl CollisionMasks {
+    pub fn new(system: &dyn RotationSystem) -> Self {
+        let rotations = (0..system.piece_count())
+            .flat_map(|piece| {
+                (0..4).map(move |rotation| {
+                    RotationMasks::new(system.cells(Tetromino(piece), rotation))
+                })
+            })
+            .collect();
+        Self { rotations }
+    }
+
+    fn rotation_masks(&self, tetromino: Tetromino, rotation: u8) -> &RotationMasks {
+        &self.rotations[tetromino.0 as usize * 4 + (rotation & 3) as usize]
+    }
+
+    /// Whether the piece is inside the board at `position` without overlapping a block.
+    pub fn fits(
+        &self,
+        board: &Bitboard,
+        tetromino: Tetromino,
+        rotation: u8,
+        (x, y): (i8, i8),
+    ) -> bool {
+        let masks = self.rotation_masks(tetromino, rotation);
+        let column = x + masks.left;
+        if column < 0 || x + masks.right > 9 || y + masks.bottom < 0 || y + masks.top > 19 {
+            return false;
+        }
+        let [low, high] = masks.masks[column as usize];
+        let row = (y + masks.bottom) as usize;
+        (board.windows[row] & low) | (board.windows[row + 4] & high) == 0
+    }
+
+    /// Row the piece falls to from `position`, where it has to fit. Above the stack that is
+    /// worked out from the column heights, under an overhang the piece is moved row by row.
+    pub fn drop_row(
+        &self,
+        board: &Bitboard,
+        tetromino: Tetromino,
+        rotation: u8,
+        (x, y): (i8, i8),
+    ) -> i8 {
+        let masks = self.rotation_masks(tetromino, rotation);
+        let column = (x + masks.left) as usize;
+        let width = (masks.right - masks.left) as usize + 1;
+        let landing = masks.skirt[..width]
+            .iter()
+            .zip(&board.heights[column..])
+            .filter(|&(&offset_y, _)| offset_y != NO_CELL)
+            .map(|(&offset_y, &height)| height as i8 - offset_y)
+            .fold(-masks.bottom, i8::max);
+        if landing <= y {
+            return landing;
+        }
+        let mut y = y;
+        while self.fits(board, tetromino, rotation, (x, y - 1)) {
+            y -= 1;
+        }
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruleset::Ruleset;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // how `BoardMap` tested a position before the masks
+    fn cells_fit(rows: &[u16; 20], cells: &[(i8, i8)], (x, y): (i8, i8)) -> bool {
+        cells.iter().all(|&(offset_x, offset_y)| {
+            let (col, row) = (x + offset_x, y + offset_y);
+            (0..10).contains(&col)
+                && (0..20).contains(&row)
+                && (rows[row as usize] >> (9 - col)) & 1 == 0
+        })
+    }
+
+    fn cells_drop(rows: &[u16; 20], cells: &[(i8, i8)], (x, y): (i8, i8)) -> i8 {
+        let mut y = y;
+        while cells_fit(rows, cells, (x, y - 1)) {
+            y -= 1;
+        }
+        y
+    }
+
+    // columns of random height with holes, so there are overhangs to drop under
+    fn random_rows(rng: &mut StdRng) -> [u16; 20] {
+        let mut rows = [0; 20];
+        for col in 0..10 {
+            for row in rows.iter_mut().take(rng.gen_range(0..16)) {
+                if rng.gen_bool(0.85) {
+                    *row |= 1 << (9 - col);
+                }
+            }
+        }
+        rows
+    }
+
+    fn masks_match_cells(ruleset: &Ruleset) {
+        let system = ruleset.rotation_system();
+        let masks = CollisionMasks::new(system);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..16 {
+            let rows = random_rows(&mut rng);
+            let bitboard = Bitboard::new(&rows);
+            for piece in 0..system.piece_count() {
+                let tetromino = Tetromino(piece);
+                for rotation in 0..4 {
+                    let cells = system.cells(tetromino, rotation);
+                    for x in -5..15 {
+                        for y in -5..25 {
+                            let fits = cells_fit(&rows, cells, (x, y));
+                            assert_eq!(
+                                masks.fits(&bitboard, tetromino, rotation, (x, y)),
+                                fits,
+                                "{tetromino:?} {rotation} at {:?}",
+                                (x, y)
+                            );
+                            if fits {
+                                assert_eq!(
+                                    masks.drop_row(&bitboard, tetromino, rotation, (x, y)),
+                                    cells_drop(&rows, cells, (x, y)),
+                                    "{tetromino:?} {rotation} from {:?}",
+                                    (x, y)
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn masks_match_cells_for_tetrominos() {
+        masks_match_cells(&Ruleset::default());
+        masks_match_cells(&Ruleset::nes());
+    }
+
+    #[test]
+    fn masks_match_cells_for_piece_sets() {
+        for path in [
+            "assets/rulesets/pentomino.ron",
+            "assets/rulesets/tromino_mix.ron",
+        ] {
+            masks_match_cells(&Ruleset::load(path).expect("ruleset"));
+        }
+    }
+}
@@ -0,0 +1,489 @@
+use crate::args::arg_get;
+use crate::game::{board_event_update, replay_playing, Player};
+use crate::input::*;
+use crate::movegen::{landings, Landing};
+use crate::resource::*;
+use crate::ruleset::*;
+use crate::tbp::{self, TbpBots};
+use crate::TetrisSet;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const DEFAULT_PPS: f32 = 2.0;
+
+/// How much each board feature counts in a placement's score, higher scores are better. The
+/// features are summed over the columns, so keep the signs: everything but `lines` is bad.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Weights {
+    pub aggregate_height: f32,
+    pub holes: f32,
+    pub bumpiness: f32,
+    pub wells: f32,
+    pub lines: f32,
+}
+
+impl Default for Weights {
+    // tuned for survival at a few pieces per second rather than for attack
+    fn default() -> Self {
+        Self {
+            aggregate_height: -0.51,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.08,
+            lines: 0.76,
+        }
+    }
+}
+
+impl Weights {
+    /// Reads weights saved by `tetris_tune`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// `--weights <path>` loads a file, the default weights otherwise.
+    pub fn args_parse(args: &[String]) -> Result<Self, String> {
+        match arg_get(args, "--weights") {
+            Some(path) => {
+                Self::load(path).map_err(|e| format!("failed to load weights {path}: {e}"))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    // written aside and renamed, so stopping a run never leaves half a file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        let partial = format!("{path}.partial");
+        std::fs::write(&partial, text).map_err(|e| e.to_string())?;
+        std::fs::rename(&partial, path).map_err(|e| e.to_string())
+    }
+
+    pub fn score(&self, features: &Features) -> f32 {
+        self.aggregate_height * features.aggregate_height as f32
+            + self.holes * features.holes as f32
+            + self.bumpiness * features.bumpiness as f32
+            + self.wells * features.wells as f32
+            + self.lines * features.lines as f32
+    }
+}
+
+/// What the evaluation looks at on a board without a falling piece.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Features {
+    pub aggregate_height: u32,
+    /// Empty cells with a block somewhere above them.
+    pub holes: u32,
+    /// Height differences between neighbouring columns.
+    pub bumpiness: u32,
+    /// Depth of the columns lower than both neighbours, the walls count as full.
+    pub wells: u32,
+    /// Lines cleared on the way to this board.
+    pub lines: u32,
+}
+
+impl Features {
+    pub fn new(rows: &[u16; 20], lines: u32) -> Self {
+        let mut heights = [0_u32; 10];
+        let mut holes = 0;
+        for (col, height) in heights.iter_mut().enumerate() {
+            let bit = 1 << (9 - col);
+            let Some(top) = rows.iter().rposition(|row| row & bit != 0) else {
+                continue;
+            };
+            *height = top as u32 + 1;
+            holes += rows[..top].iter().filter(|&&row| row & bit == 0).count() as u32;
+        }
+        let bumpiness = heights
+            .windows(2)
+            .map(|pair| pair[0].abs_diff(pair[1]))
+            .sum();
+        let wells = (0..10)
+            .map(|col| {
+                let left = if col == 0 { 20 } else { heights[col - 1] };
+                let right = if col == 9 { 20 } else { heights[col + 1] };
+                left.min(right).saturating_sub(heights[col])
+            })
+            .sum();
+        Self {
+            aggregate_height: heights.iter().sum(),
+            holes,
+            bumpiness,
+            wells,
+            lines,
+        }
+    }
+}
+
+/// One way to put the falling piece down and the board it leaves.
+pub struct Placement {
+    pub actions: Vec<Action>,
+    pub board_map: BoardMap,
+}
+
+/// Every distinct landing of the falling piece, see `movegen::landings`.
+pub fn placements(board_map: &BoardMap) -> Vec<Placement> {
+    let mut board_map = board_map.clone();
+    board_map.event_reset();
+    landings(&board_map)
+        .into_iter()
+        .map(|landing| {
+            let mut placed = board_map.clone();
+            landing.put(&mut placed);
+            Placement {
+                actions: landing.actions,
+                board_map: placed,
+            }
+        })
+        .collect()
+}
+
+/// Actions for the falling piece, or the hold piece, that give the best board once the piece
+/// after it is placed as well.
+pub fn best_actions(
+    board_map: &BoardMap,
+    tetromino_supplier: &TetrominoSupplier,
+    weights: &Weights,
+) -> Option<Vec<Action>> {
+    let next: Vec<Tetromino> = (0..tetromino_supplier.queue_len())
+        .map(|i| tetromino_supplier.get(i))
+        .collect();
+    let (hold, landing) = best_landing(board_map, &next, weights)?;
+    let mut actions = if hold { vec![Action::Hold] } else { Vec::new() };
+    actions.extend(landing.actions);
+    Some(actions)
+}
+
+/// Landing of the falling piece, or of the hold piece when the flag is set, that gives the best
+/// board once the first of the `next` pieces is placed as well.
+pub fn best_landing(
+    board_map: &BoardMap,
+    next: &[Tetromino],
+    weights: &Weights,
+) -> Option<(bool, Landing)> {
+    board_map.piece_get()?;
+    let lines = board_map.lines_get();
+    let mut board_map = board_map.clone();
+    board_map.event_reset();
+    // whether the piece is held, the board to place on and the index of the next piece
+    let mut starts = vec![(false, board_map.clone(), 0)];
+    if board_map.hold_available() {
+        let mut held = board_map.clone();
+        held.player_hold();
+        held.event_reset();
+        if held.piece_get().is_some() {
+            starts.push((true, held, 0));
+        } else if let Some(&first) = next.first() {
+            held.player_spawn(first);
+            starts.push((true, held, 1));
+        }
+    }
+    let mut best: Option<(f32, bool, Landing)> = None;
+    for (hold, board_map, next_index) in starts {
+        for landing in landings(&board_map) {
+            let mut placed = board_map.clone();
+            landing.put(&mut placed);
+            if placed.event_get(BoardEvent::GameOver) {
+                continue;
+            }
+            let score = match next.get(next_index) {
+                Some(&tetromino) => {
+                    placed.event_reset();
+                    placed.player_spawn(tetromino);
+                    placements(&placed)
+                        .iter()
+                        .filter(|second| !second.board_map.event_get(BoardEvent::GameOver))
+                        .map(|second| board_score(&second.board_map, lines, weights))
+                        .fold(f32::MIN, f32::max)
+                }
+                None => board_score(&placed, lines, weights),
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, ..)| score > *best_score)
+            {
+                best = Some((score, hold, landing));
+            }
+        }
+    }
+    best.map(|(_, hold, landing)| (hold, landing))
+}
+
+fn board_score(board_map: &BoardMap, lines: u32, weights: &Weights) -> f32 {
+    let features = Features::new(&board_map.rows_get(), board_map.lines_get() - lines);
+    weights.score(&features)
+}
+
+/// Boards the bot plays and how fast.
+#[derive(Resource, Clone)]
+pub struct BotSettings {
+    pub players: Vec<u8>,
+    /// Pieces per second at most, the inputs of a piece are spread over its time.
+    pub pps: f32,
+    pub weights: Weights,
+    /// TBP bot programs played instead of the built-in bot.
+    pub tbp: Option<TbpBots>,
+}
+
+impl Default for BotSettings {
+    fn default() -> Self {
+        Self {
+            players: vec![0],
+            pps: DEFAULT_PPS,
+            weights: Weights::default(),
+            tbp: None,
+        }
+    }
+}
+
+/// Inputs left for the falling piece, sent like a device would.
+#[derive(Default)]
+pub(crate) struct Plan {
+    actions: VecDeque<Action>,
+    start_tick: u64,
+}
+
+impl Plan {
+    /// Starts on `actions` ending with a hard drop, a piece time after the last plan started.
+    pub(crate) fn set(
+        &mut self,
+        mut actions: Vec<Action>,
+        next_tick: u64,
+        piece_ticks: u64,
+        ruleset: &Ruleset,
+    ) {
+        if !ruleset.hard_drop {
+            // soft dropped below until it locks
+            actions.pop();
+        }
+        self.actions = actions.into();
+        self.start_tick = next_tick.max(self.start_tick + piece_ticks);
+    }
+
+    pub(crate) fn send(
+        &mut self,
+        player: u8,
+        next_tick: u64,
+        piece_ticks: u64,
+        ruleset: &Ruleset,
+        game_actions: &mut EventWriter<GameAction>,
+    ) {
+        if next_tick < self.start_tick {
+            return;
+        }
+        // one input a tick, more when the rest would not fit in what is left of the piece's time
+        let len = self.actions.len();
+        let ticks_left = (self.start_tick + piece_ticks).saturating_sub(next_tick).max(1);
+        let count = len.div_ceil(ticks_left as usize).max(1).min(len);
+        let mut actions: Vec<Action> = self.actions.drain(..count).collect();
+        if actions.is_empty() && !ruleset.hard_drop {
+            actions.push(Action::SoftDrop);
+        }
+        for action in actions {
+            game_actions.send(GameAction {
+                tick: next_tick,
+                player,
+                action,
+            });
+        }
+    }
+}
+
+/// Ticks between two pieces at `pps` pieces a second.
+pub(crate) fn piece_ticks(pps: f32) -> u64 {
+    ((FRAME_RATE / pps.max(0.01)) as u64).max(1)
+}
+
+#[derive(Component, Default)]
+struct Bot {
+    plan: Plan,
+    planned: bool,
+}
+
+/// A CPU player sending `GameAction`s for the boards in `BotSettings`, like a device would.
+pub struct TetrisBotPlugin {
+    pub settings: BotSettings,
+}
+
+impl Plugin for TetrisBotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone());
+        if let Some(bots) = &self.settings.tbp {
+            tbp::frontend_add(app, bots);
+            return;
+        }
+        app.add_systems(FixedUpdate, bot_attach.in_set(TetrisSet::Input))
+            .add_systems(
+                FixedUpdate,
+                bot_update
+                    .in_set(TetrisSet::Events)
+                    .before(board_event_update)
+                    .run_if(not(replay_playing)),
+            );
+    }
+}
+
+// boards are spawned again for every versus round
+fn bot_attach(
+    mut commands: Commands,
+    boards: Query<(Entity, &Player), Added<Player>>,
+    settings: Res<BotSettings>,
+) {
+    for (entity, player) in &boards {
+        if settings.players.contains(&player.0) {
+            commands.entity(entity).insert(Bot::default());
+        }
+    }
+}
+
+// plans each piece once it spawns, the inputs go out from the next tick on
+fn bot_update(
+    tick: Res<Tick>,
+    ruleset: Res<Ruleset>,
+    settings: Res<BotSettings>,
+    mut bots: Query<(&Player, &BoardMap, &TetrominoSupplier, &mut Bot)>,
+    mut game_actions: EventWriter<GameAction>,
+) {
+    let piece_ticks = piece_ticks(settings.pps);
+    let next_tick = tick.0 + 1;
+    for (player, board_map, tetromino_supplier, mut bot) in &mut bots {
+        if board_map.event_get(BoardEvent::TetrominoPut) {
+            bot.planned = false;
+        }
+        if !bot.planned {
+            let Some(actions) = best_actions(board_map, tetromino_supplier, &settings.weights)
+            else {
+                continue;
+            };
+            bot.plan.set(actions, next_tick, piece_ticks, &ruleset);
+            bot.planned = true;
+        }
+        bot.plan.send(
+            player.0,
+            next_tick,
+            piece_ticks,
+            &ruleset,
+            &mut game_actions,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::*;
+    use bevy::ecs::system::SystemState;
+
+    // rows from the bottom up, `#` for a block
+    fn rows_new(rows: &[&str]) -> [u16; 20] {
+        let mut board = [0; 20];
+        for (row, text) in rows.iter().enumerate() {
+            for (col, cell) in text.chars().enumerate() {
+                if cell == '#' {
+                    board[row] |= 1 << (9 - col);
+                }
+            }
+        }
+        board
+    }
+
+    // what a plan sends on each of `ticks`, as `(tick, action)`
+    fn plan_run(
+        plan: &mut Plan,
+        ticks: std::ops::RangeInclusive<u64>,
+        piece_ticks: u64,
+        ruleset: &Ruleset,
+    ) -> Vec<(u64, Action)> {
+        let mut world = World::new();
+        world.init_resource::<Events<GameAction>>();
+        let mut state: SystemState<EventWriter<GameAction>> = SystemState::new(&mut world);
+        for tick in ticks {
+            let mut game_actions = state.get_mut(&mut world);
+            plan.send(0, tick, piece_ticks, ruleset, &mut game_actions);
+        }
+        let mut reader = world.resource::<Events<GameAction>>().get_reader();
+        reader
+            .read(world.resource::<Events<GameAction>>())
+            .map(|game_action| (game_action.tick, game_action.action))
+            .collect()
+    }
+
+    #[test]
+    fn features_count_holes_bumps_and_wells() {
+        let features = Features::new(&rows_new(&["##.#", ".#", "#"]), 2);
+        assert_eq!(features.aggregate_height, 3 + 2 + 1);
+        assert_eq!(features.holes, 1);
+        assert_eq!(features.bumpiness, 1 + 2 + 1 + 1);
+        // only col 2 is lower than both neighbours
+        assert_eq!(features.wells, 1);
+        assert_eq!(features.lines, 2);
+        // the walls count as full, so the last column is a well
+        let features = Features::new(&rows_new(&["#########."; 3]), 0);
+        assert_eq!((features.holes, features.bumpiness, features.wells), (0, 3, 3));
+    }
+
+    #[test]
+    fn holds_for_the_piece_that_clears() {
+        let ruleset = Ruleset {
+            hold: true,
+            ..default()
+        };
+        let mut board_map = BoardMap::new(&ruleset);
+        for row in 0..4 {
+            for col in 0..9 {
+                board_map.tile_set(row, col, Some(Tetromino::X));
+            }
+        }
+        board_map.hold_set(Some(Tetromino::I));
+        board_map.player_spawn(Tetromino::S);
+        let (hold, landing) =
+            best_landing(&board_map, &[Tetromino::O], &Weights::default()).expect("a landing");
+        assert!(hold);
+        assert_eq!(landing.tetromino, Tetromino::I);
+        assert!(landing.cells.iter().all(|&(col, _)| col == 9));
+    }
+
+    #[test]
+    fn plans_spread_their_inputs_over_the_piece() {
+        assert_eq!(piece_ticks(2.), FRAME_RATE as u64 / 2);
+        assert_eq!(piece_ticks(1000.), 1);
+        let ruleset = Ruleset::default();
+        let actions = vec![
+            Action::MoveLeft,
+            Action::MoveLeft,
+            Action::RotateRight,
+            Action::HardDrop,
+        ];
+        let mut plan = Plan::default();
+        plan.set(actions.clone(), 10, 10, &ruleset);
+        let sent = plan_run(&mut plan, 10..=19, 10, &ruleset);
+        let expected: Vec<(u64, Action)> = (10..).zip(actions.iter().copied()).collect();
+        assert_eq!(sent, expected);
+        // the next piece waits a piece time after this one started
+        plan.set(actions.clone(), 12, 10, &ruleset);
+        assert!(plan_run(&mut plan, 12..=19, 10, &ruleset).is_empty());
+        assert_eq!(plan_run(&mut plan, 20..=20, 10, &ruleset), [(20, Action::MoveLeft)]);
+        // and more inputs go out at once when the piece's time is short
+        let mut plan = Plan::default();
+        plan.set(actions.clone(), 2, 2, &ruleset);
+        let sent = plan_run(&mut plan, 2..=3, 2, &ruleset);
+        assert_eq!(sent.len(), actions.len());
+    }
+
+    #[test]
+    fn plans_soft_drop_without_hard_drop() {
+        let ruleset = Ruleset {
+            hard_drop: false,
+            ..default()
+        };
+        let mut plan = Plan::default();
+        plan.set(vec![Action::MoveLeft, Action::HardDrop], 10, 10, &ruleset);
+        let sent = plan_run(&mut plan, 10..=12, 10, &ruleset);
+        assert_eq!(
+            sent,
+            [(10, Action::MoveLeft), (11, Action::SoftDrop), (12, Action::SoftDrop)]
+        );
+    }
+}
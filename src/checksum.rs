@@ -0,0 +1,230 @@
+use crate::attack::Garbage;
+use crate::game::{DroppingTimer, LockTimer, Player};
+use crate::input::Tick;
+use crate::net::NetClient;
+use crate::replay::{Replay, ReplayMode};
+use crate::resource::*;
+use bevy::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+
+/// Version of `state_checksum`, replays taken with another one can't be checked against it.
+pub const CHECKSUM_VERSION: u32 = 3;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a over little-endian bytes, so the same state hashes the same on every platform and
+/// build, unlike the std hashers. Only the integers written one at a time are turned little
+/// endian, slices of wider integers have to be written element by element.
+pub struct Checksum(u64);
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self(FNV_OFFSET)
+    }
+}
+
+impl Hasher for Checksum {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Generator of everything random in a game. It deals what `StdRng` does from the same seed,
+/// but tells its seed and position, which are its whole state.
+pub type GameRng = ChaCha12Rng;
+
+pub fn rng_write(rng: &GameRng, hasher: &mut impl Hasher) {
+    rng.get_seed().hash(hasher);
+    // no game draws anywhere near 2^64 words
+    (rng.get_word_pos() as u64).hash(hasher);
+}
+
+/// One board as it goes into a checksum, in the same shape for live boards and snapshots.
+pub(crate) struct BoardView<'a> {
+    pub player: u8,
+    pub board_map: &'a BoardMap,
+    pub tetromino_supplier: &'a TetrominoSupplier,
+    pub dropping_timer: &'a DroppingTimer,
+    pub lock_timer: &'a LockTimer,
+    pub garbage: Option<&'a Garbage>,
+}
+
+/// Hash of everything the next ticks depend on, boards sorted by player.
+pub(crate) fn state_checksum(tick: u64, boards: &[BoardView]) -> u64 {
+    let mut checksum = Checksum::default();
+    tick.hash(&mut checksum);
+    for board in boards {
+        board.player.hash(&mut checksum);
+        board.board_map.checksum_write(&mut checksum);
+        board.tetromino_supplier.checksum_write(&mut checksum);
+        board.dropping_timer.hash(&mut checksum);
+        board.lock_timer.hash(&mut checksum);
+        if let Some(garbage) = board.garbage {
+            garbage.checksum_write(&mut checksum);
+        }
+    }
+    checksum.finish()
+}
+
+/// The hashed state in a readable form, to tell where two clients went apart.
+pub(crate) fn state_dump(tick: u64, boards: &[BoardView]) -> String {
+    let mut text = format!("tick {tick} checksum {:016x}", state_checksum(tick, boards));
+    for board in boards {
+        let _ = write!(
+            text,
+            "\nplayer {} {:?} {:?}\n{}\n{}",
+            board.player,
+            board.dropping_timer,
+            board.lock_timer,
+            board.board_map.dump(),
+            board.tetromino_supplier.dump()
+        );
+        if let Some(garbage) = board.garbage {
+            let _ = write!(text, "\n{}", garbage.dump());
+        }
+    }
+    text
+}
+
+type BoardQuery<'a> = (
+    &'a Player,
+    &'a BoardMap,
+    &'a TetrominoSupplier,
+    &'a DroppingTimer,
+    &'a LockTimer,
+    Option<&'a Garbage>,
+);
+
+fn board_views<'a>(boards: &'a Query<BoardQuery>) -> Vec<BoardView<'a>> {
+    let mut views: Vec<BoardView> = boards
+        .iter()
+        .map(
+            |(player, board_map, tetromino_supplier, dropping_timer, lock_timer, garbage)| {
+                BoardView {
+                    player: player.0,
+                    board_map,
+                    tetromino_supplier,
+                    dropping_timer,
+                    lock_timer,
+                    garbage,
+                }
+            },
+        )
+        .collect();
+    views.sort_by_key(|view| view.player);
+    views
+}
+
+/// Keeps the checksum of every tick while recording and compares against them during playback.
+/// `dump` is only called on the first mismatch and shows the played state alone, a replay keeps
+/// nothing of the recorded state but its checksum.
+pub(crate) fn replay_checksum(
+    tick: u64,
+    checksum: u64,
+    replay_mode: &ReplayMode,
+    replay: &mut Replay,
+    dump: impl FnOnce() -> String,
+) {
+    match replay_mode {
+        ReplayMode::Record(_) => replay.checksums.push((tick, checksum)),
+        ReplayMode::Play(_) if !replay.desynced => {
+            let Ok(i) = replay
+                .checksums
+                .binary_search_by_key(&tick, |&(tick, _)| tick)
+            else {
+                return;
+            };
+            let recorded = replay.checksums[i].1;
+            if recorded != checksum {
+                replay.desynced = true;
+                error!(
+                    "replay desync at tick {tick}: recorded {recorded:016x}, played \
+                     {checksum:016x}\nplayed state, the replay keeps no recorded one:\n{}",
+                    dump()
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+// online games check the confirmed ticks from the rollback snapshots instead
+pub(crate) fn checksum_update(
+    tick: Res<Tick>,
+    boards: Query<BoardQuery>,
+    replay_mode: Res<ReplayMode>,
+    mut replay: ResMut<Replay>,
+    net: Option<Res<NetClient>>,
+) {
+    if net.is_some() || matches!(*replay_mode, ReplayMode::Off) {
+        return;
+    }
+    let views = board_views(&boards);
+    let checksum = state_checksum(tick.0, &views);
+    replay_checksum(tick.0, checksum, &replay_mode, &mut replay, || {
+        state_dump(tick.0, &views)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    fn rng_checksum(rng: &GameRng) -> u64 {
+        let mut checksum = Checksum::default();
+        rng_write(rng, &mut checksum);
+        checksum.finish()
+    }
+
+    // replays recorded with `StdRng` deal the same pieces
+    #[test]
+    fn game_rng_deals_like_std_rng() {
+        let mut game_rng = GameRng::seed_from_u64(7);
+        let mut std_rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            assert_eq!(game_rng.next_u32(), std_rng.next_u32());
+            assert_eq!(game_rng.next_u64(), std_rng.next_u64());
+        }
+    }
+
+    #[test]
+    fn rng_checksum_follows_the_stream() {
+        let mut rng = GameRng::seed_from_u64(7);
+        let start = rng_checksum(&rng);
+        rng.next_u32();
+        assert_ne!(rng_checksum(&rng), start);
+        let mut other = GameRng::seed_from_u64(8);
+        other.next_u32();
+        assert_ne!(rng_checksum(&other), rng_checksum(&rng));
+        other = GameRng::seed_from_u64(7);
+        other.next_u32();
+        assert_eq!(rng_checksum(&other), rng_checksum(&rng));
+    }
+}
@@ -0,0 +1,501 @@
+use crate::game::{replay_playing, simulation_running, tick_update, Player};
+use crate::input::*;
+use crate::settings::*;
+use crate::{Screen, TetrisSet};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+// same order the keys used to be handled in, so a rotate and a hard drop on one tick still rotate
+// first
+const DEVICE_ACTION_ORDER: [Action; 8] = [
+    Action::SoftDrop,
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::RotateLeft,
+    Action::RotateRight,
+    Action::Rotate180,
+    Action::Hold,
+    Action::HardDrop,
+];
+
+#[derive(Component)]
+struct ControlsText;
+
+// keyboard tables shown on the controls screen, Tab switches between them
+const CONTROLS_PAGES: [&str; 3] = ["Solo", "Versus Player 1", "Versus Player 2"];
+
+#[derive(Resource, Default)]
+struct ControlsMenu {
+    page: usize,
+    selected: usize,
+    waiting: bool,
+    // the key pressed next goes next to the action's keys instead of replacing them
+    adding: bool,
+    message: String,
+}
+
+#[derive(Resource)]
+struct SettingsPath(String);
+
+/// Keyboard and gamepad input, pausing and the controls screen.
+pub struct TetrisInputPlugin {
+    pub settings_path: Option<String>,
+}
+
+impl Plugin for TetrisInputPlugin {
+    fn build(&self, app: &mut App) {
+        match &self.settings_path {
+            Some(path) => {
+                app.insert_resource(Settings::load(path))
+                    .insert_resource(SettingsPath(path.clone()));
+            }
+            None => {
+                app.init_resource::<Settings>();
+            }
+        }
+        app.init_resource::<ControlsMenu>()
+            .init_resource::<ActionStates>()
+            .init_resource::<InputDelay>()
+            .add_systems(
+                FixedUpdate,
+                (action_state_update, pause_update)
+                    .chain()
+                    .in_set(TetrisSet::Input),
+            )
+            .add_systems(
+                FixedUpdate,
+                device_action_send
+                    .in_set(TetrisSet::Input)
+                    .after(action_state_update)
+                    .after(tick_update)
+                    .run_if(in_state(Screen::Game))
+                    .run_if(simulation_running)
+                    .run_if(not(replay_playing)),
+            )
+            .add_systems(Update, screen_update)
+            .add_systems(OnEnter(Screen::Controls), controls_setup)
+            .add_systems(Update, controls_update.run_if(in_state(Screen::Controls)))
+            .add_systems(OnExit(Screen::Controls), controls_exit);
+    }
+}
+
+#[derive(SystemParam)]
+struct GamepadInput<'w> {
+    gamepads: Res<'w, Gamepads>,
+    buttons: Res<'w, ButtonInput<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+}
+
+fn action_state_update(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepad_input: GamepadInput,
+    settings: Res<Settings>,
+    players: Query<&Player>,
+    local_players: Option<Res<LocalPlayers>>,
+    mut action_states: ResMut<ActionStates>,
+) {
+    let player_count = match local_players {
+        Some(local_players) => local_players.0.len(),
+        None => players.iter().count(),
+    }
+    .max(1);
+    let mut gamepads: Vec<Gamepad> = gamepad_input.gamepads.iter().collect();
+    gamepads.sort_by_key(|gamepad| gamepad.id);
+    action_states
+        .0
+        .resize_with(player_count, ActionState::default);
+    for (player, action_state) in action_states.0.iter_mut().enumerate() {
+        // solo play takes every gamepad, in versus each player gets their own
+        let player_gamepads = match player_count {
+            1 => &gamepads[..],
+            _ => gamepads.get(player..=player).unwrap_or(&[]),
+        };
+        let keyboard_bindings = settings.keyboard_bindings(player_count, player);
+        action_state.update(
+            |action| {
+                keyboard_bindings.is_some_and(|bindings| {
+                    bindings.any(action, |&key| keyboard_input.pressed(key))
+                }) || gamepad_pressed(
+                    action,
+                    &settings.gamepad_bindings,
+                    player_gamepads,
+                    &gamepad_input.buttons,
+                    &gamepad_input.axes,
+                )
+            },
+            settings.das,
+            settings.arr,
+        );
+    }
+}
+
+fn device_action_send(
+    tick: Res<Tick>,
+    input_delay: Res<InputDelay>,
+    local_players: Option<Res<LocalPlayers>>,
+    action_states: Res<ActionStates>,
+    mut game_actions: EventWriter<GameAction>,
+) {
+    for (i, action_state) in action_states.0.iter().enumerate() {
+        // with every board played by the bot the devices only pause
+        let player = match &local_players {
+            Some(local_players) => match local_players.0.get(i) {
+                Some(&player) => player,
+                None => continue,
+            },
+            None => i as u8,
+        };
+        for action in DEVICE_ACTION_ORDER {
+            if action_state.triggered(action) {
+                game_actions.send(GameAction {
+                    tick: tick.0 + input_delay.0,
+                    player,
+                    action,
+                });
+            }
+        }
+    }
+}
+
+// pause reads the per-tick action state, so it has to run on the same schedule
+fn pause_update(
+    action_states: Res<ActionStates>,
+    screen: Res<State<Screen>>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    if !action_states.triggered_any(Action::Pause) {
+        return;
+    }
+    match screen.get() {
+        Screen::Game => next_screen.set(Screen::Paused),
+        Screen::Paused => next_screen.set(Screen::Game),
+        _ => (),
+    }
+}
+
+fn screen_update(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    controls_menu: Res<ControlsMenu>,
+    screen: Res<State<Screen>>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    match screen.get() {
+        Screen::Game | Screen::Paused if keyboard_input.just_pressed(KeyCode::F1) => {
+            next_screen.set(Screen::Controls);
+        }
+        Screen::Controls if !controls_menu.waiting && keyboard_input.just_pressed(KeyCode::F1) => {
+            next_screen.set(Screen::Game);
+        }
+        _ => (),
+    }
+}
+
+fn controls_setup(mut commands: Commands, mut controls_menu: ResMut<ControlsMenu>) {
+    *controls_menu = ControlsMenu::default();
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::srgba(0.0, 0.0, 0.0, 0.85).into(),
+                ..default()
+            },
+            StateScoped(Screen::Controls),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 72.0,
+                        ..default()
+                    },
+                ),
+                ControlsText,
+            ));
+        });
+}
+
+fn controls_update(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut settings: ResMut<Settings>,
+    mut controls_menu: ResMut<ControlsMenu>,
+    mut query: Query<&mut Text, With<ControlsText>>,
+) {
+    let action_count = Action::ALL.len();
+    let action = Action::ALL[controls_menu.selected];
+    let page = controls_menu.page;
+    if controls_menu.waiting {
+        let adding = controls_menu.adding;
+        if let Some(&key) = keyboard_input.get_just_pressed().next() {
+            controls_menu.waiting = false;
+            controls_menu.message = if key == KeyCode::Escape {
+                String::new()
+            } else {
+                key_bind(&mut settings, page, action, key, adding)
+                    .err()
+                    .unwrap_or_default()
+            };
+        } else if let Some(button) = gamepad_buttons.get_just_pressed().next() {
+            let button = button.button_type;
+            controls_menu.waiting = false;
+            let bindings = &mut settings.gamepad_bindings;
+            let bound = if adding {
+                bindings.bind(action, button)
+            } else {
+                bindings.rebind(action, button)
+            };
+            controls_menu.message = match bound {
+                Ok(()) => String::new(),
+                Err(other) => {
+                    format!(
+                        "{} is already bound to {}",
+                        button.input_name(),
+                        other.name()
+                    )
+                }
+            };
+        }
+    } else if keyboard_input.just_pressed(KeyCode::Tab) {
+        controls_menu.page = (page + 1) % CONTROLS_PAGES.len();
+    } else if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        controls_menu.selected = (controls_menu.selected + action_count - 1) % action_count;
+    } else if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        controls_menu.selected = (controls_menu.selected + 1) % action_count;
+    } else if keyboard_input.any_just_pressed([KeyCode::Enter, KeyCode::Space]) {
+        controls_menu.waiting = true;
+        controls_menu.adding = keyboard_input.just_pressed(KeyCode::Space);
+        controls_menu.message = format!(
+            "Press a key or button for {}, Escape to cancel",
+            action.name()
+        );
+    } else if keyboard_input.just_pressed(KeyCode::Backspace) {
+        page_bindings(&mut settings, page).clear(action);
+        settings.gamepad_bindings.clear(action);
+    }
+
+    let mut text = format!("Controls: {}\n\n", CONTROLS_PAGES[page]);
+    for (i, action) in Action::ALL.iter().enumerate() {
+        let cursor = if i == controls_menu.selected {
+            ">"
+        } else {
+            " "
+        };
+        text += &format!(
+            "{} {}: {} / {}\n",
+            cursor,
+            action.name(),
+            page_bindings(&mut settings, page).describe(*action),
+            settings.gamepad_bindings.describe(*action)
+        );
+    }
+    text += "\nUp/Down: Select  Enter: Set Key  Space: Add Key  Backspace: Clear  Tab: Page  \
+             F1: Back\n";
+    text += &controls_menu.message;
+    query.single_mut().sections[0].value = text;
+}
+
+// binds `key` to `action` on a controls page, replacing the action's keys unless `adding`, or
+// says why it can't
+fn key_bind(
+    settings: &mut Settings,
+    page: usize,
+    action: Action,
+    key: KeyCode,
+    adding: bool,
+) -> Result<(), String> {
+    if key == KeyCode::F1 || key == KeyCode::Tab {
+        return Err(format!("{} is reserved for this screen", key.input_name()));
+    }
+    // both versus players share the keyboard, so their tables can't overlap either
+    let other_player = match page {
+        1 => settings.versus_bindings[1].conflict(key),
+        2 => settings.versus_bindings[0].conflict(key),
+        _ => None,
+    };
+    if let Some(other) = other_player {
+        return Err(format!(
+            "{} is already bound to the other player's {}",
+            key.input_name(),
+            other.name()
+        ));
+    }
+    let bindings = page_bindings(settings, page);
+    let bound = if adding {
+        bindings.bind(action, key)
+    } else {
+        bindings.rebind(action, key)
+    };
+    bound.map_err(|other| format!("{} is already bound to {}", key.input_name(), other.name()))
+}
+
+fn page_bindings(settings: &mut Settings, page: usize) -> &mut Bindings<KeyCode> {
+    match page {
+        0 => &mut settings.bindings,
+        page => &mut settings.versus_bindings[page - 1],
+    }
+}
+
+fn controls_exit(settings: Res<Settings>, settings_path: Option<Res<SettingsPath>>) {
+    if let Some(settings_path) = settings_path {
+        settings.save(&settings_path.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::input::gamepad::*;
+    use bevy::input::InputPlugin;
+
+    fn app_new(players: Vec<u8>, gamepads: usize) -> App {
+        let mut app = App::new();
+        app.add_plugins(InputPlugin)
+            .init_resource::<Settings>()
+            .insert_resource(LocalPlayers(players))
+            .init_resource::<ActionStates>()
+            .add_systems(Update, action_state_update);
+        for id in 0..gamepads {
+            let info = GamepadInfo {
+                name: format!("pad {id}"),
+            };
+            app.world_mut().send_event(GamepadEvent::Connection(GamepadConnectionEvent::new(
+                Gamepad::new(id),
+                GamepadConnection::Connected(info),
+            )));
+        }
+        app.update();
+        app
+    }
+
+    fn button_set(app: &mut App, id: usize, button: GamepadButtonType, value: f32) {
+        let event = GamepadButtonChangedEvent::new(Gamepad::new(id), button, value);
+        app.world_mut().send_event(GamepadEvent::Button(event));
+    }
+
+    fn axis_set(app: &mut App, id: usize, axis: GamepadAxisType, value: f32) {
+        let event = GamepadAxisChangedEvent::new(Gamepad::new(id), axis, value);
+        app.world_mut().send_event(GamepadEvent::Axis(event));
+    }
+
+    fn triggered(app: &App, player: usize, action: Action) -> bool {
+        app.world().resource::<ActionStates>().0[player].triggered(action)
+    }
+
+    #[test]
+    fn held_dpad_repeats_after_das() {
+        let mut app = app_new(vec![0], 1);
+        let settings = Settings::default();
+        button_set(&mut app, 0, GamepadButtonType::DPadLeft, 1.0);
+        let ticks: Vec<u32> = (0..20)
+            .filter(|_| {
+                app.update();
+                triggered(&app, 0, Action::MoveLeft)
+            })
+            .collect();
+        let das = settings.das;
+        let arr = settings.arr;
+        let expected: Vec<u32> = std::iter::once(0)
+            .chain((das..20).step_by(arr as usize))
+            .collect();
+        assert_eq!(ticks, expected);
+
+        // let go and press again, it fires at once
+        button_set(&mut app, 0, GamepadButtonType::DPadLeft, 0.0);
+        app.update();
+        assert!(!triggered(&app, 0, Action::MoveLeft));
+        button_set(&mut app, 0, GamepadButtonType::DPadLeft, 1.0);
+        app.update();
+        assert!(triggered(&app, 0, Action::MoveLeft));
+    }
+
+    #[test]
+    fn stick_counts_past_the_threshold() {
+        let mut app = app_new(vec![0], 1);
+        axis_set(&mut app, 0, GamepadAxisType::LeftStickX, -0.3);
+        app.update();
+        assert!(!triggered(&app, 0, Action::MoveLeft));
+        axis_set(&mut app, 0, GamepadAxisType::LeftStickX, -0.8);
+        app.update();
+        assert!(triggered(&app, 0, Action::MoveLeft));
+        assert!(!triggered(&app, 0, Action::MoveRight));
+        axis_set(&mut app, 0, GamepadAxisType::LeftStickX, 0.0);
+        axis_set(&mut app, 0, GamepadAxisType::LeftStickY, -0.8);
+        app.update();
+        assert!(triggered(&app, 0, Action::SoftDrop));
+        assert!(!triggered(&app, 0, Action::MoveLeft));
+    }
+
+    #[test]
+    fn solo_takes_every_gamepad() {
+        let mut app = app_new(vec![0], 2);
+        button_set(&mut app, 1, GamepadButtonType::South, 1.0);
+        app.update();
+        assert!(triggered(&app, 0, Action::RotateRight));
+    }
+
+    #[test]
+    fn versus_players_get_their_own_gamepad() {
+        let mut app = app_new(vec![0, 1], 2);
+        button_set(&mut app, 1, GamepadButtonType::South, 1.0);
+        app.update();
+        assert!(!triggered(&app, 0, Action::RotateRight));
+        assert!(triggered(&app, 1, Action::RotateRight));
+        button_set(&mut app, 0, GamepadButtonType::West, 1.0);
+        app.update();
+        assert!(triggered(&app, 0, Action::RotateLeft));
+        assert!(!triggered(&app, 1, Action::RotateLeft));
+    }
+
+    #[test]
+    fn rebinding_replaces_the_old_key() {
+        let mut settings = Settings::default();
+        let old = settings.bindings.keys(Action::MoveLeft).to_vec();
+        assert!(key_bind(&mut settings, 0, Action::MoveLeft, KeyCode::KeyJ, false).is_ok());
+        assert_eq!(settings.bindings.keys(Action::MoveLeft), [KeyCode::KeyJ]);
+        assert_eq!(settings.bindings.conflict(old[0]), None);
+        // the freed key can go to another action, and adding keeps the ones there
+        assert!(key_bind(&mut settings, 0, Action::Hold, old[0], true).is_ok());
+        assert_eq!(settings.bindings.conflict(old[0]), Some(Action::Hold));
+        // rebinding an action to its own key isn't a conflict
+        assert!(key_bind(&mut settings, 0, Action::MoveLeft, KeyCode::KeyJ, false).is_ok());
+    }
+
+    #[test]
+    fn conflicting_keys_are_refused() {
+        let mut settings = Settings::default();
+        let taken = settings.bindings.keys(Action::MoveRight)[0];
+        let before = settings.bindings.keys(Action::MoveLeft).to_vec();
+        for adding in [false, true] {
+            let e = key_bind(&mut settings, 0, Action::MoveLeft, taken, adding).unwrap_err();
+            assert!(e.ends_with("already bound to Move Right"), "{e}");
+            assert_eq!(settings.bindings.keys(Action::MoveLeft), before);
+        }
+        for reserved in [KeyCode::F1, KeyCode::Tab] {
+            let e = key_bind(&mut settings, 0, Action::Hold, reserved, false).unwrap_err();
+            assert!(e.ends_with("reserved for this screen"), "{e}");
+        }
+    }
+
+    #[test]
+    fn versus_halves_of_the_keyboard_conflict() {
+        let mut settings = Settings::default();
+        // the right player's key on the left player's page, and the other way round
+        for (page, other) in [(1, 1), (2, 0)] {
+            let taken = settings.versus_bindings[other].keys(Action::HardDrop)[0];
+            let e = key_bind(&mut settings, page, Action::Hold, taken, true).unwrap_err();
+            assert!(e.ends_with("the other player's Hard Drop"), "{e}");
+            assert_ne!(settings.versus_bindings[page - 1].conflict(taken), Some(Action::Hold));
+        }
+        // the solo table is on its own
+        let taken = settings.versus_bindings[0].keys(Action::MoveRight)[0];
+        assert!(settings.bindings.conflict(taken).is_none());
+        assert!(key_bind(&mut settings, 0, Action::Hold, taken, true).is_ok());
+    }
+}
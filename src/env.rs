@@ -0,0 +1,350 @@
+use crate::game::{piece_next, tick_step, BoardBundle, BoardState, DroppingTimer, LockTimer};
+use crate::input::Action;
+use crate::movegen::{landings, Landing};
+use crate::resource::*;
+use crate::ruleset::*;
+use std::cell::OnceCell;
+
+/// Rows of the occupancy grid.
+pub const GRID_ROWS: usize = 20;
+
+/// Inputs of the per-input action space by index, `None` lets a tick pass without one.
+pub const INPUTS: [Option<Action>; 9] = [
+    None,
+    Some(Action::MoveLeft),
+    Some(Action::MoveRight),
+    Some(Action::SoftDrop),
+    Some(Action::HardDrop),
+    Some(Action::RotateLeft),
+    Some(Action::RotateRight),
+    Some(Action::Rotate180),
+    Some(Action::Hold),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ActionSpace {
+    /// An index into `INPUTS` a step, each step is one tick with gravity and lock delay.
+    Input,
+    /// An index into `Env::placements` a step, the piece is locked there at once.
+    Placement,
+}
+
+/// What the agent sees of the game.
+#[derive(Clone, Debug)]
+pub struct Observation {
+    /// `grid[row][col]` is a block, row 0 at the bottom, the falling piece left out.
+    pub grid: [[bool; 10]; GRID_ROWS],
+    pub piece: Option<Tetromino>,
+    /// Board cells of the falling piece as `(col, row)`.
+    pub piece_cells: Vec<(i8, i8)>,
+    pub hold: Option<Tetromino>,
+    pub hold_available: bool,
+    /// The previews the player can see.
+    pub queue: Vec<Tetromino>,
+}
+
+/// What a step did besides the reward.
+#[derive(Clone, Copy, Debug)]
+pub struct Info {
+    /// Lines cleared by the step.
+    pub lines: u32,
+    pub tspin: TSpin,
+    /// Ticks played, a placement counts one for each input it takes, hold included.
+    pub tick: u64,
+    pub pieces: u32,
+    pub score: u32,
+    pub top_out: Option<TopOut>,
+}
+
+/// A move of the placement action space: the landing of the falling piece, or of the hold
+/// piece after a hold.
+#[derive(Clone, Debug)]
+pub struct EnvPlacement {
+    pub hold: bool,
+    pub landing: Landing,
+}
+
+/// One board as a gym-style environment, run in process without Bevy. The reward of a step is
+/// the score it made under the ruleset's scoring. Clones step on their own, for searching ahead.
+#[derive(Clone)]
+pub struct Env {
+    ruleset: Ruleset,
+    action_space: ActionSpace,
+    board_map: BoardMap,
+    tetromino_supplier: TetrominoSupplier,
+    dropping_timer: DroppingTimer,
+    lock_timer: LockTimer,
+    tick: u64,
+    // worked out the first time they are asked for, clones searching ahead often never are
+    placements: OnceCell<Vec<EnvPlacement>>,
+}
+
+impl Env {
+    /// Ready to play a game from seed 0, `reset` starts another.
+    pub fn new(ruleset: &Ruleset, action_space: ActionSpace) -> Self {
+        Self::start(ruleset, action_space, 0)
+    }
+
+    fn start(ruleset: &Ruleset, action_space: ActionSpace, seed: u64) -> Self {
+        let board = BoardBundle::new(0, ruleset, seed);
+        Self {
+            ruleset: ruleset.clone(),
+            action_space,
+            board_map: board.board_map,
+            tetromino_supplier: board.tetromino_supplier,
+            dropping_timer: board.dropping_timer,
+            lock_timer: board.lock_timer,
+            tick: 0,
+            placements: OnceCell::new(),
+        }
+    }
+
+    /// Starts a new game dealt from `seed`.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        *self = Self::start(&self.ruleset, self.action_space, seed);
+        self.observation()
+    }
+
+    pub fn action_space(&self) -> ActionSpace {
+        self.action_space
+    }
+
+    /// Valid actions are `0..action_count()`.
+    pub fn action_count(&self) -> usize {
+        match self.action_space {
+            ActionSpace::Input => INPUTS.len(),
+            ActionSpace::Placement => self.placements().len(),
+        }
+    }
+
+    /// The moves of the placement action space for the current piece, empty with the other.
+    pub fn placements(&self) -> &[EnvPlacement] {
+        self.placements.get_or_init(|| self.placements_get())
+    }
+
+    pub fn done(&self) -> bool {
+        self.board_map.top_out_get().is_some()
+    }
+
+    pub fn board_map(&self) -> &BoardMap {
+        &self.board_map
+    }
+
+    pub fn observation(&self) -> Observation {
+        let rows = self.board_map.stack_get();
+        let piece_cells = match self.board_map.player_get() {
+            Some(player) => {
+                let (x, y) = player.position;
+                self.board_map
+                    .player_cells(player)
+                    .iter()
+                    .map(|&(offset_x, offset_y)| (x + offset_x, y + offset_y))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        let previews = self
+            .ruleset
+            .preview_count
+            .min(self.tetromino_supplier.queue_len());
+        Observation {
+            grid: std::array::from_fn(|row| {
+                std::array::from_fn(|col| (rows[row] >> (9 - col)) & 1 == 1)
+            }),
+            piece: self.board_map.piece_get(),
+            piece_cells,
+            hold: self.board_map.hold_get(),
+            hold_available: self.board_map.hold_available(),
+            queue: (0..previews)
+                .map(|i| self.tetromino_supplier.get(i))
+                .collect(),
+        }
+    }
+
+    /// Plays `action` of the action space, a finished game stays as it is. Panics on an
+    /// action out of `0..action_count()`.
+    pub fn step(&mut self, action: usize) -> (Observation, f32, bool, Info) {
+        let score = self.board_map.score_get();
+        let mut clear = None;
+        if !self.done() {
+            match self.action_space {
+                ActionSpace::Input => {
+                    self.tick += 1;
+                    let mut board = BoardState {
+                        player: 0,
+                        board_map: &mut self.board_map,
+                        tetromino_supplier: &mut self.tetromino_supplier,
+                        dropping_timer: &mut self.dropping_timer,
+                        lock_timer: &mut self.lock_timer,
+                        garbage: None,
+                    };
+                    let input = INPUTS[action];
+                    tick_step(
+                        self.tick,
+                        &self.ruleset,
+                        std::slice::from_mut(&mut board),
+                        |_| input.into_iter().collect(),
+                        None,
+                    );
+                }
+                ActionSpace::Placement => {
+                    let placement = self.placements()[action].clone();
+                    if placement.hold {
+                        self.board_map.player_hold();
+                        piece_next(&mut self.board_map, &mut self.tetromino_supplier);
+                        self.board_map.event_reset();
+                    }
+                    placement.landing.put(&mut self.board_map);
+                    piece_next(&mut self.board_map, &mut self.tetromino_supplier);
+                    self.dropping_timer = DroppingTimer::default();
+                    self.lock_timer = LockTimer::default();
+                    self.tick += placement.landing.actions.len() as u64 + placement.hold as u64;
+                }
+            }
+            if self.board_map.event_get(BoardEvent::TetrominoPut) {
+                clear = self.board_map.clear_get();
+            }
+            self.board_map.event_reset();
+            self.placements = OnceCell::new();
+        }
+        let info = Info {
+            lines: clear.map_or(0, |clear| clear.lines),
+            tspin: clear.map_or(TSpin::None, |clear| clear.tspin),
+            tick: self.tick,
+            pieces: self.board_map.pieces_get(),
+            score: self.board_map.score_get(),
+            top_out: self.board_map.top_out_get(),
+        };
+        let reward = (self.board_map.score_get() - score) as f32;
+        (self.observation(), reward, self.done(), info)
+    }
+
+    // the falling piece's landings, then the hold piece's
+    fn placements_get(&self) -> Vec<EnvPlacement> {
+        let mut placements = Vec::new();
+        if self.action_space != ActionSpace::Placement || self.done() {
+            return placements;
+        }
+        let mut board_map = self.board_map.clone();
+        board_map.event_reset();
+        placements.extend(
+            landings(&board_map)
+                .into_iter()
+                .map(|landing| EnvPlacement {
+                    hold: false,
+                    landing,
+                }),
+        );
+        if board_map.hold_available() {
+            board_map.player_hold();
+            if board_map.piece_get().is_none() {
+                board_map.player_spawn(self.tetromino_supplier.get(0));
+            }
+            placements.extend(
+                landings(&board_map)
+                    .into_iter()
+                    .map(|landing| EnvPlacement {
+                        hold: true,
+                        landing,
+                    }),
+            );
+        }
+        placements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nes_env() -> Env {
+        Env::new(
+            &Ruleset {
+                hold: true,
+                ..Ruleset::nes()
+            },
+            ActionSpace::Placement,
+        )
+    }
+
+    #[test]
+    fn reset_deals_the_same_game() {
+        let mut a = nes_env();
+        let mut b = nes_env();
+        b.reset(3);
+        let (first, second) = (a.reset(5), b.reset(5));
+        assert_eq!(first.queue, second.queue);
+        assert_eq!(first.piece, second.piece);
+        assert_eq!(first.grid, second.grid);
+        for step in 0..20 {
+            let action = step % a.action_count();
+            let (first, reward_a, _, _) = a.step(action);
+            let (second, reward_b, _, _) = b.step(action);
+            assert_eq!(first.grid, second.grid);
+            assert_eq!(first.queue, second.queue);
+            assert_eq!(reward_a, reward_b);
+        }
+    }
+
+    #[test]
+    fn placements_are_the_landings() {
+        let mut env = nes_env();
+        env.reset(1);
+        let board_map = env.board_map().clone();
+        let mut held = board_map.clone();
+        held.player_hold();
+        if held.piece_get().is_none() {
+            held.player_spawn(env.tetromino_supplier.get(0));
+        }
+        let expected: Vec<(bool, Vec<(i8, i8)>)> = landings(&board_map)
+            .into_iter()
+            .map(|landing| (false, landing.cells))
+            .chain(
+                landings(&held)
+                    .into_iter()
+                    .map(|landing| (true, landing.cells)),
+            )
+            .collect();
+        let placements: Vec<(bool, Vec<(i8, i8)>)> = env
+            .placements()
+            .iter()
+            .map(|placement| (placement.hold, placement.landing.cells.clone()))
+            .collect();
+        assert_eq!(placements, expected);
+        assert!(placements.iter().any(|(hold, _)| *hold));
+    }
+
+    #[test]
+    fn reward_is_the_score_made() {
+        let mut env = nes_env();
+        env.reset(2);
+        let mut total = 0.;
+        for _ in 0..200 {
+            // the lowest placement, to clear some lines
+            let action = (0..env.action_count())
+                .min_by_key(|&action| {
+                    let cells = &env.placements()[action].landing.cells;
+                    cells.iter().map(|&(_, y)| y).sum::<i8>()
+                })
+                .expect("placement");
+            let score = env.board_map().score_get();
+            let (_, reward, done, info) = env.step(action);
+            assert_eq!(reward, (info.score - score) as f32);
+            total += reward;
+            if done {
+                break;
+            }
+        }
+        assert!(total > 0.);
+    }
+
+    #[test]
+    fn placements_count_their_inputs_as_ticks() {
+        let mut env = nes_env();
+        env.reset(4);
+        let placement = env.placements()[0].clone();
+        let (_, _, _, info) = env.step(0);
+        let ticks = placement.landing.actions.len() as u64 + placement.hold as u64;
+        assert_eq!(info.tick, ticks);
+    }
+}
@@ -0,0 +1,407 @@
+use crate::attack::Garbage;
+use crate::checksum::checksum_update;
+use crate::input::*;
+use crate::replay::*;
+use crate::resource::*;
+use crate::ruleset::*;
+use crate::versus::{garbage_exchange, round_over, Match};
+use crate::{Screen, TetrisSet};
+use bevy::prelude::*;
+
+const LOCK_RESET_LIMIT: u8 = 15;
+
+/// Which board a `GameAction` drives, also the board's place from left to right.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Player(pub u8);
+
+// frames since the last gravity step
+#[derive(Component, Default, Clone, Hash, Debug)]
+pub struct DroppingTimer(u32);
+
+#[derive(Component, Default, Clone, Hash, Debug)]
+pub struct LockTimer {
+    frames: u32,
+    resets: u8,
+}
+
+/// Set while the next tick has to wait, e.g. for a network peer's inputs. Ticks, device
+/// actions and the simulation hold until it is cleared.
+#[derive(Resource, Default)]
+pub struct Stall(pub bool);
+
+/// Last tick whose actions can't change anymore. Online play runs ahead of it on predicted
+/// inputs, everything else is final right away.
+#[derive(Resource)]
+pub struct Confirmed(pub u64);
+
+impl Default for Confirmed {
+    fn default() -> Self {
+        Self(u64::MAX)
+    }
+}
+
+/// Actions stamped for a later tick, waiting in `action_apply`.
+#[derive(Resource, Default)]
+pub struct PendingActions(pub Vec<GameAction>);
+
+/// One board with everything it needs to play on its own. Render entities are added as children
+/// by `TetrisRenderPlugin`.
+#[derive(Bundle)]
+pub struct BoardBundle {
+    pub player: Player,
+    pub board_map: BoardMap,
+    pub tetromino_supplier: TetrominoSupplier,
+    pub dropping_timer: DroppingTimer,
+    pub lock_timer: LockTimer,
+    pub spatial: SpatialBundle,
+}
+
+impl BoardBundle {
+    /// A board with the first piece already spawned.
+    pub fn new(player: u8, ruleset: &Ruleset, seed: u64) -> Self {
+        let mut board_map = BoardMap::new(ruleset);
+        let mut tetromino_supplier = TetrominoSupplier::new(ruleset, seed);
+        tetromino_supplier.fill();
+        board_map.player_spawn(tetromino_supplier.pop());
+        Self {
+            player: Player(player),
+            board_map,
+            tetromino_supplier,
+            dropping_timer: DroppingTimer::default(),
+            lock_timer: LockTimer::default(),
+            spatial: SpatialBundle::default(),
+        }
+    }
+}
+
+/// Everything of one board a tick changes, borrowed from the ECS, a rollback snapshot or an
+/// `Env`.
+pub struct BoardState<'a> {
+    pub player: u8,
+    pub board_map: &'a mut BoardMap,
+    pub tetromino_supplier: &'a mut TetrominoSupplier,
+    pub dropping_timer: &'a mut DroppingTimer,
+    pub lock_timer: &'a mut LockTimer,
+    /// Only in versus.
+    pub garbage: Option<&'a mut Garbage>,
+}
+
+pub(crate) type BoardQuery<'a> = (
+    &'a Player,
+    &'a mut BoardMap,
+    &'a mut TetrominoSupplier,
+    &'a mut DroppingTimer,
+    &'a mut LockTimer,
+    Option<&'a mut Garbage>,
+);
+
+/// Board, supplier, replays and the fixed-tick simulation.
+pub struct TetrisCorePlugin {
+    pub ruleset: Ruleset,
+    /// Every board is dealt the same pieces from this seed.
+    pub seed: u64,
+    pub players: u8,
+    pub playback: Option<Vec<GameAction>>,
+    pub playback_checksums: Vec<(u64, u64)>,
+    pub record: Option<String>,
+}
+
+impl Plugin for TetrisCorePlugin {
+    fn build(&self, app: &mut App) {
+        let replay_mode = match (&self.playback, &self.record) {
+            (Some(_), _) => ReplayMode::Play(0),
+            (None, Some(path)) => ReplayMode::Record(path.clone()),
+            (None, None) => ReplayMode::Off,
+        };
+        let mut replay = Replay::new(self.seed, self.players, self.ruleset.clone());
+        replay.actions = self.playback.clone().unwrap_or_default();
+        replay.checksums = self.playback_checksums.clone();
+        app.init_resource::<Tick>()
+            .init_resource::<Stall>()
+            .init_resource::<Confirmed>()
+            .init_resource::<PendingActions>()
+            .insert_resource(self.ruleset.clone())
+            .insert_resource(replay)
+            .insert_resource(replay_mode)
+            .insert_resource(Time::<Fixed>::from_hz(FRAME_RATE as f64))
+            .add_event::<GameAction>()
+            .init_state::<Screen>()
+            .enable_state_scoped_entities::<Screen>()
+            .configure_sets(
+                FixedUpdate,
+                (TetrisSet::Input, TetrisSet::Simulate, TetrisSet::Events).chain(),
+            )
+            .configure_sets(
+                FixedUpdate,
+                (TetrisSet::Simulate, TetrisSet::Events)
+                    .run_if(in_state(Screen::Game))
+                    .run_if(simulation_running),
+            )
+            .add_systems(Startup, board_startup)
+            .add_systems(
+                FixedUpdate,
+                (tick_update, replay_play)
+                    .chain()
+                    .in_set(TetrisSet::Input)
+                    .run_if(in_state(Screen::Game))
+                    .run_if(simulation_running),
+            )
+            .add_systems(
+                FixedUpdate,
+                (replay_record, action_apply)
+                    .chain()
+                    .in_set(TetrisSet::Simulate),
+            )
+            .add_systems(FixedUpdate, board_event_update.in_set(TetrisSet::Events))
+            .add_systems(
+                FixedUpdate,
+                checksum_update
+                    .after(TetrisSet::Events)
+                    .run_if(in_state(Screen::Game))
+                    .run_if(simulation_running),
+            )
+            .add_systems(Last, replay_exit);
+    }
+}
+
+fn board_startup(mut commands: Commands, replay: Res<Replay>) {
+    info!("seed {}", replay.seed);
+    for player in 0..replay.players {
+        commands.spawn(BoardBundle::new(player, &replay.ruleset, replay.seed));
+    }
+}
+
+pub(crate) fn tick_update(mut tick: ResMut<Tick>) {
+    tick.0 += 1;
+}
+
+pub(crate) fn simulation_running(stall: Res<Stall>) -> bool {
+    !stall.0
+}
+
+pub(crate) fn replay_playing(replay_mode: Res<ReplayMode>) -> bool {
+    matches!(*replay_mode, ReplayMode::Play(_))
+}
+
+fn replay_play(
+    tick: Res<Tick>,
+    replay: Res<Replay>,
+    mut replay_mode: ResMut<ReplayMode>,
+    mut game_actions: EventWriter<GameAction>,
+) {
+    let ReplayMode::Play(next) = replay_mode.as_mut() else {
+        return;
+    };
+    while let Some(&game_action) = replay.actions.get(*next) {
+        if game_action.tick > tick.0 {
+            break;
+        }
+        game_actions.send(game_action);
+        *next += 1;
+    }
+}
+
+fn replay_record(
+    mut game_actions: EventReader<GameAction>,
+    replay_mode: Res<ReplayMode>,
+    mut replay: ResMut<Replay>,
+) {
+    if let ReplayMode::Record(_) = *replay_mode {
+        replay.actions.extend(game_actions.read());
+    }
+}
+
+fn replay_save(replay_mode: &ReplayMode, replay: &Replay) {
+    if let ReplayMode::Record(path) = replay_mode {
+        match replay.save(path) {
+            Ok(()) => info!("replay saved to {path}"),
+            Err(e) => warn!("failed to write {path}: {e}"),
+        }
+    }
+}
+
+fn replay_exit(
+    mut app_exit: EventReader<AppExit>,
+    replay_mode: Res<ReplayMode>,
+    replay: Res<Replay>,
+) {
+    if app_exit.read().next().is_some() {
+        replay_save(&replay_mode, &replay);
+    }
+}
+
+// the only system that drives the boards, whatever source the actions come from
+fn action_apply(
+    tick: Res<Tick>,
+    mut game_actions: EventReader<GameAction>,
+    mut pending_actions: ResMut<PendingActions>,
+    mut boards: Query<BoardQuery>,
+    ruleset: Res<Ruleset>,
+    versus_match: Option<ResMut<Match>>,
+) {
+    // actions stamped for a later tick wait until then
+    let pending_actions = &mut pending_actions.0;
+    pending_actions.extend(game_actions.read());
+    pending_actions.sort_by_key(|game_action| game_action.tick);
+    let due = pending_actions.partition_point(|game_action| game_action.tick <= tick.0);
+    let due_actions: Vec<GameAction> = pending_actions.drain(..due).collect();
+    let mut boards: Vec<BoardState> = boards
+        .iter_mut()
+        .map(
+            |(player, board_map, tetromino_supplier, dropping_timer, lock_timer, garbage)| {
+                BoardState {
+                    player: player.0,
+                    board_map: board_map.into_inner(),
+                    tetromino_supplier: tetromino_supplier.into_inner(),
+                    dropping_timer: dropping_timer.into_inner(),
+                    lock_timer: lock_timer.into_inner(),
+                    garbage: garbage.map(Mut::into_inner),
+                }
+            },
+        )
+        .collect();
+    let actions = |player| {
+        due_actions
+            .iter()
+            .filter(|game_action| game_action.player == player)
+            .map(|game_action| game_action.action)
+            .collect()
+    };
+    tick_step(
+        tick.0,
+        &ruleset,
+        &mut boards,
+        actions,
+        versus_match.map(ResMut::into_inner),
+    );
+}
+
+/// Plays tick `tick` on the boards: each one's actions, gravity and lock delay, the next piece,
+/// then the garbage between them and, in versus, which boards are still standing. The board
+/// events stay set for whatever reacts to them and are reset by the caller.
+pub fn tick_step(
+    tick: u64,
+    ruleset: &Ruleset,
+    boards: &mut [BoardState],
+    actions: impl Fn(u8) -> Vec<Action>,
+    versus_match: Option<&mut Match>,
+) {
+    for board in boards.iter_mut() {
+        board_step(
+            board.board_map,
+            board.dropping_timer,
+            board.lock_timer,
+            ruleset,
+            actions(board.player),
+        );
+        piece_next(board.board_map, board.tetromino_supplier);
+    }
+    let mut garbage_boards: Vec<(u8, &mut BoardMap, &mut Garbage)> = boards
+        .iter_mut()
+        .filter_map(|board| {
+            let garbage = board.garbage.as_deref_mut()?;
+            Some((board.player, &mut *board.board_map, garbage))
+        })
+        .collect();
+    garbage_exchange(tick, ruleset, &mut garbage_boards);
+    let Some(versus_match) = versus_match else {
+        return;
+    };
+    for board in boards.iter() {
+        if board.board_map.event_get(BoardEvent::GameOver) {
+            versus_match.alive[board.player as usize] = false;
+        }
+    }
+    if versus_match.end_tick.is_none() && round_over(&versus_match.alive) {
+        versus_match.end_tick = Some(tick);
+    }
+}
+
+/// Applies one tick of actions, gravity and lock delay to a board.
+fn board_step(
+    board_map: &mut BoardMap,
+    dropping_timer: &mut DroppingTimer,
+    lock_timer: &mut LockTimer,
+    ruleset: &Ruleset,
+    actions: impl IntoIterator<Item = Action>,
+) {
+    let mut moved = false;
+    let mut soft_dropped = false;
+    for action in actions {
+        match action {
+            Action::SoftDrop => {
+                moved |= board_map.player_move(MoveDirection::Down);
+                dropping_timer.0 = 0;
+                soft_dropped = true;
+            }
+            Action::MoveLeft => moved |= board_map.player_move(MoveDirection::Left),
+            Action::MoveRight => moved |= board_map.player_move(MoveDirection::Right),
+            Action::RotateLeft => moved |= board_map.player_rotate(RotateDirection::Left),
+            Action::RotateRight => moved |= board_map.player_rotate(RotateDirection::Right),
+            Action::Rotate180 => moved |= board_map.player_rotate(RotateDirection::Half),
+            Action::Hold => board_map.player_hold(),
+            Action::HardDrop if ruleset.hard_drop => {
+                board_map.player_move_to_bottom();
+                dropping_timer.0 = 0;
+            }
+            Action::HardDrop | Action::Pause => (),
+        }
+    }
+    if !soft_dropped {
+        dropping_timer.0 += 1;
+        if dropping_timer.0 >= ruleset.gravity_frames(board_map.level_get()) {
+            dropping_timer.0 = 0;
+            moved |= board_map.player_move(MoveDirection::Down);
+        }
+    }
+    let Some(lock_delay) = ruleset.lock_delay else {
+        return;
+    };
+    if !board_map.player_grounded() {
+        *lock_timer = LockTimer::default();
+        return;
+    }
+    if moved && lock_timer.resets < LOCK_RESET_LIMIT {
+        lock_timer.frames = 0;
+        lock_timer.resets += 1;
+    }
+    lock_timer.frames += 1;
+    if lock_timer.frames >= lock_delay {
+        board_map.player_lock();
+        lock_timer.frames = 0;
+    }
+}
+
+// the next piece comes in on the same tick the last one was put or held
+pub(crate) fn piece_next(board_map: &mut BoardMap, tetromino_supplier: &mut TetrominoSupplier) {
+    if board_map.event_get(BoardEvent::TetrominoPut)
+        || board_map.event_get(BoardEvent::TetrominoHeld)
+    {
+        board_map.player_spawn(tetromino_supplier.pop());
+    }
+}
+
+// sound and anything else reacting to the board runs in `TetrisSet::Events` before this
+pub(crate) fn board_event_update(
+    mut boards: Query<(&Player, &mut BoardMap)>,
+    replay_mode: Res<ReplayMode>,
+    replay: Res<Replay>,
+) {
+    for (player, mut board_map) in &mut boards {
+        if board_map.event_get(BoardEvent::TetrominoPut) {
+            if let Some(LineClear {
+                lines,
+                tspin: tspin @ (TSpin::Mini | TSpin::Full),
+                ..
+            }) = board_map.clear_get()
+            {
+                info!("player {} T-Spin {:?} {}", player.0, tspin, lines);
+            }
+        }
+        if board_map.event_get(BoardEvent::GameOver) {
+            info!("player {} GameOver", player.0);
+            replay_save(&replay_mode, &replay);
+        }
+        board_map.event_reset();
+    }
+}
@@ -0,0 +1,309 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// how far the stick has to be pushed to count as a d-pad press
+const STICK_THRESHOLD: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateLeft,
+    RotateRight,
+    Rotate180,
+    Hold,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; 9] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Rotate180,
+        Action::Hold,
+        Action::Pause,
+    ];
+
+    /// Held actions repeat after the DAS delay.
+    pub fn repeats(self) -> bool {
+        matches!(
+            self,
+            Action::MoveLeft | Action::MoveRight | Action::SoftDrop
+        )
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move Left",
+            Action::MoveRight => "Move Right",
+            Action::SoftDrop => "Soft Drop",
+            Action::HardDrop => "Hard Drop",
+            Action::RotateLeft => "Rotate Left",
+            Action::RotateRight => "Rotate Right",
+            Action::Rotate180 => "Rotate 180",
+            Action::Hold => "Hold",
+            Action::Pause => "Pause",
+        }
+    }
+}
+
+/// An action stamped with the tick it applies to and the player whose board it drives.
+/// Keyboard, gamepad, replays, bots and network peers all send these, `action_apply` is the
+/// only reader.
+#[derive(Event, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct GameAction {
+    pub tick: u64,
+    #[serde(default)]
+    pub player: u8,
+    pub action: Action,
+}
+
+/// Frames since the game started.
+#[derive(Resource, Default)]
+pub struct Tick(pub u64);
+
+/// Boards driven by the keyboard and gamepads of this machine, every board when missing.
+#[derive(Resource, Clone)]
+pub struct LocalPlayers(pub Vec<u8>);
+
+/// Ticks between reading a device action and applying it, so peers get it in time.
+#[derive(Resource, Default)]
+pub struct InputDelay(pub u64);
+
+pub trait InputName {
+    fn input_name(&self) -> String;
+}
+
+impl InputName for KeyCode {
+    fn input_name(&self) -> String {
+        let name = format!("{:?}", self);
+        match name.as_str() {
+            "ArrowLeft" => "Left".to_string(),
+            "ArrowRight" => "Right".to_string(),
+            "ArrowUp" => "Up".to_string(),
+            "ArrowDown" => "Down".to_string(),
+            _ => name
+                .strip_prefix("Key")
+                .or_else(|| name.strip_prefix("Digit"))
+                .unwrap_or(&name)
+                .to_string(),
+        }
+    }
+}
+
+impl InputName for GamepadButtonType {
+    fn input_name(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+/// Keys or gamepad buttons bound to each action.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bindings<T>(BTreeMap<Action, Vec<T>>);
+
+impl Default for Bindings<KeyCode> {
+    fn default() -> Self {
+        Self(BTreeMap::from([
+            (Action::MoveLeft, vec![KeyCode::ArrowLeft]),
+            (Action::MoveRight, vec![KeyCode::ArrowRight]),
+            (Action::SoftDrop, vec![KeyCode::ArrowDown]),
+            (Action::HardDrop, vec![KeyCode::Space]),
+            (Action::RotateLeft, vec![KeyCode::KeyQ]),
+            (Action::RotateRight, vec![KeyCode::KeyE, KeyCode::KeyW]),
+            (Action::Rotate180, vec![KeyCode::KeyA]),
+            (Action::Hold, vec![KeyCode::KeyC]),
+            (Action::Pause, vec![KeyCode::Escape, KeyCode::KeyP]),
+        ]))
+    }
+}
+
+impl Bindings<KeyCode> {
+    /// Left half of the keyboard for the first versus player.
+    pub fn versus_left() -> Self {
+        Self(BTreeMap::from([
+            (Action::MoveLeft, vec![KeyCode::KeyA]),
+            (Action::MoveRight, vec![KeyCode::KeyD]),
+            (Action::SoftDrop, vec![KeyCode::KeyS]),
+            (Action::HardDrop, vec![KeyCode::KeyW]),
+            (Action::RotateLeft, vec![KeyCode::KeyQ]),
+            (Action::RotateRight, vec![KeyCode::KeyE]),
+            (Action::Rotate180, vec![KeyCode::KeyR]),
+            (Action::Hold, vec![KeyCode::ShiftLeft]),
+            (Action::Pause, vec![KeyCode::Escape]),
+        ]))
+    }
+
+    /// Arrows and the keys around them for the second versus player.
+    pub fn versus_right() -> Self {
+        Self(BTreeMap::from([
+            (Action::MoveLeft, vec![KeyCode::ArrowLeft]),
+            (Action::MoveRight, vec![KeyCode::ArrowRight]),
+            (Action::SoftDrop, vec![KeyCode::ArrowDown]),
+            (Action::HardDrop, vec![KeyCode::ArrowUp]),
+            (Action::RotateLeft, vec![KeyCode::Comma, KeyCode::Numpad1]),
+            (Action::RotateRight, vec![KeyCode::Period, KeyCode::Numpad2]),
+            (Action::Rotate180, vec![KeyCode::Slash, KeyCode::Numpad3]),
+            (Action::Hold, vec![KeyCode::ShiftRight, KeyCode::Numpad0]),
+            (Action::Pause, vec![KeyCode::KeyP]),
+        ]))
+    }
+}
+
+impl Default for Bindings<GamepadButtonType> {
+    fn default() -> Self {
+        Self(BTreeMap::from([
+            (Action::MoveLeft, vec![GamepadButtonType::DPadLeft]),
+            (Action::MoveRight, vec![GamepadButtonType::DPadRight]),
+            (Action::SoftDrop, vec![GamepadButtonType::DPadDown]),
+            (Action::HardDrop, vec![GamepadButtonType::DPadUp]),
+            (Action::RotateLeft, vec![GamepadButtonType::West]),
+            (Action::RotateRight, vec![GamepadButtonType::South]),
+            (Action::Rotate180, vec![GamepadButtonType::North]),
+            (
+                Action::Hold,
+                vec![
+                    GamepadButtonType::LeftTrigger,
+                    GamepadButtonType::RightTrigger,
+                ],
+            ),
+            (Action::Pause, vec![GamepadButtonType::Start]),
+        ]))
+    }
+}
+
+impl<T: InputName + Copy + PartialEq> Bindings<T> {
+    pub fn keys(&self, action: Action) -> &[T] {
+        self.0.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn any(&self, action: Action, pressed: impl FnMut(&T) -> bool) -> bool {
+        self.keys(action).iter().any(pressed)
+    }
+
+    /// The action the key is already bound to, if any.
+    pub fn conflict(&self, key: T) -> Option<Action> {
+        self.0
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(&action, _)| action)
+    }
+
+    pub fn bind(&mut self, action: Action, key: T) -> Result<(), Action> {
+        match self.conflict(key) {
+            Some(other) if other != action => Err(other),
+            Some(_) => Ok(()),
+            None => {
+                self.0.entry(action).or_default().push(key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Makes `key` the only key of `action`, unless another action has it.
+    pub fn rebind(&mut self, action: Action, key: T) -> Result<(), Action> {
+        match self.conflict(key) {
+            Some(other) if other != action => Err(other),
+            _ => {
+                self.0.insert(action, vec![key]);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        self.0.remove(&action);
+    }
+
+    pub fn describe(&self, action: Action) -> String {
+        let keys: Vec<String> = self.keys(action).iter().map(T::input_name).collect();
+        if keys.is_empty() {
+            "-".to_string()
+        } else {
+            keys.join(" ")
+        }
+    }
+}
+
+/// Which actions fire this tick for one player, from all of their devices, with DAS applied
+/// to held actions.
+#[derive(Default)]
+pub struct ActionState {
+    held: BTreeMap<Action, u32>,
+    triggered: Vec<Action>,
+}
+
+impl ActionState {
+    /// Called once per tick, `das` and `arr` are in ticks and an `arr` of 0 repeats every tick.
+    pub fn update(&mut self, mut pressed: impl FnMut(Action) -> bool, das: u32, arr: u32) {
+        self.triggered.clear();
+        for action in Action::ALL {
+            if !pressed(action) {
+                self.held.remove(&action);
+                continue;
+            }
+            let Some(&before) = self.held.get(&action) else {
+                self.held.insert(action, 0);
+                self.triggered.push(action);
+                continue;
+            };
+            let after = before + 1;
+            self.held.insert(action, after);
+            if !action.repeats() || after < das {
+                continue;
+            }
+            if arr == 0 || (after - das).is_multiple_of(arr) {
+                self.triggered.push(action);
+            }
+        }
+    }
+
+    pub fn triggered(&self, action: Action) -> bool {
+        self.triggered.contains(&action)
+    }
+}
+
+/// Action state of every local player, in the order of `LocalPlayers`.
+#[derive(Resource, Default)]
+pub struct ActionStates(pub Vec<ActionState>);
+
+impl ActionStates {
+    pub fn triggered_any(&self, action: Action) -> bool {
+        self.0
+            .iter()
+            .any(|action_state| action_state.triggered(action))
+    }
+}
+
+pub fn gamepad_pressed(
+    action: Action,
+    bindings: &Bindings<GamepadButtonType>,
+    gamepads: &[Gamepad],
+    buttons: &ButtonInput<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+) -> bool {
+    gamepads.iter().any(|&gamepad| {
+        let stick_x = axes
+            .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+            .unwrap_or(0.0);
+        let stick_y = axes
+            .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+            .unwrap_or(0.0);
+        let stick = match action {
+            Action::MoveLeft => stick_x < -STICK_THRESHOLD,
+            Action::MoveRight => stick_x > STICK_THRESHOLD,
+            Action::SoftDrop => stick_y < -STICK_THRESHOLD,
+            _ => false,
+        };
+        stick
+            || bindings.any(action, |&button| {
+                buttons.pressed(GamepadButton::new(gamepad, button))
+            })
+    })
+}
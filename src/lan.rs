@@ -0,0 +1,292 @@
+use crate::ruleset::*;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// First UDP port lobbies listen on for announcements.
+pub const DISCOVERY_PORT: u16 = 7879;
+/// Lobbies take the first free port of this many from `DISCOVERY_PORT` and hosts announce to
+/// all of them, so several lobbies can run on one machine.
+pub const DISCOVERY_PORTS: u16 = 8;
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_BROADCAST: IpAddr = IpAddr::V4(Ipv4Addr::BROADCAST);
+// a game is dropped from the list after missing this many announcements
+const EXPIRY: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum Mode {
+    Versus { best_of: u8 },
+}
+
+/// What a host broadcasts about its game every `ANNOUNCE_INTERVAL`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    pub name: String,
+    /// TCP port of the game, on the address the announcement came from.
+    pub port: u16,
+    pub mode: Mode,
+    pub ruleset: Ruleset,
+    /// Players waiting for an opponent.
+    pub players: u8,
+    /// Players a match starts with.
+    pub capacity: u8,
+}
+
+impl Announcement {
+    /// One line for the lobby list.
+    pub fn describe(&self) -> String {
+        let mode = match self.mode {
+            Mode::Versus { best_of } => format!("versus, best of {best_of}"),
+        };
+        format!(
+            "{}  {}  {}  {}/{}",
+            self.name,
+            mode,
+            ruleset_describe(&self.ruleset),
+            self.players,
+            self.capacity
+        )
+    }
+}
+
+fn ruleset_describe(ruleset: &Ruleset) -> String {
+    let rotation = match (&ruleset.pieces, ruleset.rotation) {
+        (Some(pieces), _) => pieces.path.as_str(),
+        (None, RotationKind::Srs) => "SRS",
+        (None, RotationKind::Ars) => "ARS",
+        (None, RotationKind::Classic) => "Classic",
+    };
+    let randomizer = match ruleset.randomizer {
+        Randomizer::Random => "random",
+        Randomizer::Bag => "bag",
+        Randomizer::Nes => "NES",
+    };
+    let mut text = format!("{rotation} {randomizer}");
+    if ruleset.hold {
+        text += " hold";
+    }
+    text += &format!(" {} next", ruleset.preview_count);
+    text
+}
+
+/// Sends a host's announcements to every lobby port at one broadcast address.
+pub struct Announcer {
+    socket: UdpSocket,
+    broadcast: IpAddr,
+}
+
+impl Announcer {
+    /// `broadcast` is usually `DEFAULT_BROADCAST`, a loopback address keeps the game on this
+    /// machine.
+    pub fn new(broadcast: IpAddr) -> Result<Self, String> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(|e| e.to_string())?;
+        socket.set_broadcast(true).map_err(|e| e.to_string())?;
+        Ok(Self { socket, broadcast })
+    }
+
+    pub fn send(&self, announcement: &Announcement) -> Result<(), String> {
+        let text = ron::to_string(announcement).map_err(|e| e.to_string())?;
+        for port in DISCOVERY_PORT..DISCOVERY_PORT + DISCOVERY_PORTS {
+            self.socket
+                .send_to(text.as_bytes(), (self.broadcast, port))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// A game heard on the network.
+pub struct LanGame {
+    /// Where to `--connect`.
+    pub addr: SocketAddr,
+    pub announcement: Announcement,
+    seen: Instant,
+}
+
+/// Listens for announcements and keeps the games heard lately.
+#[derive(Resource)]
+pub struct Discovery {
+    socket: UdpSocket,
+    games: Vec<LanGame>,
+}
+
+impl Discovery {
+    pub fn bind() -> Result<Self, String> {
+        let mut last_error = String::new();
+        for port in DISCOVERY_PORT..DISCOVERY_PORT + DISCOVERY_PORTS {
+            match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)) {
+                Ok(socket) => {
+                    socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+                    return Ok(Self {
+                        socket,
+                        games: Vec::new(),
+                    });
+                }
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+        Err(format!("no free discovery port: {last_error}"))
+    }
+
+    pub fn port_get(&self) -> Option<u16> {
+        self.socket.local_addr().ok().map(|addr| addr.port())
+    }
+
+    /// Reads the announcements received since the last call and drops games gone quiet.
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let mut buf = [0; 65536];
+        while let Ok((len, from)) = self.socket.recv_from(&mut buf) {
+            // anything else on the port is not ours
+            let Some(announcement) = std::str::from_utf8(&buf[..len])
+                .ok()
+                .and_then(|text| ron::from_str::<Announcement>(text).ok())
+            else {
+                continue;
+            };
+            let addr = SocketAddr::new(from.ip(), announcement.port);
+            match self.games.iter_mut().find(|game| game.addr == addr) {
+                Some(game) => {
+                    game.announcement = announcement;
+                    game.seen = now;
+                }
+                None => self.games.push(LanGame {
+                    addr,
+                    announcement,
+                    seen: now,
+                }),
+            }
+        }
+        self.games
+            .retain(|game| now.duration_since(game.seen) < EXPIRY);
+        self.games.sort_by_key(|game| game.addr);
+    }
+
+    pub fn games_get(&self) -> &[LanGame] {
+        &self.games
+    }
+}
+
+/// A lobby listing the games announced on the network. Joining one starts the game again with
+/// `--connect` to it, the rest of the command line is kept.
+pub struct TetrisLobbyPlugin;
+
+impl Plugin for TetrisLobbyPlugin {
+    fn build(&self, app: &mut App) {
+        let discovery = Discovery::bind().unwrap_or_else(|e| panic!("lobby: {e}"));
+        app.insert_resource(discovery)
+            .init_resource::<LobbyMenu>()
+            .add_systems(Startup, lobby_setup)
+            .add_systems(Update, lobby_update);
+    }
+}
+
+#[derive(Resource, Default)]
+struct LobbyMenu {
+    selected: usize,
+    message: String,
+}
+
+#[derive(Component)]
+struct LobbyText;
+
+fn lobby_setup(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 72.0,
+                        ..default()
+                    },
+                ),
+                LobbyText,
+            ));
+        });
+}
+
+fn lobby_update(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut discovery: ResMut<Discovery>,
+    mut lobby_menu: ResMut<LobbyMenu>,
+    mut query: Query<&mut Text, With<LobbyText>>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    discovery.update();
+    let games = discovery.games_get();
+    let button = |button_type| {
+        gamepad_buttons
+            .get_just_pressed()
+            .any(|button| button.button_type == button_type)
+    };
+    if !games.is_empty() {
+        let count = games.len();
+        let selected = lobby_menu.selected.min(count - 1);
+        lobby_menu.selected =
+            if keyboard_input.just_pressed(KeyCode::ArrowUp) || button(GamepadButtonType::DPadUp) {
+                (selected + count - 1) % count
+            } else if keyboard_input.just_pressed(KeyCode::ArrowDown)
+                || button(GamepadButtonType::DPadDown)
+            {
+                (selected + 1) % count
+            } else {
+                selected
+            };
+        if keyboard_input.just_pressed(KeyCode::Enter) || button(GamepadButtonType::Start) {
+            let addr = games[lobby_menu.selected].addr;
+            match game_join(addr) {
+                Ok(()) => {
+                    app_exit.send(AppExit::Success);
+                }
+                Err(e) => lobby_menu.message = format!("failed to join {addr}: {e}"),
+            }
+        }
+    }
+
+    let mut text = "LAN Games\n\n".to_string();
+    if games.is_empty() {
+        text += "  Looking for games...\n";
+    }
+    for (i, game) in games.iter().enumerate() {
+        let cursor = if i == lobby_menu.selected { ">" } else { " " };
+        text += &format!(
+            "{} {}  {}\n",
+            cursor,
+            game.announcement.describe(),
+            game.addr
+        );
+    }
+    text += "\nUp/Down: Select  Enter: Join\n";
+    text += &lobby_menu.message;
+    query.single_mut().sections[0].value = text;
+}
+
+// a window can't be opened twice in one process, so the game runs in a new one
+fn game_join(addr: SocketAddr) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--lobby")
+        .collect();
+    Command::new(exe)
+        .args(args)
+        .args(["--connect", &addr.to_string()])
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,177 @@
+pub mod args;
+pub mod attack;
+pub mod bitboard;
+pub mod bot;
+pub mod checksum;
+mod controls;
+pub mod env;
+mod game;
+pub mod input;
+pub mod lan;
+pub mod movegen;
+pub mod net;
+pub mod piece;
+mod render;
+pub mod replay;
+pub mod resource;
+pub mod rollback;
+pub mod rotation;
+pub mod ruleset;
+pub mod settings;
+mod sound;
+pub mod tbp;
+pub mod tune;
+mod versus;
+
+pub use bot::TetrisBotPlugin;
+pub use controls::TetrisInputPlugin;
+pub use game::{Player, TetrisCorePlugin};
+pub use lan::TetrisLobbyPlugin;
+pub use net::TetrisNetPlugin;
+pub use render::TetrisRenderPlugin;
+pub use sound::TetrisAudioPlugin;
+pub use versus::TetrisVersusPlugin;
+
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use bot::BotSettings;
+use input::{GameAction, LocalPlayers};
+use net::NetClient;
+use ruleset::*;
+use settings::SETTINGS_PATH;
+use std::time::Duration;
+
+#[derive(States, Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Screen {
+    #[default]
+    Game,
+    Paused,
+    Controls,
+    Countdown,
+    Result,
+}
+
+/// Input, simulate and events run once per tick in `FixedUpdate`, render reads the result in
+/// `Update`. Systems added by other plugins can be ordered against these.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TetrisSet {
+    Input,
+    Simulate,
+    Events,
+    Render,
+}
+
+/// The whole game. Add it after `DefaultPlugins`, or at least the asset, window, input, audio
+/// and state plugins the sub-plugins use.
+#[derive(Clone)]
+pub struct TetrisPlugin {
+    pub ruleset: Ruleset,
+    /// Seed of the piece supplier, shared by every board.
+    pub seed: u64,
+    /// Number of boards, each driven by the `GameAction`s for its player.
+    pub players: u8,
+    /// Play a versus match of this many rounds between the boards.
+    pub best_of: Option<u8>,
+    /// Actions to play back instead of reading the devices.
+    pub playback: Option<Vec<GameAction>>,
+    /// `(tick, checksum)` pairs the playback is checked against.
+    pub playback_checksums: Vec<(u64, u64)>,
+    /// Replay file written on game over or exit.
+    pub record: Option<String>,
+    /// Bindings file read at startup and written when leaving the controls screen,
+    /// `None` keeps the default bindings in memory only.
+    pub settings_path: Option<String>,
+    /// Play the versus match online against this server's other client.
+    pub net: Option<NetClient>,
+    /// Boards played by the built-in bot, the devices drive the others.
+    pub bot: Option<BotSettings>,
+    pub render: bool,
+    pub audio: bool,
+}
+
+impl Default for TetrisPlugin {
+    fn default() -> Self {
+        Self {
+            ruleset: Ruleset::default(),
+            seed: rand::random(),
+            players: 1,
+            best_of: None,
+            playback: None,
+            playback_checksums: Vec::new(),
+            record: None,
+            settings_path: Some(SETTINGS_PATH.to_string()),
+            net: None,
+            bot: None,
+            render: true,
+            audio: true,
+        }
+    }
+}
+
+impl TetrisPlugin {
+    /// An app of the game without a window, audio or GPU, for the tools and tests. Every
+    /// `update` plays one tick.
+    pub fn headless(self) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, StatesPlugin, InputPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+                1. / FRAME_RATE as f64,
+            )))
+            .add_plugins(TetrisPlugin {
+                settings_path: None,
+                render: false,
+                audio: false,
+                ..self
+            });
+        app.finish();
+        app.cleanup();
+        app
+    }
+}
+
+impl Plugin for TetrisPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(TetrisCorePlugin {
+            ruleset: self.ruleset.clone(),
+            seed: self.seed,
+            players: self.players,
+            playback: self.playback.clone(),
+            playback_checksums: self.playback_checksums.clone(),
+            record: self.record.clone(),
+        })
+        .add_plugins(TetrisInputPlugin {
+            settings_path: self.settings_path.clone(),
+        });
+        if let Some(best_of) = self.best_of {
+            app.add_plugins(TetrisVersusPlugin {
+                best_of,
+                players: self.players,
+            });
+        }
+        if let Some(client) = &self.net {
+            if self.bot.is_some() {
+                warn!("bots can't play over the network, the bot settings are ignored");
+            }
+            app.add_plugins(TetrisNetPlugin {
+                client: client.clone(),
+            });
+        }
+        if let (Some(bot), None) = (&self.bot, &self.net) {
+            let humans = (0..self.players)
+                .filter(|player| !bot.players.contains(player))
+                .collect();
+            app.insert_resource(LocalPlayers(humans))
+                .add_plugins(TetrisBotPlugin {
+                    settings: bot.clone(),
+                });
+        }
+        if self.render {
+            app.add_plugins(TetrisRenderPlugin);
+        }
+        if self.audio {
+            app.add_plugins(TetrisAudioPlugin);
+        }
+    }
+}
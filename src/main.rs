@@ -1,311 +1,122 @@
-mod resource;
-use bevy::audio::Volume;
-use resource::*;
-
 use bevy::prelude::*;
 use bevy::window::WindowResolution;
-
-const TILE_SIZE: f32 = 64.;
-const BOARD_WIDTH: f32 = 10. * 64.;
-const BOARD_HEIGHT: f32 = 20. * 64.;
-
-#[derive(Component)]
-struct Block {
-    row: u8,
-    column: u8,
-    tile: Option<Tetromino>,
-}
-
-#[derive(Component)]
-struct Board;
-
-#[derive(Component)]
-struct TetrominoPreview {
-    index: usize,
-    tetromino: Tetromino,
-}
-
-#[derive(Component)]
-struct ScoreText;
-
-#[derive(Resource)]
-struct DroppingTimer(Timer);
-
-fn main() {
-    App::new()
-        .insert_resource(TileAssets::new())
-        .insert_resource(TetrominoAssets::new())
-        .insert_resource(BoardMap::new())
-        .insert_resource(TetrominoSupplier::new())
-        .insert_resource(DroppingTimer(Timer::from_seconds(
-            1.0,
-            TimerMode::Repeating,
-        )))
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                // window resolution, scale
-                resolution: WindowResolution::new(1280., 720.).with_scale_factor_override(0.5),
-                ..default()
-            }),
-            ..default()
-        }))
-        .add_systems(Startup, setup)
-        .add_systems(Startup, board_startup)
-        .add_systems(Update, board_update)
-        .add_systems(Update, score_update)
-        .add_systems(Update, preview_update)
-        .add_systems(Update, player_update)
-        .add_systems(Update, board_event_update)
-        .run();
-}
-
-fn setup(
-    mut commands: Commands,
-    mut tile_assets: ResMut<TileAssets>,
-    mut tetromino_assets: ResMut<TetrominoAssets>,
-    asset_server: Res<AssetServer>,
-) {
-    tile_assets.setup(&asset_server);
-    tetromino_assets.setup(&asset_server);
-
-    // camera setup
-    commands.spawn(Camera2dBundle::default());
-
-    // board setup
-    commands.spawn((
-        SpriteBundle {
-            texture: asset_server.load("Board.png"),
-            ..default()
-        },
-        Board,
-    ));
-
-    // block setup
-    for y in 0..20 {
-        for x in 0..10 {
-            commands.spawn((
-                SpriteBundle {
-                    texture: tile_assets.get(Tetromino::G),
-                    transform: Transform::from_xyz(
-                        x as f32 * TILE_SIZE - BOARD_WIDTH * 0.5 + TILE_SIZE * 0.5,
-                        y as f32 * TILE_SIZE - BOARD_HEIGHT * 0.5 + TILE_SIZE * 0.5,
-                        1.,
-                    ),
-                    visibility: Visibility::Hidden,
-                    ..default()
-                },
-                Block {
-                    row: y,
-                    column: x,
-                    tile: None,
-                },
-            ));
+use tetris_bevy::args::*;
+use tetris_bevy::bot::{self, BotSettings, Weights};
+use tetris_bevy::net::NetClient;
+use tetris_bevy::replay::Replay;
+use tetris_bevy::tbp::TbpBots;
+use tetris_bevy::ruleset::Ruleset;
+use tetris_bevy::{TetrisLobbyPlugin, TetrisPlugin};
+
+/// `--replay <path>` plays a file back, otherwise a new game is started from
+/// `--seed <n>` (random if missing) with `--players <n>` boards and the ruleset flags, and
+/// recorded to `--record <path>`. `--versus` plays a match of `--best-of <n>` rounds,
+/// `--connect <addr>` plays one against a `tetris_server` opponent with the server's rules, by
+/// hand only.
+/// `--bot <n,..>` hands the numbered boards to the bot, playing `--pps <n>` pieces a second.
+/// `--tbp "<command>"` runs that TBP bot program for them instead, board 1 by default.
+/// `--weights <path>` loads the bot's evaluation weights, e.g. from `tetris_tune`.
+fn tetris_parse(args: &[String]) -> Result<TetrisPlugin, String> {
+    if let Some(path) = arg_get(args, "--replay") {
+        let mut replay =
+            Replay::load(path).map_err(|e| format!("failed to load replay {path}: {e}"))?;
+        if !replay.checksums_current() {
+            eprintln!("{path} has checksums of an older version, playing it without checking");
+            replay.checksums.clear();
         }
-    }
-
-    // score text
-    commands.spawn((
-        TextBundle::from_section(
-            "00000000",
-            TextStyle {
-                font_size: 128.0,
-                ..default()
-            },
-        )
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            top: Val::Px(5.0),
-            right: Val::Px(15.0),
-            ..default()
-        }),
-        ScoreText,
-    ));
-
-    // preview bg
-    commands.spawn((
-        SpriteBundle {
-            texture: asset_server.load("blocks/Ghost.png"),
-            sprite: Sprite {
-                custom_size: Some(Vec2::new(192.0, 640.0)),
-                ..default()
-            },
-            transform: Transform::from_xyz(
-                BOARD_WIDTH * 0.5 + 256.0,
-                BOARD_HEIGHT * 0.5 - 512.0,
-                0.,
-            ),
-            ..default()
-        },
-        ImageScaleMode::Sliced(TextureSlicer {
-            border: BorderRect::square(10.),
-            center_scale_mode: SliceScaleMode::Tile { stretch_value: 0.5 },
-            sides_scale_mode: SliceScaleMode::Tile { stretch_value: 0.5 },
+        return Ok(TetrisPlugin {
+            ruleset: replay.ruleset,
+            seed: replay.seed,
+            players: replay.players,
+            best_of: replay.best_of,
+            playback: Some(replay.actions),
+            playback_checksums: replay.checksums,
             ..default()
-        }),
-    ));
-
-    // next tetrominoe preview
-    for i in 0..5 {
-        commands.spawn((
-            SpriteBundle {
-                texture: tetromino_assets.get(Tetromino::I),
-                transform: Transform::from_xyz(
-                    BOARD_WIDTH * 0.5 + 256.0,
-                    BOARD_HEIGHT * 0.5 - 256.0 - 128.0 * (i as f32),
-                    1.,
-                )
-                .with_scale(Vec3::splat(0.5)),
-                ..default()
-            },
-            TetrominoPreview {
-                index: i,
-                tetromino: Tetromino::I,
-            },
-        ));
+        });
     }
-
-    // guide text
-    commands.spawn((TextBundle::from_section(
-        "Move: Arrow Key\nRotate: Q E\nPut: Speace",
-        TextStyle {
-            font_size: 96.0,
-            ..default()
-        },
-    )
-    .with_style(Style {
-        position_type: PositionType::Absolute,
-        top: Val::Px(5.0),
-        left: Val::Px(15.0),
-        ..default()
-    }),));
-}
-
-fn board_startup(
-    mut board_map: ResMut<BoardMap>,
-    mut tetromino_supplier: ResMut<TetrominoSupplier>,
-) {
-    let rng = &mut rand::thread_rng();
-    board_map.player_spawn(Tetromino::gen(rng));
-    tetromino_supplier.fill(rng);
-}
-
-fn board_update(
-    mut query: Query<(&mut Block, &mut Handle<Image>, &mut Visibility)>,
-    board_map: Res<BoardMap>,
-    tile_assets: Res<TileAssets>,
-) {
-    for (mut block, mut tile_image, mut visibility) in &mut query {
-        let tile = board_map.tile_get(block.row, block.column);
-        if block.tile == tile {
-            continue;
-        }
-        block.tile = tile;
-        if let Some(tile) = tile {
-            *tile_image = tile_assets.get(tile);
-            *visibility = Visibility::Visible;
-        } else {
-            *visibility = Visibility::Hidden;
+    if let Some(addr) = arg_get(args, "--connect") {
+        // the server's match has no bot seats, so its flags would do nothing
+        if let Some(flag) = ["--bot", "--tbp", "--weights"]
+            .into_iter()
+            .find(|flag| args.iter().any(|arg| arg == flag))
+        {
+            return Err(format!("{flag} can't be used with --connect"));
         }
+        println!("waiting for an opponent on {addr}");
+        let (client, start) =
+            NetClient::connect(addr).map_err(|e| format!("failed to connect to {addr}: {e}"))?;
+        return Ok(TetrisPlugin {
+            ruleset: start.ruleset,
+            seed: start.seed,
+            players: 2,
+            best_of: Some(start.best_of),
+            record: arg_get(args, "--record").cloned(),
+            net: Some(client),
+            ..default()
+        });
     }
-}
-
-fn score_update(mut query: Query<&mut Text, With<ScoreText>>, board_map: Res<BoardMap>) {
-    let mut score_text = query.single_mut();
-    let score = board_map.score_get();
-    score_text.sections[0].value = format!("{0:<08}", score);
-}
-
-fn preview_update(
-    mut query: Query<(&mut TetrominoPreview, &mut Handle<Image>)>,
-    tetromino_supplier: Res<TetrominoSupplier>,
-    tetromino_assets: Res<TetrominoAssets>,
-) {
-    for (mut tetromino_preview, mut tetromino_image) in &mut query {
-        let tetromino = tetromino_supplier.get(tetromino_preview.index);
-        if tetromino_preview.tetromino == tetromino {
-            continue;
+    let versus = args.iter().any(|arg| arg == "--versus");
+    let best_of = arg_parse(args, "--best-of", 3)?;
+    let players = arg_parse(args, "--players", if versus { 2 } else { 1 })?;
+    let ruleset = Ruleset::args_parse(args)?;
+    let seed = arg_parse(args, "--seed", rand::random())?;
+    let boards = arg_get(args, "--bot").map(String::as_str);
+    let bot = match boards.or(arg_get(args, "--tbp").map(|_| "1")) {
+        Some(boards) => {
+            let players: Vec<u8> = boards
+                .split(',')
+                .map(|board| match board.parse::<u8>() {
+                    Ok(board @ 1..) => Ok(board - 1),
+                    _ => Err(format!("bad bot board {board}")),
+                })
+                .collect::<Result<_, _>>()?;
+            let pps = arg_parse(args, "--pps", bot::DEFAULT_PPS)?;
+            let weights = Weights::args_parse(args)?;
+            // last, the other flags are checked before any bot program runs
+            Some(BotSettings {
+                tbp: TbpBots::args_parse(args, &players)?,
+                players,
+                pps,
+                weights,
+            })
         }
-        tetromino_preview.tetromino = tetromino;
-        *tetromino_image = tetromino_assets.get(tetromino);
-    }
-}
-
-fn player_update(
-    time: Res<Time>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut board_map: ResMut<BoardMap>,
-    mut dropping_timer: ResMut<DroppingTimer>,
-) {
-    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
-        board_map.player_move(MoveDirection::Down);
-        dropping_timer.0.reset();
-    } else if dropping_timer.0.tick(time.delta()).just_finished() {
-        board_map.player_move(MoveDirection::Down);
-    }
-    if keyboard_input.just_pressed(KeyCode::ArrowLeft) {
-        board_map.player_move(MoveDirection::Left);
-    }
-    if keyboard_input.just_pressed(KeyCode::ArrowRight) {
-        board_map.player_move(MoveDirection::Right);
-    }
-    // if keyboard_input.just_pressed(KeyCode::ArrowUp) {
-    //     board_map.player_move(MoveDirection::Up);
-    // }
-    if keyboard_input.just_pressed(KeyCode::KeyQ) {
-        board_map.player_rotate(RotateDirection::Left);
-    }
-    if keyboard_input.just_pressed(KeyCode::KeyW) {
-        board_map.player_rotate(RotateDirection::Right);
-    }
-    if keyboard_input.just_pressed(KeyCode::Space) {
-        board_map.player_move_to_bottom();
-        dropping_timer.0.reset();
-    }
+        None => None,
+    };
+    Ok(TetrisPlugin {
+        ruleset,
+        seed,
+        players,
+        best_of: versus.then_some(best_of),
+        record: arg_get(args, "--record").cloned(),
+        bot,
+        ..default()
+    })
 }
 
-fn board_event_update(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut board_map: ResMut<BoardMap>,
-    mut tetromino_supplier: ResMut<TetrominoSupplier>,
-) {
-    if board_map.event_get(BoardEvent::LineCompleted) {
-        commands.spawn(AudioBundle {
-            source: asset_server.load::<AudioSource>("sounds/Line.wav"),
-            settings: PlaybackSettings {
-                mode: bevy::audio::PlaybackMode::Despawn,
-                volume: Volume::new(0.2),
-                ..default()
-            },
-            ..default()
-        });
-    }
-    if board_map.event_get(BoardEvent::TetrominoMoved) {
-        commands.spawn(AudioBundle {
-            source: asset_server.load::<AudioSource>("sounds/Move.wav"),
-            settings: PlaybackSettings {
-                mode: bevy::audio::PlaybackMode::Despawn,
-                volume: Volume::new(0.1),
-                ..default()
-            },
-            ..default()
-        });
-    }
-    if board_map.event_get(BoardEvent::TetrominoPut) {
-        commands.spawn(AudioBundle {
-            source: asset_server.load::<AudioSource>("sounds/Put.wav"),
-            settings: PlaybackSettings {
-                mode: bevy::audio::PlaybackMode::Despawn,
-                volume: Volume::new(0.2),
-                ..default()
-            },
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    // `--lobby` lists the games on the network instead of playing one
+    let lobby = args.iter().any(|arg| arg == "--lobby");
+    // before the window opens, `--connect` waits here for an opponent
+    let tetris = match (!lobby).then(|| tetris_parse(&args)).transpose() {
+        Ok(tetris) => tetris,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            // window resolution, scale
+            resolution: WindowResolution::new(1280., 720.).with_scale_factor_override(0.5),
             ..default()
-        });
-        let rng = &mut rand::thread_rng();
-        board_map.player_spawn(tetromino_supplier.pop(rng));
-    }
-    if board_map.event_get(BoardEvent::GameOver) {
-        info!("GameOver");
-    }
-    board_map.event_reset();
+        }),
+        ..default()
+    }));
+    match tetris {
+        Some(tetris) => app.add_plugins(tetris),
+        None => app.add_plugins(TetrisLobbyPlugin),
+    };
+    app.run();
 }
@@ -0,0 +1,315 @@
+use crate::bitboard::Bitboard;
+use crate::input::Action;
+use crate::resource::*;
+use std::collections::VecDeque;
+
+/// A final position of the falling piece, reachable from where it is now.
+#[derive(Clone, Debug)]
+pub struct Landing {
+    pub tetromino: Tetromino,
+    pub position: (i8, i8),
+    pub rotation: u8,
+    /// Board cells the piece covers as `(col, row)`, sorted.
+    pub cells: Vec<(i8, i8)>,
+    pub tspin: TSpin,
+    /// Shortest inputs that get the piece here, a soft drop moves one row and the last input is
+    /// a hard drop.
+    pub actions: Vec<Action>,
+    kick: Option<Kick>,
+}
+
+impl Landing {
+    /// Locks the piece of `board_map` here, like playing `actions` would.
+    pub fn put(&self, board_map: &mut BoardMap) {
+        let player = PlayerTetromino {
+            tetromion: self.tetromino,
+            position: self.position,
+            rotation: self.rotation,
+        };
+        board_map.player_put_at(player, self.kick);
+    }
+}
+
+const STEPS: [Action; 6] = [
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::SoftDrop,
+    Action::RotateRight,
+    Action::RotateLeft,
+    Action::Rotate180,
+];
+
+// positions the piece fits in by rotation and by the kick of the last rotation, which tells
+// T-spins apart, a move or a fall clears the kick. A T-spin only tells a kick that makes it full
+// from the others, so the rest share one state
+struct States {
+    reach: i8,
+    width: usize,
+    seen: Vec<bool>,
+}
+
+impl States {
+    fn new(board_map: &BoardMap, tetromino: Tetromino) -> Self {
+        // a piece in the board is at most this far out of it
+        let reach = (0..4)
+            .flat_map(|rotation| {
+                board_map.player_cells(&PlayerTetromino {
+                    tetromion: tetromino,
+                    position: (0, 0),
+                    rotation,
+                })
+            })
+            .map(|&(x, y)| x.abs().max(y.abs()))
+            .max()
+            .unwrap_or(0);
+        let width = 10 + 2 * reach as usize;
+        let height = 20 + 2 * reach as usize;
+        Self {
+            reach,
+            width,
+            seen: vec![false; width * height * 4 * 3],
+        }
+    }
+
+    /// False when the state was already there.
+    fn insert(
+        &mut self,
+        board_map: &BoardMap,
+        player: &PlayerTetromino,
+        kick: Option<Kick>,
+    ) -> bool {
+        let (x, y) = player.position;
+        let col = (x + self.reach) as usize;
+        let row = (y + self.reach) as usize;
+        let kick = match kick {
+            None => 0,
+            Some(kick) if board_map.kick_full(player, kick) => 2,
+            Some(_) => 1,
+        };
+        let index = ((row * self.width + col) * 4 + player.rotation as usize % 4) * 3 + kick;
+        !std::mem::replace(&mut self.seen[index], true)
+    }
+}
+
+type Node = (PlayerTetromino, Option<Kick>, Option<(usize, Action)>);
+
+/// Every distinct landing of the falling piece, found breadth first over moves, one row soft
+/// drops and kicked rotations with a hard drop from each state, so tucks and spins are
+/// included. Landings covering the same cells are only kept apart when they score a different
+/// T-spin.
+pub fn landings(board_map: &BoardMap) -> Vec<Landing> {
+    let Some(start) = board_map.player_get().cloned() else {
+        return Vec::new();
+    };
+    let mut board_map = board_map.clone();
+    board_map.player_erase();
+    let bitboard = Bitboard::new(&board_map.rows_get());
+    // every state reached, with the state and input it was reached from
+    let mut nodes: Vec<Node> = vec![(start.clone(), None, None)];
+    let mut seen = States::new(&board_map, start.tetromion);
+    seen.insert(&board_map, &start, None);
+    let mut dropped_seen = States::new(&board_map, start.tetromion);
+    let mut queue = VecDeque::from([0]);
+    let mut landings: Vec<Landing> = Vec::new();
+    while let Some(index) = queue.pop_front() {
+        let (player, kick, from) = nodes[index].clone();
+        // a hard drop from every state, it only keeps the kick when the piece does not fall.
+        // Soft dropped states land where the state above them does
+        if !matches!(from, Some((_, Action::SoftDrop))) {
+            let dropped = board_map.player_dropped(&bitboard, &player);
+            let kick = kick.filter(|_| dropped.position == player.position);
+            if dropped_seen.insert(&board_map, &dropped, kick) {
+                landing_add(&board_map, &mut landings, &nodes, index, dropped, kick);
+            }
+        }
+        for action in STEPS {
+            let next = match action {
+                Action::MoveLeft => moved(&board_map, &bitboard, &player, MoveDirection::Left),
+                Action::MoveRight => moved(&board_map, &bitboard, &player, MoveDirection::Right),
+                Action::SoftDrop => moved(&board_map, &bitboard, &player, MoveDirection::Down),
+                Action::RotateRight => {
+                    rotated(&board_map, &bitboard, &player, RotateDirection::Right)
+                }
+                Action::RotateLeft => {
+                    rotated(&board_map, &bitboard, &player, RotateDirection::Left)
+                }
+                _ => rotated(&board_map, &bitboard, &player, RotateDirection::Half),
+            };
+            let Some((next, next_kick)) = next else {
+                continue;
+            };
+            // the piece falls from a state that is not grounded, which drops the kick
+            let mut below = next.clone();
+            below.transform(MoveDirection::Down);
+            let next_kick = next_kick.filter(|_| !board_map.player_fits(&bitboard, &below));
+            if !seen.insert(&board_map, &next, next_kick) {
+                continue;
+            }
+            queue.push_back(nodes.len());
+            nodes.push((next, next_kick, Some((index, action))));
+        }
+    }
+    landings
+}
+
+fn rotated(
+    board_map: &BoardMap,
+    bitboard: &Bitboard,
+    player: &PlayerTetromino,
+    dir: RotateDirection,
+) -> Option<(PlayerTetromino, Option<Kick>)> {
+    board_map
+        .player_rotated(bitboard, player, dir)
+        .map(|(player, index)| (player, Some(Kick { dir, index })))
+}
+
+fn moved(
+    board_map: &BoardMap,
+    bitboard: &Bitboard,
+    player: &PlayerTetromino,
+    dir: MoveDirection,
+) -> Option<(PlayerTetromino, Option<Kick>)> {
+    let mut player = player.clone();
+    player.transform(dir);
+    board_map
+        .player_fits(bitboard, &player)
+        .then_some((player, None))
+}
+
+fn landing_add(
+    board_map: &BoardMap,
+    landings: &mut Vec<Landing>,
+    nodes: &[Node],
+    index: usize,
+    player: PlayerTetromino,
+    kick: Option<Kick>,
+) {
+    let tspin = board_map.tspin_get(&player, kick);
+    let (x, y) = player.position;
+    let mut cells: Vec<(i8, i8)> = board_map
+        .player_cells(&player)
+        .iter()
+        .map(|&(offset_x, offset_y)| (x + offset_x, y + offset_y))
+        .collect();
+    cells.sort();
+    // breadth first, so the landing found first has the shortest inputs
+    if landings
+        .iter()
+        .any(|landing| landing.cells == cells && landing.tspin == tspin)
+    {
+        return;
+    }
+    let mut actions = vec![Action::HardDrop];
+    let mut at = index;
+    while let Some((parent, action)) = nodes[at].2 {
+        actions.push(action);
+        at = parent;
+    }
+    actions.reverse();
+    landings.push(Landing {
+        tetromino: player.tetromion,
+        position: player.position,
+        rotation: player.rotation,
+        cells,
+        tspin,
+        actions,
+        kick,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::movegen::*;
+    use crate::ruleset::Ruleset;
+
+    // rows from the bottom up, `#` for a block
+    fn board_new(rows: &[&str], tetromino: Tetromino) -> BoardMap {
+        let mut board_map = BoardMap::new(&Ruleset::default());
+        for (row, text) in rows.iter().enumerate() {
+            for (col, cell) in text.chars().enumerate() {
+                if cell == '#' {
+                    board_map.tile_set(row as u8, col as u8, Some(Tetromino::X));
+                }
+            }
+        }
+        board_map.player_spawn(tetromino);
+        board_map
+    }
+
+    // plays the inputs the way the game does without lock delay
+    fn actions_play(board_map: &mut BoardMap, actions: &[Action]) {
+        for action in actions {
+            match action {
+                Action::MoveLeft => board_map.player_move(MoveDirection::Left),
+                Action::MoveRight => board_map.player_move(MoveDirection::Right),
+                Action::SoftDrop => board_map.player_move(MoveDirection::Down),
+                Action::RotateLeft => board_map.player_rotate(RotateDirection::Left),
+                Action::RotateRight => board_map.player_rotate(RotateDirection::Right),
+                Action::Rotate180 => board_map.player_rotate(RotateDirection::Half),
+                Action::HardDrop => {
+                    board_map.player_move_to_bottom();
+                    true
+                }
+                Action::Hold | Action::Pause => unreachable!("landings never hold or pause"),
+            };
+        }
+    }
+
+    const OVERHANG: [&str; 2] = ["..........", "######...."];
+    const TSD_SLOT: [&str; 3] = ["####.#####", "###...####", "####......"];
+
+    #[test]
+    fn tucks_under_an_overhang() {
+        let board_map = board_new(&OVERHANG, Tetromino::I);
+        let landings = landings(&board_map);
+        let tuck = landings
+            .iter()
+            .find(|landing| landing.cells == [(0, 0), (1, 0), (2, 0), (3, 0)])
+            .expect("no tuck");
+        let soft_drop = tuck.actions.iter().position(|&action| action == Action::SoftDrop);
+        let last_left = tuck.actions.iter().rposition(|&action| action == Action::MoveLeft);
+        assert!(soft_drop < last_left, "{:?}", tuck.actions);
+    }
+
+    #[test]
+    fn finds_the_tspin_double() {
+        let board_map = board_new(&TSD_SLOT, Tetromino::T);
+        let landings = landings(&board_map);
+        let tsd = landings
+            .iter()
+            .find(|landing| landing.cells == [(3, 1), (4, 0), (4, 1), (5, 1)])
+            .expect("no T-spin double");
+        assert_eq!(tsd.tspin, TSpin::Full);
+        let mut board_map = board_map.clone();
+        tsd.put(&mut board_map);
+        let clear = board_map.clear_get().expect("no clear");
+        assert_eq!((clear.lines, clear.tspin), (2, TSpin::Full));
+    }
+
+    #[test]
+    fn landings_are_reached_by_their_actions_once() {
+        for (rows, tetromino) in [
+            (&OVERHANG[..], Tetromino::I),
+            (&TSD_SLOT[..], Tetromino::T),
+            (&TSD_SLOT[..], Tetromino::L),
+            (&[][..], Tetromino::S),
+        ] {
+            let board_map = board_new(rows, tetromino);
+            let landings = landings(&board_map);
+            assert!(!landings.is_empty());
+            for (i, landing) in landings.iter().enumerate() {
+                let mut played = board_map.clone();
+                actions_play(&mut played, &landing.actions);
+                let mut put = board_map.clone();
+                landing.put(&mut put);
+                assert_eq!(played.rows_get(), put.rows_get(), "{:?}", landing.actions);
+                let tspin = played.clear_get().map(|clear| clear.tspin);
+                assert_eq!(tspin, Some(landing.tspin), "{:?}", landing.actions);
+                let twice = landings[..i]
+                    .iter()
+                    .any(|other| other.cells == landing.cells && other.tspin == landing.tspin);
+                assert!(!twice, "{:?} twice", landing.cells);
+            }
+        }
+    }
+}
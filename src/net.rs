@@ -0,0 +1,687 @@
+use crate::checksum::{replay_checksum, state_checksum, state_dump};
+use crate::game::{simulation_running, tick_update, Confirmed, Stall};
+use crate::input::*;
+use crate::replay::{Replay, ReplayMode};
+use crate::rollback::*;
+use crate::ruleset::Ruleset;
+use crate::versus::{garbage_setup, round_end, Match, RoundReady, RoundStart};
+use crate::{Screen, TetrisSet};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+/// Input delay the server hands out unless told otherwise.
+pub const DEFAULT_INPUT_DELAY: u64 = 3;
+/// Ticks a client may run ahead of the opponent's inputs unless told otherwise.
+pub const DEFAULT_ROLLBACK_WINDOW: u64 = 8;
+// ticks of checksums kept to compare with the opponent's
+const CHECKSUM_HISTORY: u64 = 600;
+
+/// Sent by the server to both clients once they are paired.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MatchStart {
+    /// Board driven by this client, the other one is the opponent's.
+    pub player: u8,
+    pub seed: u64,
+    pub best_of: u8,
+    pub input_delay: u64,
+    /// Ticks played on predicted inputs at most, 0 waits for every input.
+    pub rollback_window: u64,
+    pub ruleset: Ruleset,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// Every action of the sender's board for one tick, sent for every tick even when empty so
+    /// the peer knows the tick is final.
+    Inputs { tick: u64, actions: Vec<Action> },
+    /// The round ended on this tick, `topped_out` for each board as the sender ran them.
+    RoundEnd { tick: u64, topped_out: Vec<bool> },
+    /// Enter or Start pressed on the result screen.
+    Ready,
+    /// State checksums of the confirmed ticks from `tick` on.
+    Checksums {
+        round: u64,
+        tick: u64,
+        checksums: Vec<u64>,
+    },
+    /// The sender's state on the first tick its checksum differed.
+    Dump { tick: u64, text: String },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ServerMessage {
+    Start(MatchStart),
+    /// The opponent's inputs for one tick.
+    Inputs {
+        tick: u64,
+        actions: Vec<Action>,
+    },
+    /// The server's call on the round, `None` for a draw.
+    RoundOver {
+        winner: Option<u8>,
+        wins: Vec<u8>,
+    },
+    /// Both players are ready for the next round.
+    Round {
+        round: u64,
+    },
+    /// The opponent's checksums of the confirmed ticks from `tick` on.
+    Checksums {
+        round: u64,
+        tick: u64,
+        checksums: Vec<u64>,
+    },
+    /// The opponent's state on the first tick the checksums differed.
+    Dump {
+        tick: u64,
+        text: String,
+    },
+    OpponentLeft,
+}
+
+/// Writes one message as a line of RON.
+pub fn message_send<T: Serialize>(mut writer: impl Write, message: &T) -> Result<(), String> {
+    let mut line = ron::to_string(message).map_err(|e| e.to_string())?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Reads one message, `Ok(None)` when the connection is closed.
+pub fn message_read<T: DeserializeOwned>(reader: &mut impl BufRead) -> Result<Option<T>, String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+        return Ok(None);
+    }
+    ron::from_str(&line).map(Some).map_err(|e| e.to_string())
+}
+
+/// A round decided by the `Referee`.
+pub struct RoundReport {
+    pub round: u64,
+    pub winner: Option<u8>,
+    pub wins: Vec<u8>,
+    /// The tick each client says the round ended on, the same unless they desynced.
+    pub ticks: [u64; 2],
+    /// The clients disagreed on how the round ended or on a checksum, it counts as a draw.
+    pub disputed: bool,
+}
+
+#[derive(Clone, PartialEq)]
+struct RoundEnd {
+    tick: u64,
+    topped_out: Vec<bool>,
+}
+
+/// The server side of a match: relays inputs, decides each round from what both clients report
+/// about both boards and starts the next one once both are ready. Both clients run both boards,
+/// so a round only counts when their reports and checksums agree.
+pub struct Referee {
+    best_of: u8,
+    wins: Vec<u8>,
+    round: u64,
+    round_ends: [Option<RoundEnd>; 2],
+    // checksums of the round one client sent and the other has not yet, by tick
+    checksums: [BTreeMap<u64, u64>; 2],
+    // first tick of the round the clients' checksums differed on
+    mismatch: Option<u64>,
+    ready: [bool; 2],
+    report: Option<RoundReport>,
+}
+
+impl Referee {
+    pub fn new(best_of: u8) -> Self {
+        Self {
+            best_of,
+            wins: vec![0; 2],
+            round: 0,
+            round_ends: [None, None],
+            checksums: [BTreeMap::new(), BTreeMap::new()],
+            mismatch: None,
+            ready: [false; 2],
+            report: None,
+        }
+    }
+
+    /// Answers a message from `player` with messages for either player.
+    pub fn message_handle(
+        &mut self,
+        player: usize,
+        message: ClientMessage,
+    ) -> Vec<(usize, ServerMessage)> {
+        let other = 1 - player;
+        match message {
+            ClientMessage::Inputs { tick, actions } => {
+                vec![(other, ServerMessage::Inputs { tick, actions })]
+            }
+            ClientMessage::RoundEnd { tick, topped_out } => {
+                self.round_ends[player] = Some(RoundEnd { tick, topped_out });
+                let [Some(first), Some(second)] = &self.round_ends else {
+                    return Vec::new();
+                };
+                let disputed = first != second || self.mismatch.is_some();
+                // a board standing against a topped out one wins, anything else is a draw
+                let winner = match first.topped_out.as_slice() {
+                    _ if disputed => None,
+                    [false, true] => Some(0),
+                    [true, false] => Some(1),
+                    _ => None,
+                };
+                if let Some(winner) = winner {
+                    self.wins[winner as usize] += 1;
+                }
+                self.report = Some(RoundReport {
+                    round: self.round,
+                    winner,
+                    wins: self.wins.clone(),
+                    ticks: [first.tick, second.tick],
+                    disputed,
+                });
+                (0..2)
+                    .map(|player| {
+                        let wins = self.wins.clone();
+                        (player, ServerMessage::RoundOver { winner, wins })
+                    })
+                    .collect()
+            }
+            ClientMessage::Ready => {
+                self.ready[player] = true;
+                if self.ready != [true; 2] || self.round_ends.iter().any(Option::is_none) {
+                    return Vec::new();
+                }
+                if self.wins.iter().any(|&wins| wins > self.best_of / 2) {
+                    self.wins.fill(0);
+                }
+                self.round += 1;
+                self.round_ends = [None, None];
+                self.checksums = [BTreeMap::new(), BTreeMap::new()];
+                self.mismatch = None;
+                self.ready = [false; 2];
+                let round = self.round;
+                (0..2)
+                    .map(|player| (player, ServerMessage::Round { round }))
+                    .collect()
+            }
+            ClientMessage::Checksums {
+                round,
+                tick,
+                checksums,
+            } => {
+                if round == self.round {
+                    self.checksums_compare(player, tick, &checksums);
+                }
+                let message = ServerMessage::Checksums {
+                    round,
+                    tick,
+                    checksums,
+                };
+                vec![(other, message)]
+            }
+            ClientMessage::Dump { tick, text } => {
+                vec![(other, ServerMessage::Dump { tick, text })]
+            }
+        }
+    }
+
+    fn checksums_compare(&mut self, player: usize, tick: u64, checksums: &[u64]) {
+        for (tick, &checksum) in (tick..).zip(checksums) {
+            match self.checksums[1 - player].remove(&tick) {
+                Some(other) if other != checksum => {
+                    self.mismatch = Some(self.mismatch.map_or(tick, |first| first.min(tick)));
+                }
+                Some(_) => (),
+                None => {
+                    self.checksums[player].insert(tick, checksum);
+                }
+            }
+        }
+    }
+
+    /// The round decided by the last message, if any.
+    pub fn report_take(&mut self) -> Option<RoundReport> {
+        self.report.take()
+    }
+}
+
+/// Connection to a `tetris_server` match. Both clients run both boards and garbage follows from
+/// the inputs alone, so inputs are all that is exchanged.
+#[derive(Resource, Clone)]
+pub struct NetClient {
+    outgoing: Sender<ClientMessage>,
+    incoming: Arc<Mutex<Receiver<ServerMessage>>>,
+    pub player: u8,
+    pub input_delay: u64,
+    pub rollback_window: u64,
+    closed: bool,
+}
+
+impl NetClient {
+    /// Connects and waits until the server pairs us with an opponent.
+    pub fn connect(addr: &str) -> Result<(Self, MatchStart), String> {
+        let stream = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+        let start = match message_read(&mut reader)? {
+            Some(ServerMessage::Start(start)) => start,
+            Some(_) => return Err("expected the match start".to_string()),
+            None => return Err("connection closed".to_string()),
+        };
+        let (incoming_sender, incoming) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            match message_read(&mut reader) {
+                Ok(Some(message)) => {
+                    if incoming_sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) | Err(_) => {
+                    let _ = incoming_sender.send(ServerMessage::OpponentLeft);
+                    break;
+                }
+            }
+        });
+        let (outgoing, outgoing_receiver) = mpsc::channel::<ClientMessage>();
+        std::thread::spawn(move || {
+            for message in outgoing_receiver {
+                if let Err(e) = message_send(&stream, &message) {
+                    warn!("failed to send to the server: {e}");
+                    break;
+                }
+            }
+        });
+        Ok((Self::from_channels(&start, outgoing, incoming), start))
+    }
+
+    /// A client talking through channels instead of a socket, e.g. to simulate a network.
+    pub fn from_channels(
+        start: &MatchStart,
+        outgoing: Sender<ClientMessage>,
+        incoming: Receiver<ServerMessage>,
+    ) -> Self {
+        Self {
+            outgoing,
+            incoming: Arc::new(Mutex::new(incoming)),
+            player: start.player,
+            input_delay: start.input_delay,
+            rollback_window: start.rollback_window,
+            closed: false,
+        }
+    }
+
+    fn send(&self, message: ClientMessage) {
+        if !self.closed {
+            let _ = self.outgoing.send(message);
+        }
+    }
+}
+
+/// Online versus against a `tetris_server` opponent. Add it with `TetrisVersusPlugin`.
+pub struct TetrisNetPlugin {
+    pub client: NetClient,
+}
+
+impl Plugin for TetrisNetPlugin {
+    fn build(&self, app: &mut App) {
+        let client = &self.client;
+        app.insert_resource(client.clone())
+            .insert_resource(Rollback::new(client.player, client.rollback_window))
+            .init_resource::<ChecksumLog>()
+            .init_resource::<RollbackStats>()
+            .insert_resource(Confirmed(client.input_delay))
+            .insert_resource(LocalPlayers(vec![client.player]))
+            .insert_resource(InputDelay(client.input_delay))
+            .add_systems(
+                FixedUpdate,
+                (
+                    net_receive,
+                    rollback_apply,
+                    late_action_record,
+                    snapshot_save.after(garbage_setup),
+                    checksum_exchange,
+                    stall_update,
+                )
+                    .chain()
+                    .in_set(TetrisSet::Input)
+                    .before(round_end)
+                    .before(tick_update),
+            )
+            .add_systems(
+                FixedUpdate,
+                remote_action_send
+                    .in_set(TetrisSet::Input)
+                    .after(tick_update)
+                    .run_if(in_state(Screen::Game))
+                    .run_if(simulation_running),
+            )
+            .add_systems(FixedUpdate, local_action_send.in_set(TetrisSet::Simulate))
+            .add_systems(OnEnter(Screen::Result), round_end_send)
+            .add_systems(
+                Update,
+                (round_ready_send, net_round_reset).run_if(in_state(Screen::Result)),
+            );
+    }
+}
+
+/// Checksums of this round's confirmed ticks, checked against the ones the opponent sends.
+#[derive(Resource, Default)]
+pub struct ChecksumLog {
+    round: u64,
+    // last tick checksummed
+    checked: Option<u64>,
+    local: BTreeMap<u64, (u64, Snapshot)>,
+    // by round and tick, the opponent may already be in the next round
+    remote: BTreeMap<(u64, u64), u64>,
+    /// Ticks compared with the opponent so far.
+    pub compared: u64,
+    /// First tick the clients disagreed on.
+    pub desync: Option<u64>,
+}
+
+impl ChecksumLog {
+    fn round_next(&mut self) {
+        self.round += 1;
+        self.checked = None;
+        self.local.clear();
+        let round = self.round;
+        self.remote
+            .retain(|&(remote_round, _), _| remote_round >= round);
+    }
+}
+
+// what a tick depends on that changes when late inputs arrive
+#[derive(SystemParam)]
+struct NetSync<'w> {
+    confirmed: ResMut<'w, Confirmed>,
+    rollback: ResMut<'w, Rollback>,
+    checksum_log: ResMut<'w, ChecksumLog>,
+}
+
+fn net_receive(
+    mut client: ResMut<NetClient>,
+    tick: Res<Tick>,
+    mut sync: NetSync,
+    mut versus_match: ResMut<Match>,
+    mut round_start: EventWriter<RoundStart>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let NetSync {
+        confirmed,
+        rollback,
+        checksum_log,
+    } = &mut sync;
+    let messages: Vec<ServerMessage> = client.incoming.lock().unwrap().try_iter().collect();
+    for message in messages {
+        match message {
+            ServerMessage::Inputs {
+                tick: input_tick,
+                actions,
+            } => {
+                confirmed.0 = input_tick;
+                // already played on the prediction that the opponent did nothing
+                if input_tick <= tick.0 && !actions.is_empty() {
+                    rollback.from = Some(
+                        rollback
+                            .from
+                            .map_or(input_tick, |from| from.min(input_tick)),
+                    );
+                    let player = 1 - client.player;
+                    rollback
+                        .late
+                        .extend(actions.iter().map(|&action| GameAction {
+                            tick: input_tick,
+                            player,
+                            action,
+                        }));
+                }
+                rollback.remote.insert(input_tick, actions);
+            }
+            ServerMessage::RoundOver { winner, wins } => {
+                if wins != versus_match.wins {
+                    warn!(
+                        "desync: server has wins {wins:?}, local {:?}",
+                        versus_match.wins
+                    );
+                }
+                versus_match.wins = wins;
+                versus_match.round_winner = winner;
+            }
+            ServerMessage::Round { round } => {
+                info!("round {round}");
+                round_start.send(RoundStart);
+            }
+            ServerMessage::OpponentLeft => {
+                warn!("opponent left");
+                client.closed = true;
+                let player = client.player as usize;
+                versus_match.wins[player] = versus_match.best_of / 2 + 1;
+                versus_match.round_winner = Some(client.player);
+                versus_match.over = true;
+                next_screen.set(Screen::Result);
+            }
+            ServerMessage::Checksums {
+                round,
+                tick,
+                checksums,
+            } => {
+                for (i, checksum) in checksums.into_iter().enumerate() {
+                    checksum_log
+                        .remote
+                        .insert((round, tick + i as u64), checksum);
+                }
+            }
+            ServerMessage::Dump { tick, text } => {
+                error!("opponent state at tick {tick}:\n{text}");
+            }
+            ServerMessage::Start(_) => (),
+        }
+    }
+}
+
+// checksums every tick once it is confirmed, as it can no longer roll back
+fn checksum_exchange(
+    client: Res<NetClient>,
+    confirmed: Res<Confirmed>,
+    rollback: Res<Rollback>,
+    mut checksum_log: ResMut<ChecksumLog>,
+    replay_mode: Res<ReplayMode>,
+    mut replay: ResMut<Replay>,
+) {
+    let log = &mut *checksum_log;
+    let mut first = None;
+    let mut checksums = Vec::new();
+    for snapshot in rollback.snapshots() {
+        let tick = snapshot.tick();
+        if tick > confirmed.0 || log.checked.is_some_and(|checked| tick <= checked) {
+            continue;
+        }
+        let views = snapshot.views();
+        let checksum = state_checksum(tick, &views);
+        replay_checksum(tick, checksum, &replay_mode, &mut replay, || {
+            state_dump(tick, &views)
+        });
+        first.get_or_insert(tick);
+        checksums.push(checksum);
+        log.checked = Some(tick);
+        log.local.insert(tick, (checksum, snapshot.clone()));
+    }
+    if let Some(tick) = first {
+        client.send(ClientMessage::Checksums {
+            round: log.round,
+            tick,
+            checksums,
+        });
+    }
+    // both sides check the ticks in order, so they report the same first one
+    let ticks: Vec<u64> = log
+        .remote
+        .range((log.round, 0)..(log.round + 1, 0))
+        .map(|(&(_, tick), _)| tick)
+        .filter(|tick| log.local.contains_key(tick))
+        .collect();
+    for tick in ticks {
+        let remote = log.remote.remove(&(log.round, tick)).unwrap_or_default();
+        let (local, snapshot) = &log.local[&tick];
+        log.compared += 1;
+        if *local != remote && log.desync.is_none() {
+            log.desync = Some(tick);
+            let text = state_dump(tick, &snapshot.views());
+            error!("desync at tick {tick}: local {local:016x}, opponent {remote:016x}\n{text}");
+            client.send(ClientMessage::Dump { tick, text });
+        }
+    }
+    // the opponent's checksums come about a round trip later
+    if let Some(checked) = log.checked {
+        let oldest = checked.saturating_sub(CHECKSUM_HISTORY);
+        log.local.retain(|&tick, _| tick > oldest);
+    }
+}
+
+// the game holds while it is a whole window ahead of the opponent's inputs
+fn stall_update(
+    client: Res<NetClient>,
+    tick: Res<Tick>,
+    confirmed: Res<Confirmed>,
+    rollback: Res<Rollback>,
+    screen: Res<State<Screen>>,
+    mut stall: ResMut<Stall>,
+    mut stats: ResMut<RollbackStats>,
+) {
+    stall.0 =
+        *screen.get() == Screen::Game && !client.closed && tick.0 >= confirmed.0 + rollback.window;
+    if stall.0 {
+        stats.stalled_ticks += 1;
+    }
+}
+
+// opponent inputs that arrive in time go through `action_apply` like any other
+fn remote_action_send(
+    client: Res<NetClient>,
+    tick: Res<Tick>,
+    rollback: Res<Rollback>,
+    mut game_actions: EventWriter<GameAction>,
+) {
+    let Some(actions) = rollback.remote.get(&tick.0) else {
+        return;
+    };
+    for &action in actions {
+        game_actions.send(GameAction {
+            tick: tick.0,
+            player: 1 - client.player,
+            action,
+        });
+    }
+}
+
+// every simulated tick sends the local actions stamped for `tick + input_delay`
+fn local_action_send(
+    client: Res<NetClient>,
+    tick: Res<Tick>,
+    mut rollback: ResMut<Rollback>,
+    mut game_actions: EventReader<GameAction>,
+) {
+    let sent_tick = tick.0 + client.input_delay;
+    let actions: Vec<Action> = game_actions
+        .read()
+        .filter(|game_action| game_action.player == client.player && game_action.tick == sent_tick)
+        .map(|game_action| game_action.action)
+        .collect();
+    rollback.local.insert(sent_tick, actions.clone());
+    client.send(ClientMessage::Inputs {
+        tick: sent_tick,
+        actions,
+    });
+}
+
+fn round_end_send(client: Res<NetClient>, tick: Res<Tick>, versus_match: Res<Match>) {
+    client.send(ClientMessage::RoundEnd {
+        tick: tick.0,
+        topped_out: versus_match.alive.iter().map(|alive| !alive).collect(),
+    });
+}
+
+fn round_ready_send(client: Res<NetClient>, mut round_ready: EventReader<RoundReady>) {
+    if round_ready.read().count() > 0 {
+        client.send(ClientMessage::Ready);
+    }
+}
+
+// both clients start the next round from the tick the last one ended on
+fn net_round_reset(
+    client: Res<NetClient>,
+    tick: Res<Tick>,
+    mut round_start: EventReader<RoundStart>,
+    mut confirmed: ResMut<Confirmed>,
+    mut rollback: ResMut<Rollback>,
+    mut checksum_log: ResMut<ChecksumLog>,
+) {
+    if round_start.read().count() > 0 {
+        rollback.reset();
+        checksum_log.round_next();
+        confirmed.0 = tick.0 + client.input_delay;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_end(referee: &mut Referee, player: usize, topped_out: [bool; 2]) {
+        let message = ClientMessage::RoundEnd {
+            tick: 100,
+            topped_out: topped_out.to_vec(),
+        };
+        referee.message_handle(player, message);
+    }
+
+    fn checksums(referee: &mut Referee, player: usize, checksums: Vec<u64>) {
+        let message = ClientMessage::Checksums {
+            round: 0,
+            tick: 10,
+            checksums,
+        };
+        referee.message_handle(player, message);
+    }
+
+    #[test]
+    fn agreeing_reports_decide_the_round() {
+        let mut referee = Referee::new(3);
+        checksums(&mut referee, 0, vec![1, 2, 3]);
+        checksums(&mut referee, 1, vec![1, 2, 3]);
+        round_end(&mut referee, 0, [false, true]);
+        round_end(&mut referee, 1, [false, true]);
+        let report = referee.report_take().expect("report");
+        assert_eq!(report.winner, Some(0));
+        assert!(!report.disputed);
+    }
+
+    #[test]
+    fn disagreeing_reports_are_a_draw() {
+        let mut referee = Referee::new(3);
+        // the second client claims its opponent topped out
+        round_end(&mut referee, 0, [false, true]);
+        round_end(&mut referee, 1, [true, false]);
+        let report = referee.report_take().expect("report");
+        assert_eq!(report.winner, None);
+        assert!(report.disputed);
+        assert_eq!(report.wins, [0, 0]);
+    }
+
+    #[test]
+    fn differing_checksums_are_a_draw() {
+        let mut referee = Referee::new(3);
+        checksums(&mut referee, 0, vec![1, 2, 3]);
+        checksums(&mut referee, 1, vec![1, 5, 3]);
+        round_end(&mut referee, 0, [true, false]);
+        round_end(&mut referee, 1, [true, false]);
+        let report = referee.report_take().expect("report");
+        assert_eq!(report.winner, None);
+        assert!(report.disputed);
+    }
+}
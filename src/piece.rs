@@ -0,0 +1,120 @@
+use crate::bitboard::{CollisionMasks, MAX_HEIGHT};
+use crate::resource::Tetromino;
+use crate::rotation::*;
+use crate::ruleset::RotationKind;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum KickTable {
+    None,
+    Srs,
+    SrsI,
+    Ars,
+    /// Offsets tried for each `(from, to)` rotation, missing pairs can't rotate.
+    Custom(HashMap<(u8, u8), Vec<(i8, i8)>>),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PieceDef {
+    pub name: String,
+    pub tile: String,
+    /// Cells for each orientation, repeated when there are fewer than 4.
+    pub rotations: Vec<Vec<(i8, i8)>>,
+    #[serde(default)]
+    pub spawn: (i8, i8),
+    pub kicks: KickTable,
+}
+
+/// Piece definitions loaded from a RON file, see `assets/pieces`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PieceSet {
+    pub pieces: Vec<PieceDef>,
+    #[serde(skip)]
+    collision_masks: OnceLock<CollisionMasks>,
+}
+
+impl PieceSet {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let piece_set: PieceSet = ron::from_str(&text).map_err(|e| e.to_string())?;
+        if piece_set.pieces.is_empty() || piece_set.pieces.len() >= Tetromino::G.0 as usize {
+            return Err(format!("{} pieces", piece_set.pieces.len()));
+        }
+        for piece in &piece_set.pieces {
+            if piece.rotations.is_empty() || piece.rotations.iter().any(Vec::is_empty) {
+                return Err(format!("{} has no cells", piece.name));
+            }
+            let tall = piece.rotations.iter().any(|cells| {
+                let rows = cells.iter().map(|&(_, y)| y);
+                rows.clone().max().unwrap_or(0) - rows.min().unwrap_or(0) >= MAX_HEIGHT as i8
+            });
+            if tall {
+                return Err(format!("{} is taller than {MAX_HEIGHT} rows", piece.name));
+            }
+        }
+        Ok(piece_set)
+    }
+
+    pub fn collision_masks(&self) -> &CollisionMasks {
+        self.collision_masks
+            .get_or_init(|| CollisionMasks::new(self))
+    }
+}
+
+/// A piece set file, written as its path and loaded when it is read, so every ruleset that
+/// comes from a file, a replay or the network has its pieces.
+#[derive(Clone)]
+pub struct PieceSetFile {
+    pub path: String,
+    pub piece_set: Arc<PieceSet>,
+}
+
+impl PieceSetFile {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let piece_set = PieceSet::load(path).map_err(|e| format!("{path}: {e}"))?;
+        Ok(Self {
+            path: path.to_string(),
+            piece_set: Arc::new(piece_set),
+        })
+    }
+}
+
+impl Serialize for PieceSetFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.path)
+    }
+}
+
+impl<'de> Deserialize<'de> for PieceSetFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let path = String::deserialize(deserializer)?;
+        Self::load(&path).map_err(serde::de::Error::custom)
+    }
+}
+
+impl RotationSystem for PieceSet {
+    fn cells(&self, tetromino: Tetromino, rotation: u8) -> &[(i8, i8)] {
+        let rotations = &self.pieces[tetromino.0 as usize].rotations;
+        &rotations[rotation as usize % rotations.len()]
+    }
+
+    fn kicks(&self, tetromino: Tetromino, from: u8, to: u8) -> &[(i8, i8)] {
+        match &self.pieces[tetromino.0 as usize].kicks {
+            KickTable::None => RotationKind::Classic.system().kicks(Tetromino::T, from, to),
+            KickTable::Srs => RotationKind::Srs.system().kicks(Tetromino::T, from, to),
+            KickTable::SrsI => RotationKind::Srs.system().kicks(Tetromino::I, from, to),
+            KickTable::Ars => RotationKind::Ars.system().kicks(Tetromino::T, from, to),
+            KickTable::Custom(map) => map.get(&(from, to)).map_or(&[], Vec::as_slice),
+        }
+    }
+
+    fn piece_count(&self) -> u8 {
+        self.pieces.len() as u8
+    }
+
+    fn spawn_offset(&self, tetromino: Tetromino) -> (i8, i8) {
+        self.pieces[tetromino.0 as usize].spawn
+    }
+}
@@ -0,0 +1,435 @@
+use crate::game::Player;
+use crate::input::*;
+use crate::resource::*;
+use crate::ruleset::*;
+use crate::settings::*;
+use crate::{Screen, TetrisSet};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+pub(crate) const TILE_SIZE: f32 = 64.;
+pub(crate) const BOARD_WIDTH: f32 = 10. * 64.;
+pub(crate) const BOARD_HEIGHT: f32 = 20. * 64.;
+const GARBAGE_COLOR: Color = Color::srgb(0.5, 0.5, 0.5);
+// distance between the centers of boards next to each other, leaves room for hold and previews
+const BOARD_SPACING: f32 = BOARD_WIDTH * 2.;
+
+// the render entities below are all children of a board entity
+#[derive(Component)]
+struct Block {
+    row: u8,
+    column: u8,
+    tile: Option<Tetromino>,
+}
+
+#[derive(Component)]
+struct TetrominoPreview {
+    index: usize,
+    tetromino: Option<Tetromino>,
+}
+
+#[derive(Component)]
+struct HoldPreview {
+    tetromino: Option<Tetromino>,
+}
+
+#[derive(Component)]
+struct PreviewBlock;
+
+#[derive(Component)]
+struct ScoreText;
+
+#[derive(Component)]
+struct GuideText;
+
+/// Board, previews, score and guide sprites, synced from the simulation every frame.
+pub struct TetrisRenderPlugin;
+
+impl Plugin for TetrisRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileAssets>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (
+                    board_render_setup,
+                    (
+                        board_update,
+                        score_update,
+                        preview_update,
+                        hold_update,
+                        guide_update,
+                    ),
+                )
+                    .chain()
+                    .in_set(TetrisSet::Render),
+            )
+            .add_systems(OnEnter(Screen::Paused), paused_setup);
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut tile_assets: ResMut<TileAssets>,
+    asset_server: Res<AssetServer>,
+    ruleset: Res<Ruleset>,
+) {
+    tile_assets.setup(&asset_server, &ruleset);
+
+    // camera setup
+    commands.spawn(Camera2dBundle::default());
+
+    // guide text
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 64.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(15.0),
+            ..default()
+        }),
+        GuideText,
+    ));
+}
+
+// gives every new board its sprites and lays the boards out left to right by player
+fn board_render_setup(
+    mut commands: Commands,
+    new_boards: Query<Entity, Added<BoardMap>>,
+    mut boards: Query<(&Player, &mut Transform), With<BoardMap>>,
+    tile_assets: Res<TileAssets>,
+    asset_server: Res<AssetServer>,
+    ruleset: Res<Ruleset>,
+) {
+    if new_boards.is_empty() {
+        return;
+    }
+    let board_count = boards.iter().count();
+    for (player, mut transform) in &mut boards {
+        let offset = player.0 as f32 - (board_count - 1) as f32 * 0.5;
+        transform.translation.x = offset * BOARD_SPACING;
+    }
+
+    let preview_count = ruleset.preview_count.max(1);
+    let rotation_system = ruleset.rotation_system();
+    let preview_block_count = (0..rotation_system.piece_count())
+        .map(|idx| rotation_system.cells(Tetromino(idx), 0).len())
+        .max()
+        .unwrap_or(0);
+    for board in &new_boards {
+        commands.entity(board).with_children(|parent| {
+            // board setup
+            parent.spawn(SpriteBundle {
+                texture: asset_server.load("Board.png"),
+                ..default()
+            });
+
+            // block setup
+            for y in 0..20 {
+                for x in 0..10 {
+                    parent.spawn((
+                        SpriteBundle {
+                            texture: tile_assets.get(Tetromino::G),
+                            transform: Transform::from_xyz(
+                                x as f32 * TILE_SIZE - BOARD_WIDTH * 0.5 + TILE_SIZE * 0.5,
+                                y as f32 * TILE_SIZE - BOARD_HEIGHT * 0.5 + TILE_SIZE * 0.5,
+                                1.,
+                            ),
+                            visibility: Visibility::Hidden,
+                            ..default()
+                        },
+                        Block {
+                            row: y,
+                            column: x,
+                            tile: None,
+                        },
+                    ));
+                }
+            }
+
+            // score text, under the hold preview
+            parent.spawn((
+                Text2dBundle {
+                    text: Text::from_section(
+                        "00000000",
+                        TextStyle {
+                            font_size: 96.0,
+                            ..default()
+                        },
+                    ),
+                    transform: Transform::from_xyz(
+                        -BOARD_WIDTH * 0.5 - 256.0,
+                        -BOARD_HEIGHT * 0.5 + 64.0,
+                        1.,
+                    ),
+                    ..default()
+                },
+                ScoreText,
+            ));
+
+            // preview bg
+            parent.spawn((
+                SpriteBundle {
+                    texture: asset_server.load("blocks/Ghost.png"),
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(192.0, 128.0 * preview_count as f32)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(
+                        BOARD_WIDTH * 0.5 + 256.0,
+                        BOARD_HEIGHT * 0.5 - 192.0 - 64.0 * preview_count as f32,
+                        0.,
+                    ),
+                    ..default()
+                },
+                ImageScaleMode::Sliced(TextureSlicer {
+                    border: BorderRect::square(10.),
+                    center_scale_mode: SliceScaleMode::Tile { stretch_value: 0.5 },
+                    sides_scale_mode: SliceScaleMode::Tile { stretch_value: 0.5 },
+                    ..default()
+                }),
+            ));
+
+            // next tetrominoe preview
+            for i in 0..preview_count {
+                parent
+                    .spawn((
+                        SpatialBundle::from_transform(
+                            Transform::from_xyz(
+                                BOARD_WIDTH * 0.5 + 256.0,
+                                BOARD_HEIGHT * 0.5 - 256.0 - 128.0 * (i as f32),
+                                1.,
+                            )
+                            .with_scale(Vec3::splat(0.5)),
+                        ),
+                        TetrominoPreview {
+                            index: i,
+                            tetromino: None,
+                        },
+                    ))
+                    .with_children(|parent| preview_blocks_spawn(parent, preview_block_count));
+            }
+
+            // hold preview
+            if ruleset.hold {
+                parent
+                    .spawn((
+                        SpatialBundle::from_transform(
+                            Transform::from_xyz(
+                                -BOARD_WIDTH * 0.5 - 256.0,
+                                BOARD_HEIGHT * 0.5 - 256.0,
+                                1.,
+                            )
+                            .with_scale(Vec3::splat(0.5)),
+                        ),
+                        HoldPreview { tetromino: None },
+                    ))
+                    .with_children(|parent| preview_blocks_spawn(parent, preview_block_count));
+            }
+        });
+    }
+}
+
+fn preview_blocks_spawn(parent: &mut ChildBuilder, count: usize) {
+    for _ in 0..count {
+        parent.spawn((
+            SpriteBundle {
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            PreviewBlock,
+        ));
+    }
+}
+
+// lay the preview blocks out as the spawn orientation, centered on the parent
+fn preview_blocks_set(
+    children: &Children,
+    blocks: &mut Query<(&mut Transform, &mut Handle<Image>, &mut Visibility), With<PreviewBlock>>,
+    tetromino: Option<Tetromino>,
+    ruleset: &Ruleset,
+    tile_assets: &TileAssets,
+) {
+    let cells = match tetromino {
+        Some(tetromino) => ruleset.rotation_system().cells(tetromino, 0),
+        None => &[],
+    };
+    let (min_x, max_x, min_y, max_y) = cells.iter().fold(
+        (i8::MAX, i8::MIN, i8::MAX, i8::MIN),
+        |(min_x, max_x, min_y, max_y), &(x, y)| {
+            (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+        },
+    );
+    let center_x = (min_x as f32 + max_x as f32) * 0.5;
+    let center_y = (min_y as f32 + max_y as f32) * 0.5;
+    for (i, &child) in children.iter().enumerate() {
+        let Ok((mut transform, mut tile_image, mut visibility)) = blocks.get_mut(child) else {
+            continue;
+        };
+        if let (Some(&(x, y)), Some(tetromino)) = (cells.get(i), tetromino) {
+            transform.translation = Vec3::new(
+                (x as f32 - center_x) * TILE_SIZE,
+                (y as f32 - center_y) * TILE_SIZE,
+                0.,
+            );
+            *tile_image = tile_assets.get(tetromino);
+            *visibility = Visibility::Inherited;
+        } else {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+fn board_update(
+    mut query: Query<(
+        &Parent,
+        &mut Block,
+        &mut Sprite,
+        &mut Handle<Image>,
+        &mut Visibility,
+    )>,
+    boards: Query<(Entity, &BoardMap)>,
+    tile_assets: Res<TileAssets>,
+) {
+    let ghost_cells: HashMap<Entity, Vec<(u8, u8)>> = boards
+        .iter()
+        .map(|(board, board_map)| (board, board_map.ghost_cells()))
+        .collect();
+    for (parent, mut block, mut sprite, mut tile_image, mut visibility) in &mut query {
+        let Ok((board, board_map)) = boards.get(parent.get()) else {
+            continue;
+        };
+        let tile = board_map.tile_get(block.row, block.column).or_else(|| {
+            ghost_cells[&board]
+                .contains(&(block.row, block.column))
+                .then_some(Tetromino::G)
+        });
+        if block.tile == tile {
+            continue;
+        }
+        block.tile = tile;
+        if let Some(tile) = tile {
+            *tile_image = tile_assets.get(tile);
+            sprite.color = match tile {
+                Tetromino::X => GARBAGE_COLOR,
+                _ => Color::WHITE,
+            };
+            *visibility = Visibility::Visible;
+        } else {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+fn score_update(mut query: Query<(&Parent, &mut Text), With<ScoreText>>, boards: Query<&BoardMap>) {
+    for (parent, mut score_text) in &mut query {
+        let Ok(board_map) = boards.get(parent.get()) else {
+            continue;
+        };
+        score_text.sections[0].value = format!("{0:<08}", board_map.score_get());
+    }
+}
+
+fn preview_update(
+    mut query: Query<(&Parent, &mut TetrominoPreview, &Children)>,
+    mut blocks: Query<(&mut Transform, &mut Handle<Image>, &mut Visibility), With<PreviewBlock>>,
+    boards: Query<&TetrominoSupplier>,
+    tile_assets: Res<TileAssets>,
+    ruleset: Res<Ruleset>,
+) {
+    for (parent, mut tetromino_preview, children) in &mut query {
+        let Ok(tetromino_supplier) = boards.get(parent.get()) else {
+            continue;
+        };
+        let tetromino = Some(tetromino_supplier.get(tetromino_preview.index));
+        if tetromino_preview.tetromino == tetromino {
+            continue;
+        }
+        tetromino_preview.tetromino = tetromino;
+        preview_blocks_set(children, &mut blocks, tetromino, &ruleset, &tile_assets);
+    }
+}
+
+fn hold_update(
+    mut query: Query<(&Parent, &mut HoldPreview, &Children)>,
+    mut blocks: Query<(&mut Transform, &mut Handle<Image>, &mut Visibility), With<PreviewBlock>>,
+    boards: Query<&BoardMap>,
+    tile_assets: Res<TileAssets>,
+    ruleset: Res<Ruleset>,
+) {
+    for (parent, mut hold_preview, children) in &mut query {
+        let Ok(board_map) = boards.get(parent.get()) else {
+            continue;
+        };
+        let tetromino = board_map.hold_get();
+        if hold_preview.tetromino == tetromino {
+            continue;
+        }
+        hold_preview.tetromino = tetromino;
+        preview_blocks_set(children, &mut blocks, tetromino, &ruleset, &tile_assets);
+    }
+}
+
+fn guide_update(
+    mut query: Query<&mut Text, With<GuideText>>,
+    settings: Res<Settings>,
+    ruleset: Res<Ruleset>,
+    players: Query<&Player>,
+    mut player_count_last: Local<usize>,
+) {
+    let player_count = players.iter().count().max(1);
+    if !settings.is_changed() && *player_count_last == player_count {
+        return;
+    }
+    *player_count_last = player_count;
+    let mut guide_text = query.single_mut();
+    // actions the ruleset turns off aren't listed
+    let mut lines: Vec<String> = Action::ALL
+        .iter()
+        .filter(|&&action| match action {
+            Action::Hold => ruleset.hold,
+            Action::HardDrop => ruleset.hard_drop,
+            Action::Rotate180 => ruleset.rotate_180,
+            _ => true,
+        })
+        .map(|&action| {
+            let keys: Vec<String> = (0..player_count)
+                .filter_map(|player| settings.keyboard_bindings(player_count, player))
+                .map(|bindings| bindings.describe(action))
+                .collect();
+            format!(
+                "{}: {} / {}",
+                action.name(),
+                keys.join(" | "),
+                settings.gamepad_bindings.describe(action)
+            )
+        })
+        .collect();
+    lines.push("Controls: F1".to_string());
+    guide_text.sections[0].value = lines.join("\n");
+}
+
+fn paused_setup(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "PAUSED",
+            TextStyle {
+                font_size: 160.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(40.0),
+            left: Val::Percent(40.0),
+            ..default()
+        }),
+        StateScoped(Screen::Paused),
+    ));
+}
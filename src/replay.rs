@@ -0,0 +1,72 @@
+use crate::checksum::CHECKSUM_VERSION;
+use crate::input::GameAction;
+use crate::ruleset::Ruleset;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Everything needed to play a game again exactly: the rules, the supplier seed and every action.
+#[derive(Resource, Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    #[serde(default = "players_default")]
+    pub players: u8,
+    /// Rounds of a versus match, `None` for solo play.
+    #[serde(default)]
+    pub best_of: Option<u8>,
+    pub ruleset: Ruleset,
+    pub actions: Vec<GameAction>,
+    /// `(tick, checksum)` of the state every tick, checked on playback.
+    #[serde(default)]
+    pub checksums: Vec<(u64, u64)>,
+    /// [`CHECKSUM_VERSION`] the checksums were taken with, 0 for replays from before it.
+    #[serde(default)]
+    pub checksum_version: u32,
+    /// A checksum already differed during playback, only the first one is reported.
+    #[serde(skip)]
+    pub desynced: bool,
+}
+
+fn players_default() -> u8 {
+    1
+}
+
+#[derive(Resource, PartialEq, Eq)]
+pub enum ReplayMode {
+    Off,
+    /// Actions are appended and the file is written on game over or exit.
+    Record(String),
+    /// Index of the next action to send.
+    Play(usize),
+}
+
+impl Replay {
+    pub fn new(seed: u64, players: u8, ruleset: Ruleset) -> Self {
+        Self {
+            seed,
+            players,
+            best_of: None,
+            ruleset,
+            actions: Vec::new(),
+            checksums: Vec::new(),
+            checksum_version: CHECKSUM_VERSION,
+            desynced: false,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// The checksums were taken with this version of `state_checksum`, playback can check them.
+    pub fn checksums_current(&self) -> bool {
+        self.checksum_version == CHECKSUM_VERSION
+    }
+
+    // an action or checksum a line
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let config = ron::ser::PrettyConfig::default().depth_limit(2);
+        let text = ron::ser::to_string_pretty(self, config).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| e.to_string())
+    }
+}
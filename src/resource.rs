@@ -1,99 +1,28 @@
+use crate::bitboard::Bitboard;
+use crate::checksum::{rng_write, GameRng};
+use crate::ruleset::*;
 use bevy::prelude::*;
 use bevy::utils::HashMap;
-use rand::Rng;
-
-const ROTATION_TABLE: [[[(i8, i8); 4]; 4]; 7] = [
-    [
-        [(0, 2), (1, 2), (2, 2), (3, 2)],
-        [(2, 0), (2, 1), (2, 2), (2, 3)],
-        [(0, 1), (1, 1), (2, 1), (3, 1)],
-        [(1, 0), (1, 1), (1, 2), (1, 3)],
-    ], //I
-    [
-        [(0, 1), (0, 2), (1, 1), (2, 1)],
-        [(1, 0), (1, 1), (1, 2), (2, 2)],
-        [(0, 1), (1, 1), (2, 0), (2, 1)],
-        [(0, 0), (1, 0), (1, 1), (1, 2)],
-    ], //J
-    [
-        [(0, 1), (1, 1), (2, 1), (2, 2)],
-        [(1, 0), (1, 1), (1, 2), (2, 0)],
-        [(0, 0), (0, 1), (1, 1), (2, 1)],
-        [(0, 2), (1, 0), (1, 1), (1, 2)],
-    ], //L
-    [
-        [(1, 1), (1, 2), (2, 1), (2, 2)],
-        [(0, 2), (1, 2), (2, 2), (3, 2)],
-        [(0, 2), (1, 2), (2, 2), (3, 2)],
-        [(0, 2), (1, 2), (2, 2), (3, 2)],
-    ], //O
-    [
-        [(0, 1), (1, 1), (1, 2), (2, 2)],
-        [(1, 1), (1, 2), (2, 0), (2, 1)],
-        [(0, 0), (1, 0), (1, 1), (2, 1)],
-        [(0, 1), (0, 2), (1, 0), (1, 1)],
-    ], //S
-    [
-        [(0, 1), (1, 1), (1, 2), (2, 1)],
-        [(1, 0), (1, 1), (1, 2), (2, 1)],
-        [(0, 1), (1, 0), (1, 1), (2, 1)],
-        [(0, 1), (1, 0), (1, 1), (1, 2)],
-    ], //T
-    [
-        [(0, 2), (1, 1), (1, 2), (2, 1)],
-        [(1, 0), (1, 1), (2, 1), (2, 2)],
-        [(0, 1), (1, 0), (1, 1), (2, 0)],
-        [(0, 0), (0, 1), (1, 1), (1, 2)],
-    ], //Z
-];
-
-const WALL_KICK_TABLE_JLSTZ: [[(i8, i8); 5]; 8] = [
-    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-];
-
-const WALL_KICK_TABLE_I: [[(i8, i8); 5]; 8] = [
-    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
-    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
-    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
-    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
-    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
-    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
-    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
-    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
-];
-
-#[derive(Eq, Hash, PartialEq, Clone, Copy)]
-pub enum Tetromino {
-    I = 0,
-    J,
-    L,
-    O,
-    S,
-    T,
-    Z,
-    G, // Ghost
-}
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+
+/// Index of a piece in the active piece set, the standard set uses the named constants.
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub struct Tetromino(pub u8);
 
 impl Tetromino {
-    pub fn gen<R: Rng + ?Sized>(rng: &mut R) -> Tetromino {
-        let x: u8 = rng.gen_range(0 .. 7);
-        match x {
-            0 => Tetromino::I,
-            1 => Tetromino::J,
-            2 => Tetromino::L,
-            3 => Tetromino::O,
-            4 => Tetromino::S,
-            5 => Tetromino::T,
-            _ => Tetromino::Z,
-        }
-    }
+    pub const I: Tetromino = Tetromino(0);
+    pub const J: Tetromino = Tetromino(1);
+    pub const L: Tetromino = Tetromino(2);
+    pub const O: Tetromino = Tetromino(3);
+    pub const S: Tetromino = Tetromino(4);
+    pub const T: Tetromino = Tetromino(5);
+    pub const Z: Tetromino = Tetromino(6);
+    pub const G: Tetromino = Tetromino(u8::MAX); // Ghost
+    pub const X: Tetromino = Tetromino(u8::MAX - 1); // Garbage
 }
 
 #[derive(Clone, Copy)]
@@ -104,10 +33,42 @@ pub enum MoveDirection {
     // Up,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum RotateDirection {
     Left,
     Right,
+    Half,
+}
+
+/// The turn the piece last took and the index of its kick in the turn's kick table.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct Kick {
+    pub(crate) dir: RotateDirection,
+    pub(crate) index: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TSpin {
+    None,
+    Mini,
+    Full,
+}
+
+#[derive(Clone, Copy)]
+pub struct LineClear {
+    pub lines: u32,
+    pub tspin: TSpin,
+    /// The clear left the board empty.
+    pub perfect: bool,
+}
+
+/// Why a board topped out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TopOut {
+    /// No room for the next piece to spawn.
+    BlockOut,
+    /// Garbage pushed blocks above the board or left the piece no room.
+    GarbageOut,
 }
 
 #[derive(Clone, Copy)]
@@ -115,10 +76,11 @@ pub enum BoardEvent {
     LineCompleted = 0,
     TetrominoPut,
     TetrominoMoved,
+    TetrominoHeld,
     GameOver,
 }
 
-#[derive(Resource)]
+#[derive(Resource, Default)]
 pub struct TileAssets {
     map: HashMap<Tetromino, Handle<Image>>,
 }
@@ -130,57 +92,16 @@ impl TileAssets {
         }
     }
 
-    pub fn setup(&mut self, asset_server: &Res<AssetServer>) {
-        self.map
-            .insert(Tetromino::I, asset_server.load("blocks/LightBlue.png"));
-        self.map
-            .insert(Tetromino::J, asset_server.load("blocks/Blue.png"));
-        self.map
-            .insert(Tetromino::L, asset_server.load("blocks/Orange.png"));
-        self.map
-            .insert(Tetromino::O, asset_server.load("blocks/Yellow.png"));
-        self.map
-            .insert(Tetromino::S, asset_server.load("blocks/Green.png"));
-        self.map
-            .insert(Tetromino::T, asset_server.load("blocks/Purple.png"));
-        self.map
-            .insert(Tetromino::Z, asset_server.load("blocks/Red.png"));
-        self.map
-            .insert(Tetromino::G, asset_server.load("blocks/Ghost.png"));
-    }
-
-    pub fn get(&self, k: Tetromino) -> Handle<Image> {
-        self.map[&k].clone()
-    }
-}
-
-#[derive(Resource)]
-pub struct TetrominoAssets {
-    map: HashMap<Tetromino, Handle<Image>>,
-}
-
-impl TetrominoAssets {
-    pub fn new() -> Self {
-        Self {
-            map: HashMap::new(),
+    pub fn setup(&mut self, asset_server: &Res<AssetServer>, ruleset: &Ruleset) {
+        for idx in 0..ruleset.rotation_system().piece_count() {
+            let tetromino = Tetromino(idx);
+            self.map
+                .insert(tetromino, asset_server.load(ruleset.tile(tetromino).to_string()));
         }
-    }
-
-    pub fn setup(&mut self, asset_server: &Res<AssetServer>) {
-        self.map
-            .insert(Tetromino::I, asset_server.load("tetrominos/I.png"));
-        self.map
-            .insert(Tetromino::J, asset_server.load("tetrominos/J.png"));
-        self.map
-            .insert(Tetromino::L, asset_server.load("tetrominos/L.png"));
         self.map
-            .insert(Tetromino::O, asset_server.load("tetrominos/O.png"));
-        self.map
-            .insert(Tetromino::S, asset_server.load("tetrominos/S.png"));
-        self.map
-            .insert(Tetromino::T, asset_server.load("tetrominos/T.png"));
+            .insert(Tetromino::G, asset_server.load("blocks/Ghost.png"));
         self.map
-            .insert(Tetromino::Z, asset_server.load("tetrominos/Z.png"));
+            .insert(Tetromino::X, asset_server.load("blocks/Ghost.png"));
     }
 
     pub fn get(&self, k: Tetromino) -> Handle<Image> {
@@ -189,14 +110,14 @@ impl TetrominoAssets {
 }
 
 #[derive(Clone)]
-struct PlayerTetromino {
-    tetromion: Tetromino,
-    position: (i8, i8),
-    rotation: u8,
+pub(crate) struct PlayerTetromino {
+    pub(crate) tetromion: Tetromino,
+    pub(crate) position: (i8, i8),
+    pub(crate) rotation: u8,
 }
 
 impl PlayerTetromino {
-    fn transform(&mut self, dir: MoveDirection) {
+    pub(crate) fn transform(&mut self, dir: MoveDirection) {
         match dir {
             MoveDirection::Down => self.position = (self.position.0, self.position.1 - 1),
             MoveDirection::Left => self.position = (self.position.0 - 1, self.position.1),
@@ -206,27 +127,43 @@ impl PlayerTetromino {
     }
 
     fn transform_xy(&mut self, x: i8, y: i8) {
-        self.position = (self.position.0 as i8 + x, self.position.1 as i8 + y);
+        self.position = (self.position.0 + x, self.position.1 + y);
     }
 }
 
-#[derive(Resource)]
+#[derive(Component, Clone)]
 pub struct BoardMap {
     rows: [u16; 20],
     tiles: [[Tetromino; 10]; 20],
     player: Option<PlayerTetromino>,
+    hold: Option<Tetromino>,
+    hold_used: bool,
+    last_kick: Option<Kick>,
+    last_clear: Option<LineClear>,
     score: u32,
+    lines: u32,
+    pieces: u32,
+    top_out: Option<TopOut>,
     eventflag: u8,
+    ruleset: Ruleset,
 }
 
 impl BoardMap {
-    pub fn new() -> Self {
+    pub fn new(ruleset: &Ruleset) -> Self {
         Self {
             rows: [0; 20],
             tiles: [[Tetromino::I; 10]; 20],
             player: None,
+            hold: None,
+            hold_used: false,
+            last_kick: None,
+            last_clear: None,
             score: 0,
+            lines: 0,
+            pieces: 0,
+            top_out: None,
             eventflag: 0,
+            ruleset: ruleset.clone(),
         }
     }
 
@@ -251,67 +188,176 @@ impl BoardMap {
         }
     }
 
-    fn player_draw(&mut self) {
-        let Some(player) = &self.player else {
-            return;
-        };
-        let (x, y) = player.position;
-        let tetromion = Some(player.tetromion);
-        for (offset_x, offset_y) in
-            ROTATION_TABLE[player.tetromion as usize][player.rotation as usize]
-        {
-            let col = (x + offset_x) as u8;
-            let row = (y + offset_y) as u8;
-            self.tile_set(row, col, tetromion);
-        }
+    pub(crate) fn player_get(&self) -> Option<&PlayerTetromino> {
+        self.player.as_ref()
     }
 
-    fn player_erase(&mut self) {
+    pub(crate) fn player_cells(&self, player: &PlayerTetromino) -> &[(i8, i8)] {
+        self.ruleset
+            .rotation_system()
+            .cells(player.tetromion, player.rotation)
+    }
+
+    // the cells come from the ruleset, so the tiles are written field by field
+    fn player_tiles_set(&mut self, tile: Option<Tetromino>) {
         let Some(player) = &self.player else {
             return;
         };
         let (x, y) = player.position;
-        for (offset_x, offset_y) in
-            ROTATION_TABLE[player.tetromion as usize][player.rotation as usize]
-        {
-            let col = (x + offset_x) as u8;
-            let row = (y + offset_y) as u8;
-            self.tile_set(row, col, None);
+        let cells = self
+            .ruleset
+            .rotation_system()
+            .cells(player.tetromion, player.rotation);
+        for &(offset_x, offset_y) in cells {
+            let col = (x + offset_x) as usize;
+            let row = (y + offset_y) as usize;
+            match tile {
+                Some(tile) => {
+                    self.rows[row] |= 1_u16 << (9 - col);
+                    self.tiles[row][col] = tile;
+                }
+                None => self.rows[row] &= !(1_u16 << (9 - col)),
+            }
         }
     }
 
+    fn player_draw(&mut self) {
+        let tile = self.player.as_ref().map(|player| player.tetromion);
+        self.player_tiles_set(tile);
+    }
+
+    pub(crate) fn player_erase(&mut self) {
+        self.player_tiles_set(None);
+    }
+
     fn player_collision_check(&self, player: &PlayerTetromino) -> bool {
-        let (x, y) = player.position;
-        for (offset_x, offset_y) in
-            ROTATION_TABLE[player.tetromion as usize][player.rotation as usize]
-        {
-            let col = x + offset_x;
-            let row = y + offset_y;
-            if col < 0 || col >= 10 || row < 0 || row >= 20 {
-                return false;
-            }
-            let col = col as usize;
-            let row = row as usize;
-            if (self.rows[row] >> (9 - col)) & 1 == 1 {
-                return false;
-            }
+        self.player_fits(&Bitboard::new(&self.rows), player)
+    }
+
+    /// `player_collision_check` against a board prepared once for many tests.
+    pub(crate) fn player_fits(&self, bitboard: &Bitboard, player: &PlayerTetromino) -> bool {
+        self.ruleset.collision_masks().fits(
+            bitboard,
+            player.tetromion,
+            player.rotation,
+            player.position,
+        )
+    }
+
+    /// `player` moved down as far as it goes. The piece itself has to be erased from the board.
+    pub(crate) fn player_dropped(
+        &self,
+        bitboard: &Bitboard,
+        player: &PlayerTetromino,
+    ) -> PlayerTetromino {
+        let row = self.ruleset.collision_masks().drop_row(
+            bitboard,
+            player.tetromion,
+            player.rotation,
+            player.position,
+        );
+        PlayerTetromino {
+            position: (player.position.0, row),
+            ..player.clone()
         }
-        return true;
+    }
+
+    /// Locks `player` where it is, as if it got there with the kick of the last rotation.
+    pub(crate) fn player_put_at(&mut self, player: PlayerTetromino, kick: Option<Kick>) {
+        self.player_erase();
+        self.player = Some(player);
+        self.last_kick = kick;
+        self.player_put();
     }
 
     fn player_put(&mut self) {
+        let tspin = match &self.player {
+            Some(player) => self.tspin_get(player, self.last_kick),
+            None => TSpin::None,
+        };
         self.player_draw();
         self.player = None;
-        self.line_check();
+        self.hold_used = false;
+        self.last_kick = None;
+        self.pieces += 1;
+        let lines = self.line_check();
+        let perfect = lines > 0 && self.rows.iter().all(|&row| row == 0);
+        self.last_clear = Some(LineClear {
+            lines,
+            tspin,
+            perfect,
+        });
         self.event_set(BoardEvent::TetrominoPut);
     }
 
-    fn line_check(&mut self) {
+    // 3-corner rule, the T has to be locked right after a rotation
+    pub(crate) fn tspin_get(&self, player: &PlayerTetromino, kick: Option<Kick>) -> TSpin {
+        let Some(kick) = kick else {
+            return TSpin::None;
+        };
+        // the T is the only 4 cell piece with a cell touching the other 3
+        let cells = self.player_cells(player);
+        if cells.len() != 4 {
+            return TSpin::None;
+        }
+        let Some(&center) = cells.iter().find(|&&(x, y)| {
+            cells
+                .iter()
+                .filter(|&&(other_x, other_y)| (other_x - x).abs() + (other_y - y).abs() == 1)
+                .count()
+                == 3
+        }) else {
+            return TSpin::None;
+        };
+        // the arm without an opposite arm is the one the T points to
+        let Some(front) = cells.iter().map(|&(x, y)| (x - center.0, y - center.1)).find(
+            |&(x, y)| (x, y) != (0, 0) && !cells.contains(&(center.0 - x, center.1 - y)),
+        ) else {
+            return TSpin::None;
+        };
+        let (x, y) = player.position;
+        let mut corners = 0;
+        let mut front_corners = 0;
+        for (corner_x, corner_y) in [(-1, -1), (-1, 1), (1, -1), (1, 1)] {
+            let col = x + center.0 + corner_x;
+            let row = y + center.1 + corner_y;
+            let filled = !(0..10).contains(&col)
+                || !(0..20).contains(&row)
+                || (self.rows[row as usize] >> (9 - col)) & 1 == 1;
+            if filled {
+                corners += 1;
+                if corner_x * front.0 + corner_y * front.1 > 0 {
+                    front_corners += 1;
+                }
+            }
+        }
+        match (corners, front_corners) {
+            (0..=2, _) => TSpin::None,
+            (_, 2) => TSpin::Full,
+            _ if self.kick_full(player, kick) => TSpin::Full,
+            _ => TSpin::Mini,
+        }
+    }
+
+    /// Whether the kick that turned `player` where it is makes any T-spin a full one.
+    pub(crate) fn kick_full(&self, player: &PlayerTetromino, kick: Kick) -> bool {
+        let from = match kick.dir {
+            RotateDirection::Left => (player.rotation + 1) % 4,
+            RotateDirection::Right => (player.rotation + 3) % 4,
+            RotateDirection::Half => (player.rotation + 2) % 4,
+        };
+        self.ruleset
+            .rotation_system()
+            .kick_full(player.tetromion, from, player.rotation, kick.index)
+    }
+
+    fn line_check(&mut self) -> u32 {
         let row_len = self.rows.len();
         let mut row_idx = 0;
+        let mut lines = 0;
         while row_idx < row_len {
             if self.rows[row_idx] == 0 {
-                return;
+                break;
             }
             if self.rows[row_idx] & 0x3ff != 0x3ff {
                 row_idx += 1;
@@ -326,98 +372,260 @@ impl BoardMap {
                 }
             }
             self.rows[row_len - 1] = 0;
-            self.score += 1;
+            lines += 1;
+        }
+        if lines > 0 {
+            self.score += self.ruleset.line_score(lines, self.level_get());
+            self.lines += lines;
             self.event_set(BoardEvent::LineCompleted);
         }
+        lines
     }
 
     pub fn player_spawn(&mut self, tetromion: Tetromino) {
         if self.player.is_some() {
             return;
         };
+        let (x, y) = self.ruleset.spawn;
+        let (offset_x, offset_y) = self.ruleset.rotation_system().spawn_offset(tetromion);
         let player = PlayerTetromino {
             tetromion,
-            position: (3, 17),
+            position: (x + offset_x, y + offset_y),
             rotation: 0,
         };
         if self.player_collision_check(&player) {
             self.player = Some(player);
             self.player_draw();
         } else {
+            self.top_out = Some(TopOut::BlockOut);
             self.event_set(BoardEvent::GameOver);
         }
     }
 
-    pub fn player_move(&mut self, dir: MoveDirection) {
+    pub fn player_move(&mut self, dir: MoveDirection) -> bool {
         let Some(mut player) = self.player.clone() else {
-            return;
+            return false;
         };
         self.player_erase();
         player.transform(dir);
-        if self.player_collision_check(&player) {
+        let moved = self.player_collision_check(&player);
+        if moved {
             self.player = Some(player);
+            self.last_kick = None;
             self.event_set(BoardEvent::TetrominoMoved);
-        } else {
-            match dir {
-                MoveDirection::Down => self.player_put(),
-                _ => (),
-            }
+        } else if let (MoveDirection::Down, None) = (dir, self.ruleset.lock_delay) {
+            self.player_put();
         }
         self.player_draw();
+        moved
     }
 
     pub fn player_move_to_bottom(&mut self) {
-        let Some(mut player) = self.player.clone() else {
+        let Some(player) = self.player.clone() else {
             return;
         };
         self.player_erase();
-        player.transform(MoveDirection::Down);
-        while self.player_collision_check(&player) {
-            self.player = Some(player.clone());
-            player.transform(MoveDirection::Down);
+        let dropped = self.player_dropped(&Bitboard::new(&self.rows), &player);
+        if dropped.position != player.position {
+            self.player = Some(dropped);
+            self.last_kick = None;
         }
         self.player_put();
     }
 
-    pub fn player_rotate(&mut self, dir: RotateDirection) {
-        let Some(mut player) = self.player.clone() else {
+    pub fn player_rotate(&mut self, dir: RotateDirection) -> bool {
+        let Some(player) = self.player.clone() else {
+            return false;
+        };
+        self.player_erase();
+        let rotated = self.player_rotated(&Bitboard::new(&self.rows), &player, dir);
+        if let Some((player, index)) = rotated.clone() {
+            self.player = Some(player);
+            self.last_kick = Some(Kick { dir, index });
+        }
+        self.player_draw();
+        rotated.is_some()
+    }
+
+    /// Where `player` ends up after the first kick that fits, and the kick's index. The piece
+    /// itself has to be erased from the board.
+    pub(crate) fn player_rotated(
+        &self,
+        bitboard: &Bitboard,
+        player: &PlayerTetromino,
+        dir: RotateDirection,
+    ) -> Option<(PlayerTetromino, usize)> {
+        let mut player = player.clone();
+        let from = player.rotation;
+        player.rotation = match dir {
+            RotateDirection::Left => (from + 3) % 4,
+            RotateDirection::Right => (from + 1) % 4,
+            RotateDirection::Half if self.ruleset.rotate_180 => (from + 2) % 4,
+            RotateDirection::Half => return None,
+        };
+        let wall_kick_table = self
+            .ruleset
+            .rotation_system()
+            .kicks(player.tetromion, from, player.rotation);
+        let wall_kick_count = if self.ruleset.wall_kick {
+            wall_kick_table.len()
+        } else {
+            wall_kick_table.len().min(1)
+        };
+        wall_kick_table[..wall_kick_count]
+            .iter()
+            .enumerate()
+            .find_map(|(kick, &(offset_x, offset_y))| {
+                let mut player = player.clone();
+                player.transform_xy(offset_x, offset_y);
+                self.player_fits(bitboard, &player)
+                    .then_some((player, kick))
+            })
+    }
+
+    pub fn player_hold(&mut self) {
+        if !self.ruleset.hold || self.hold_used {
+            return;
+        }
+        let Some(player) = self.player.clone() else {
             return;
         };
-        let wall_kick_table = match player.tetromion {
-            Tetromino::I => WALL_KICK_TABLE_I,
-            Tetromino::J | Tetromino::L | Tetromino::S | Tetromino::T | Tetromino::Z => {
-                WALL_KICK_TABLE_JLSTZ
-            }
-            _ => {
-                return;
-            } // no rotation for O, G
+        self.player_erase();
+        self.player = None;
+        self.hold_used = true;
+        match self.hold.replace(player.tetromion) {
+            Some(hold) => self.player_spawn(hold),
+            None => self.event_set(BoardEvent::TetrominoHeld), // next one comes from the supplier
+        }
+    }
+
+    pub fn player_lock(&mut self) {
+        if self.player.is_some() {
+            self.player_erase();
+            self.player_put();
+        }
+    }
+
+    pub fn player_grounded(&self) -> bool {
+        let Some(mut player) = self.player.clone() else {
+            return false;
         };
-        let table_idx = match dir {
-            RotateDirection::Left => {
-                player.rotation = (player.rotation + 3) % 4;
-                ((player.rotation * 2) + 1) as usize
-            }
-            RotateDirection::Right => {
-                player.rotation = (player.rotation + 1) % 4;
-                (((player.rotation + 3) % 4) * 2) as usize
-            }
+        player.transform(MoveDirection::Down);
+        !self.player_fits(&Bitboard::new(&self.stack_get()), &player)
+    }
+
+    pub fn ghost_cells(&self) -> Vec<(u8, u8)> {
+        let Some(player) = &self.player else {
+            return Vec::new();
         };
+        if !self.ruleset.ghost {
+            return Vec::new();
+        }
+        let player = self.player_dropped(&Bitboard::new(&self.stack_get()), player);
+        let (x, y) = player.position;
+        self.player_cells(&player)
+            .iter()
+            .map(|(offset_x, offset_y)| ((y + offset_y) as u8, (x + offset_x) as u8))
+            .collect()
+    }
+
+    /// Pushes the stack up by `count` rows of garbage with one hole at `hole`, the falling piece
+    /// moves up with it if they would overlap. Blocks pushed over the top end the game.
+    pub fn garbage_add(&mut self, count: usize, hole: u8) {
+        let row_len = self.rows.len();
+        let count = count.min(row_len);
+        if count == 0 {
+            return;
+        }
         self.player_erase();
-        for (offset_x, offset_y) in wall_kick_table[table_idx] {
-            let mut player = player.clone();
-            player.transform_xy(offset_x, offset_y);
+        let overflow = self.rows[row_len - count..].iter().any(|&row| row != 0);
+        self.rows.copy_within(..row_len - count, count);
+        self.tiles.copy_within(..row_len - count, count);
+        for row in 0..count {
+            self.rows[row] = 0x3ff & !(1 << (9 - hole));
+            self.tiles[row] = [Tetromino::X; 10];
+        }
+        let mut topped_out = overflow;
+        if let Some(mut player) = self.player.take() {
+            while !self.player_collision_check(&player) && player.position.1 < row_len as i8 {
+                player.transform_xy(0, 1);
+            }
             if self.player_collision_check(&player) {
                 self.player = Some(player);
-                break;
+            } else {
+                topped_out = true;
             }
         }
+        if topped_out {
+            self.player = None;
+            self.top_out = Some(TopOut::GarbageOut);
+            self.event_set(BoardEvent::GameOver);
+        }
         self.player_draw();
     }
 
+    pub fn clear_get(&self) -> Option<LineClear> {
+        self.last_clear
+    }
+
+    pub fn hold_get(&self) -> Option<Tetromino> {
+        self.hold
+    }
+
+    /// Puts a piece in hold, for boards set up from outside the game.
+    pub fn hold_set(&mut self, hold: Option<Tetromino>) {
+        self.hold = hold;
+    }
+
+    /// Whether `player_hold` would do anything right now.
+    pub fn hold_available(&self) -> bool {
+        self.ruleset.hold && !self.hold_used && self.player.is_some()
+    }
+
+    /// The falling piece, `None` once the board topped out.
+    pub fn piece_get(&self) -> Option<Tetromino> {
+        self.player.as_ref().map(|player| player.tetromion)
+    }
+
     pub fn score_get(&self) -> u32 {
         self.score
     }
 
+    /// Pieces locked so far.
+    pub fn pieces_get(&self) -> u32 {
+        self.pieces
+    }
+
+    pub fn top_out_get(&self) -> Option<TopOut> {
+        self.top_out
+    }
+
+    pub fn lines_get(&self) -> u32 {
+        self.lines
+    }
+
+    /// Occupancy from the bottom row up, bit `9 - col` for each column, the falling piece
+    /// included.
+    pub fn rows_get(&self) -> [u16; 20] {
+        self.rows
+    }
+
+    /// `rows_get` with the falling piece left out.
+    pub fn stack_get(&self) -> [u16; 20] {
+        let mut rows = self.rows;
+        if let Some(player) = &self.player {
+            let (x, y) = player.position;
+            for &(offset_x, offset_y) in self.player_cells(player) {
+                rows[(y + offset_y) as usize] &= !(1_u16 << (9 - (x + offset_x)));
+            }
+        }
+        rows
+    }
+
+    pub fn level_get(&self) -> u32 {
+        self.lines / 10
+    }
+
     fn event_set(&mut self, e: BoardEvent) {
         self.eventflag |= 1 << (e as usize);
     }
@@ -433,37 +641,249 @@ impl BoardMap {
     pub fn event_reset(&mut self) {
         self.eventflag = 0;
     }
+
+    /// Feeds everything but the events and the rules, which every tick resets or never changes.
+    pub fn checksum_write(&self, hasher: &mut impl Hasher) {
+        // row by row, hashing the array would write its bytes in the platform's order
+        hasher.write_usize(self.rows.len());
+        for row in self.rows {
+            hasher.write_u16(row);
+        }
+        // tiles of empty cells are left over from earlier pieces
+        for row in 0..20 {
+            for col in 0..10 {
+                self.tile_get(row, col).hash(hasher);
+            }
+        }
+        if let Some(player) = &self.player {
+            player.tetromion.hash(hasher);
+            player.position.hash(hasher);
+            player.rotation.hash(hasher);
+        }
+        self.hold.hash(hasher);
+        self.hold_used.hash(hasher);
+        self.last_kick.hash(hasher);
+        if let Some(clear) = self.last_clear {
+            clear.lines.hash(hasher);
+            (clear.tspin as u8).hash(hasher);
+            clear.perfect.hash(hasher);
+        }
+        self.score.hash(hasher);
+        self.lines.hash(hasher);
+        self.pieces.hash(hasher);
+        self.top_out.map(|top_out| top_out as u8).hash(hasher);
+    }
+
+    /// The matrix from the top, `#` for blocks and `@` for the piece, then the rest of the state.
+    pub fn dump(&self) -> String {
+        let cells: Vec<(i8, i8)> = self
+            .player
+            .as_ref()
+            .map(|player| {
+                let (x, y) = player.position;
+                self.player_cells(player)
+                    .iter()
+                    .map(|(offset_x, offset_y)| (y + offset_y, x + offset_x))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut text = String::new();
+        for row in (0..20).rev() {
+            for col in 0..10 {
+                text.push(if cells.contains(&(row as i8, col as i8)) {
+                    '@'
+                } else if self.tile_get(row, col).is_some() {
+                    '#'
+                } else {
+                    '.'
+                });
+            }
+            text.push('\n');
+        }
+        if let Some(player) = &self.player {
+            let _ = write!(
+                text,
+                "piece {:?} at {:?} rotation {}, ",
+                player.tetromion, player.position, player.rotation
+            );
+        }
+        let _ = write!(
+            text,
+            "hold {:?} used {}, last kick {:?}, score {}, lines {}",
+            self.hold, self.hold_used, self.last_kick, self.score, self.lines
+        );
+        if let Some(clear) = self.last_clear {
+            let _ = write!(
+                text,
+                ", last clear {} {:?} perfect {}",
+                clear.lines, clear.tspin, clear.perfect
+            );
+        }
+        text
+    }
 }
 
-#[derive(Resource)]
+#[derive(Component, Clone)]
 pub struct TetrominoSupplier {
-    idx: usize,
-    list: [Tetromino; 5],
+    list: VecDeque<Tetromino>,
+    bag: Vec<Tetromino>,
+    last: Option<Tetromino>,
+    ruleset: Ruleset,
+    // seeded so the same seed always deals the same pieces
+    rng: GameRng,
 }
 
 impl TetrominoSupplier {
-    pub fn new() -> Self {
-        Self { 
-            idx: 0,
-            list: [Tetromino::I; 5],
+    pub fn new(ruleset: &Ruleset, seed: u64) -> Self {
+        Self {
+            list: VecDeque::new(),
+            bag: Vec::new(),
+            last: None,
+            ruleset: ruleset.clone(),
+            rng: GameRng::seed_from_u64(seed),
         }
     }
 
     pub fn get(&self, idx: usize) -> Tetromino {
-        self.list[(self.idx + idx) % 5]
+        self.list[idx]
+    }
+
+    /// Pieces `get` can see ahead.
+    pub fn queue_len(&self) -> usize {
+        self.list.len()
     }
 
-    pub fn fill<R: Rng + ?Sized>(&mut self, rng: &mut R) {
-        let list = &mut self.list;
-        for i in 0 .. 5 {
-            list[i] = Tetromino::gen(rng);
+    pub fn checksum_write(&self, hasher: &mut impl Hasher) {
+        for tetromino in &self.list {
+            tetromino.hash(hasher);
         }
+        self.bag.hash(hasher);
+        self.last.hash(hasher);
+        rng_write(&self.rng, hasher);
     }
 
-    pub fn pop<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Tetromino {
-        let current = self.list[self.idx];
-        self.list[self.idx] = Tetromino::gen(rng);
-        self.idx = (self.idx + 1) % 5;
+    pub fn dump(&self) -> String {
+        format!(
+            "queue {:?}, bag {:?}, last {:?}, rng word {}",
+            self.list,
+            self.bag,
+            self.last,
+            self.rng.get_word_pos()
+        )
+    }
+
+    pub fn fill(&mut self) {
+        while self.list.len() < self.ruleset.preview_count.max(1) {
+            let tetromino = self.gen();
+            self.list.push_back(tetromino);
+        }
+    }
+
+    pub fn pop(&mut self) -> Tetromino {
+        let current = self.list.pop_front().unwrap_or_else(|| self.gen());
+        self.fill();
         current
     }
-}
\ No newline at end of file
+
+    fn gen(&mut self) -> Tetromino {
+        let rng = &mut self.rng;
+        let count = self.ruleset.rotation_system().piece_count();
+        let tetromino = match self.ruleset.randomizer {
+            Randomizer::Random => Tetromino(rng.gen_range(0..count)),
+            Randomizer::Bag => {
+                if self.bag.is_empty() {
+                    self.bag = (0..count).map(Tetromino).collect();
+                    self.bag.shuffle(rng);
+                }
+                self.bag.pop().unwrap()
+            }
+            Randomizer::Nes => {
+                // roll one extra side, reroll once on the dummy side or a repeat
+                let x: u8 = rng.gen_range(0..=count);
+                if x == count || Some(Tetromino(x)) == self.last {
+                    Tetromino(rng.gen_range(0..count))
+                } else {
+                    Tetromino(x)
+                }
+            }
+        };
+        self.last = Some(tetromino);
+        tetromino
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resource::*;
+
+    // a T pointing down in the bottom left corner, its center on col 1 row 1
+    fn t_slot(blocks: &[(u8, u8)]) -> (BoardMap, PlayerTetromino) {
+        let mut board_map = BoardMap::new(&Ruleset::default());
+        for &(col, row) in blocks {
+            board_map.tile_set(row, col, Some(Tetromino::X));
+        }
+        let player = PlayerTetromino {
+            tetromion: Tetromino::T,
+            position: (0, 0),
+            rotation: 2,
+        };
+        (board_map, player)
+    }
+
+    const TURNS: [RotateDirection; 3] = [
+        RotateDirection::Left,
+        RotateDirection::Right,
+        RotateDirection::Half,
+    ];
+
+    fn kick(dir: RotateDirection, index: usize) -> Option<Kick> {
+        Some(Kick { dir, index })
+    }
+
+    #[test]
+    fn tspins_take_three_corners_and_a_rotation() {
+        let (board_map, player) = t_slot(&[(0, 0), (2, 0), (0, 2)]);
+        for dir in TURNS {
+            assert_eq!(board_map.tspin_get(&player, kick(dir, 0)), TSpin::Full);
+        }
+        assert_eq!(board_map.tspin_get(&player, None), TSpin::None);
+        let (board_map, player) = t_slot(&[(0, 0), (2, 0)]);
+        let tspin = board_map.tspin_get(&player, kick(RotateDirection::Right, 0));
+        assert_eq!(tspin, TSpin::None);
+    }
+
+    #[test]
+    fn mini_tspins_miss_a_front_corner() {
+        let (board_map, player) = t_slot(&[(0, 0), (0, 2), (2, 2)]);
+        for dir in TURNS {
+            assert_eq!(board_map.tspin_get(&player, kick(dir, 0)), TSpin::Mini);
+        }
+    }
+
+    #[test]
+    fn only_the_last_quarter_turn_kick_makes_a_mini_full() {
+        let (board_map, player) = t_slot(&[(0, 0), (0, 2), (2, 2)]);
+        for dir in [RotateDirection::Left, RotateDirection::Right] {
+            assert_eq!(board_map.tspin_get(&player, kick(dir, 4)), TSpin::Full);
+            assert_eq!(board_map.tspin_get(&player, kick(dir, 3)), TSpin::Mini);
+        }
+        // the fifth 180 kick is a plain sideways shift
+        let tspin = board_map.tspin_get(&player, kick(RotateDirection::Half, 4));
+        assert_eq!(tspin, TSpin::Mini);
+        // and other rotation systems have no such kick, the ARS T points down at spawn
+        let player = PlayerTetromino {
+            rotation: 0,
+            ..player
+        };
+        let ruleset = Ruleset {
+            rotation: RotationKind::Ars,
+            ..Default::default()
+        };
+        let mut board_map = BoardMap::new(&ruleset);
+        for (col, row) in [(0, 0), (0, 2), (2, 2)] {
+            board_map.tile_set(row, col, Some(Tetromino::X));
+        }
+        let tspin = board_map.tspin_get(&player, kick(RotateDirection::Right, 4));
+        assert_eq!(tspin, TSpin::Mini);
+    }
+}
@@ -0,0 +1,417 @@
+use crate::attack::Garbage;
+use crate::checksum::BoardView;
+use crate::game::{tick_step, BoardQuery, BoardState, DroppingTimer, LockTimer};
+use crate::input::*;
+use crate::replay::{Replay, ReplayMode};
+use crate::resource::*;
+use crate::ruleset::Ruleset;
+use crate::versus::Match;
+use bevy::prelude::*;
+use std::collections::{BTreeMap, VecDeque};
+
+// everything one board needs to play a tick again
+#[derive(Clone)]
+struct SavedBoard {
+    player: u8,
+    board_map: BoardMap,
+    tetromino_supplier: TetrominoSupplier,
+    dropping_timer: DroppingTimer,
+    lock_timer: LockTimer,
+    garbage: Option<Garbage>,
+}
+
+impl SavedBoard {
+    fn state(&mut self) -> BoardState<'_> {
+        BoardState {
+            player: self.player,
+            board_map: &mut self.board_map,
+            tetromino_supplier: &mut self.tetromino_supplier,
+            dropping_timer: &mut self.dropping_timer,
+            lock_timer: &mut self.lock_timer,
+            garbage: self.garbage.as_mut(),
+        }
+    }
+}
+
+/// The boards and the round at the end of a tick.
+#[derive(Clone)]
+pub(crate) struct Snapshot {
+    tick: u64,
+    boards: Vec<SavedBoard>,
+    versus_match: Match,
+}
+
+impl Snapshot {
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn views(&self) -> Vec<BoardView<'_>> {
+        self.boards
+            .iter()
+            .map(|board| BoardView {
+                player: board.player,
+                board_map: &board.board_map,
+                tetromino_supplier: &board.tetromino_supplier,
+                dropping_timer: &board.dropping_timer,
+                lock_timer: &board.lock_timer,
+                garbage: board.garbage.as_ref(),
+            })
+            .collect()
+    }
+
+    // the same tick the fixed-tick systems play, without anything for render or sound
+    fn simulate(&mut self, ruleset: &Ruleset, actions: impl Fn(u8) -> Vec<Action>) {
+        self.tick += 1;
+        let mut boards: Vec<BoardState> = self.boards.iter_mut().map(SavedBoard::state).collect();
+        tick_step(
+            self.tick,
+            ruleset,
+            &mut boards,
+            actions,
+            Some(&mut self.versus_match),
+        );
+        for board in &mut self.boards {
+            board.board_map.event_reset();
+        }
+    }
+}
+
+/// How much rollback an online match needed so far.
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct RollbackStats {
+    pub rollbacks: u64,
+    pub resimulated_ticks: u64,
+    pub max_depth: u64,
+    /// Ticks held back because the opponent fell too far behind.
+    pub stalled_ticks: u64,
+}
+
+/// Snapshots and inputs of the last ticks. The opponent is predicted to do nothing, when their
+/// real inputs for a tick already played arrive the boards go back to the snapshot before it and
+/// play the ticks since again.
+#[derive(Resource)]
+pub(crate) struct Rollback {
+    /// Board of this client, the other one is predicted.
+    pub player: u8,
+    /// Ticks the game may run ahead of the opponent's inputs, 0 plays in lockstep.
+    pub window: u64,
+    snapshots: VecDeque<Snapshot>,
+    pub local: BTreeMap<u64, Vec<Action>>,
+    pub remote: BTreeMap<u64, Vec<Action>>,
+    /// Earliest tick played on a wrong prediction.
+    pub from: Option<u64>,
+    /// Opponent actions applied by a rollback, still to be recorded.
+    pub late: Vec<GameAction>,
+}
+
+impl Rollback {
+    pub fn new(player: u8, window: u64) -> Self {
+        Self {
+            player,
+            window,
+            snapshots: VecDeque::new(),
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            from: None,
+            late: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.player, self.window);
+    }
+
+    pub fn snapshots(&self) -> impl Iterator<Item = &Snapshot> {
+        self.snapshots.iter()
+    }
+}
+
+pub(crate) fn snapshot_save(
+    tick: Res<Tick>,
+    boards: Query<BoardQuery>,
+    versus_match: Res<Match>,
+    mut rollback: ResMut<Rollback>,
+) {
+    if rollback
+        .snapshots
+        .back()
+        .is_some_and(|snapshot| snapshot.tick == tick.0)
+    {
+        return;
+    }
+    let mut states: Vec<SavedBoard> = boards
+        .iter()
+        .map(
+            |(player, board_map, tetromino_supplier, dropping_timer, lock_timer, garbage)| {
+                SavedBoard {
+                    player: player.0,
+                    board_map: board_map.clone(),
+                    tetromino_supplier: tetromino_supplier.clone(),
+                    dropping_timer: dropping_timer.clone(),
+                    lock_timer: lock_timer.clone(),
+                    garbage: garbage.cloned(),
+                }
+            },
+        )
+        .collect();
+    states.sort_by_key(|state| state.player);
+    rollback.snapshots.push_back(Snapshot {
+        tick: tick.0,
+        boards: states,
+        versus_match: versus_match.clone(),
+    });
+    // a rollback never goes further back than the window
+    let keep = rollback.window as usize + 2;
+    while rollback.snapshots.len() > keep {
+        rollback.snapshots.pop_front();
+    }
+    let oldest = rollback.snapshots[0].tick;
+    rollback.local.retain(|&tick, _| tick > oldest);
+    rollback.remote.retain(|&tick, _| tick > oldest);
+}
+
+pub(crate) fn rollback_apply(
+    mut tick: ResMut<Tick>,
+    ruleset: Res<Ruleset>,
+    mut boards: Query<BoardQuery>,
+    mut versus_match: ResMut<Match>,
+    mut rollback: ResMut<Rollback>,
+    mut stats: ResMut<RollbackStats>,
+) {
+    let rollback = &mut *rollback;
+    let Some(from) = rollback.from.take() else {
+        return;
+    };
+    let Some(index) = rollback
+        .snapshots
+        .iter()
+        .position(|snapshot| snapshot.tick + 1 == from)
+    else {
+        warn!("no snapshot to roll back to tick {from}");
+        return;
+    };
+    rollback.snapshots.truncate(index + 1);
+    let mut snapshot = rollback.snapshots[index].clone();
+    stats.rollbacks += 1;
+    stats.max_depth = stats.max_depth.max(tick.0 + 1 - from);
+    let (local, remote) = (&rollback.local, &rollback.remote);
+    while snapshot.tick < tick.0 && snapshot.versus_match.end_tick.is_none() {
+        let next = snapshot.tick + 1;
+        snapshot.simulate(&ruleset, |player| {
+            let inputs = if player == rollback.player {
+                local
+            } else {
+                remote
+            };
+            inputs.get(&next).cloned().unwrap_or_default()
+        });
+        rollback.snapshots.push_back(snapshot.clone());
+        stats.resimulated_ticks += 1;
+    }
+    // the round may have ended on a tick the prediction got wrong
+    tick.0 = snapshot.tick;
+    for (player, mut board_map, mut supplier, mut dropping_timer, mut lock_timer, garbage) in
+        &mut boards
+    {
+        let Some(state) = snapshot
+            .boards
+            .iter()
+            .find(|state| state.player == player.0)
+        else {
+            continue;
+        };
+        *board_map = state.board_map.clone();
+        *supplier = state.tetromino_supplier.clone();
+        *dropping_timer = state.dropping_timer.clone();
+        *lock_timer = state.lock_timer.clone();
+        if let (Some(mut garbage), Some(state_garbage)) = (garbage, &state.garbage) {
+            *garbage = state_garbage.clone();
+        }
+    }
+    versus_match.alive = snapshot.versus_match.alive;
+    versus_match.end_tick = snapshot.versus_match.end_tick;
+}
+
+pub(crate) fn late_action_record(
+    mut rollback: ResMut<Rollback>,
+    replay_mode: Res<ReplayMode>,
+    mut replay: ResMut<Replay>,
+) {
+    if rollback.late.is_empty() {
+        return;
+    }
+    let late = std::mem::take(&mut rollback.late);
+    if let ReplayMode::Record(_) = *replay_mode {
+        replay.actions.extend(late);
+        // playback expects the actions in tick order
+        replay.actions.sort_by_key(|game_action| game_action.tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::state_checksum;
+    use crate::game::Player;
+    use crate::rollback::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    const SEED: u64 = 5;
+    const TICKS: u64 = 300;
+
+    // a piece every 20 ticks, turned and moved somewhere else each time
+    fn actions_get(player: u8, tick: u64) -> Vec<Action> {
+        let piece = tick / 20 + player as u64;
+        let shift = (piece * 3 % 7) as i64 - 3;
+        match tick % 20 {
+            1 => match piece % 4 {
+                1 => vec![Action::RotateRight],
+                2 => vec![Action::RotateLeft],
+                3 => vec![Action::Rotate180],
+                _ => Vec::new(),
+            },
+            phase @ 2..=4 if phase - 2 < shift.unsigned_abs() => {
+                let action = if shift < 0 {
+                    Action::MoveLeft
+                } else {
+                    Action::MoveRight
+                };
+                vec![action]
+            }
+            19 => vec![Action::HardDrop],
+            _ => Vec::new(),
+        }
+    }
+
+    fn world_new(ruleset: &Ruleset) -> World {
+        let mut world = World::new();
+        world.insert_resource(Tick(0));
+        world.insert_resource(ruleset.clone());
+        world.insert_resource(Match::new(1, 2));
+        world.insert_resource(Rollback::new(0, 8));
+        world.insert_resource(RollbackStats::default());
+        world.insert_resource(ReplayMode::Record(String::new()));
+        world.insert_resource(Replay::new(SEED, 2, ruleset.clone()));
+        for player in 0..2 {
+            world.spawn((
+                Player(player),
+                BoardMap::new(ruleset),
+                TetrominoSupplier::new(ruleset, SEED),
+                DroppingTimer::default(),
+                LockTimer::default(),
+                Garbage::new(SEED + player as u64),
+            ));
+        }
+        world
+    }
+
+    // the next tick the way the game plays it, the opponent doing nothing unless known
+    fn tick_play(world: &mut World) {
+        let tick = {
+            let mut tick = world.resource_mut::<Tick>();
+            tick.0 += 1;
+            tick.0
+        };
+        let local = actions_get(0, tick);
+        let remote = world.resource::<Rollback>().remote.get(&tick).cloned();
+        let mut rollback = world.resource_mut::<Rollback>();
+        rollback.local.insert(tick, local.clone());
+        let mut replay = world.resource_mut::<Replay>();
+        replay.actions.extend(local.iter().map(|&action| GameAction {
+            tick,
+            player: 0,
+            action,
+        }));
+        let ruleset = world.resource::<Ruleset>().clone();
+        world.resource_scope(|world, mut versus_match: Mut<Match>| {
+            let mut query = world.query::<BoardQuery>();
+            let mut boards: Vec<BoardState> = query
+                .iter_mut(world)
+                .map(
+                    |(player, board_map, tetromino_supplier, dropping_timer, lock_timer, garbage)| {
+                        BoardState {
+                            player: player.0,
+                            board_map: board_map.into_inner(),
+                            tetromino_supplier: tetromino_supplier.into_inner(),
+                            dropping_timer: dropping_timer.into_inner(),
+                            lock_timer: lock_timer.into_inner(),
+                            garbage: garbage.map(Mut::into_inner),
+                        }
+                    },
+                )
+                .collect();
+            let actions = |player| match player {
+                0 => local.clone(),
+                _ => remote.clone().unwrap_or_default(),
+            };
+            tick_step(tick, &ruleset, &mut boards, actions, Some(&mut versus_match));
+            for board in &mut boards {
+                board.board_map.event_reset();
+            }
+        });
+    }
+
+    // the opponent's inputs for `input_tick` come in, like `net_receive` takes them
+    fn remote_receive(world: &mut World, input_tick: u64) {
+        let played = world.resource::<Tick>().0;
+        let actions = actions_get(1, input_tick);
+        let mut rollback = world.resource_mut::<Rollback>();
+        if input_tick <= played && !actions.is_empty() {
+            rollback.from = Some(rollback.from.map_or(input_tick, |from| from.min(input_tick)));
+            rollback.late.extend(actions.iter().map(|&action| GameAction {
+                tick: input_tick,
+                player: 1,
+                action,
+            }));
+        }
+        rollback.remote.insert(input_tick, actions);
+    }
+
+    // the systems an online client runs before each tick
+    fn rollback_run(world: &mut World) {
+        world.run_system_once(rollback_apply);
+        world.run_system_once(late_action_record);
+        world.run_system_once(snapshot_save);
+    }
+
+    fn last_checksum(world: &World) -> u64 {
+        let snapshot = world.resource::<Rollback>().snapshots().last().expect("snapshot");
+        state_checksum(snapshot.tick(), &snapshot.views())
+    }
+
+    // the opponent's inputs arrive `lateness` ticks after they were played, 0 in time
+    fn match_play(lateness: u64) -> World {
+        let mut world = world_new(&Ruleset::default());
+        for tick in 1..=TICKS {
+            if tick > lateness {
+                remote_receive(&mut world, tick - lateness);
+            }
+            rollback_run(&mut world);
+            tick_play(&mut world);
+        }
+        for tick in TICKS + 1 - lateness..=TICKS {
+            remote_receive(&mut world, tick);
+        }
+        rollback_run(&mut world);
+        world
+    }
+
+    #[test]
+    fn late_inputs_roll_back_to_the_same_state() {
+        let on_time = match_play(0);
+        let late = match_play(3);
+        assert_eq!(on_time.resource::<RollbackStats>().rollbacks, 0);
+        let stats = late.resource::<RollbackStats>();
+        assert!(stats.resimulated_ticks > 0, "{stats:?}");
+        assert_eq!(late.resource::<Tick>().0, TICKS);
+        assert!(late.resource::<Match>().end_tick.is_none());
+        assert_eq!(last_checksum(&late), last_checksum(&on_time));
+        // the late inputs are recorded in tick order, along with the ones played in time
+        let actions = &late.resource::<Replay>().actions;
+        assert!(actions.windows(2).all(|pair| pair[0].tick <= pair[1].tick));
+        let count: usize = (1..=TICKS)
+            .map(|tick| actions_get(0, tick).len() + actions_get(1, tick).len())
+            .sum();
+        assert_eq!(actions.len(), count);
+    }
+}
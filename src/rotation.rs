@@ -0,0 +1,291 @@
+use crate::resource::Tetromino;
+
+const SRS_ROTATION_TABLE: [[[(i8, i8); 4]; 4]; 7] = [
+    [
+        [(0, 2), (1, 2), (2, 2), (3, 2)],
+        [(2, 0), (2, 1), (2, 2), (2, 3)],
+        [(0, 1), (1, 1), (2, 1), (3, 1)],
+        [(1, 0), (1, 1), (1, 2), (1, 3)],
+    ], //I
+    [
+        [(0, 1), (0, 2), (1, 1), (2, 1)],
+        [(1, 0), (1, 1), (1, 2), (2, 2)],
+        [(0, 1), (1, 1), (2, 0), (2, 1)],
+        [(0, 0), (1, 0), (1, 1), (1, 2)],
+    ], //J
+    [
+        [(0, 1), (1, 1), (2, 1), (2, 2)],
+        [(1, 0), (1, 1), (1, 2), (2, 0)],
+        [(0, 0), (0, 1), (1, 1), (2, 1)],
+        [(0, 2), (1, 0), (1, 1), (1, 2)],
+    ], //L
+    [
+        [(1, 1), (1, 2), (2, 1), (2, 2)],
+        [(1, 1), (1, 2), (2, 1), (2, 2)],
+        [(1, 1), (1, 2), (2, 1), (2, 2)],
+        [(1, 1), (1, 2), (2, 1), (2, 2)],
+    ], //O
+    [
+        [(0, 1), (1, 1), (1, 2), (2, 2)],
+        [(1, 1), (1, 2), (2, 0), (2, 1)],
+        [(0, 0), (1, 0), (1, 1), (2, 1)],
+        [(0, 1), (0, 2), (1, 0), (1, 1)],
+    ], //S
+    [
+        [(0, 1), (1, 1), (1, 2), (2, 1)],
+        [(1, 0), (1, 1), (1, 2), (2, 1)],
+        [(0, 1), (1, 0), (1, 1), (2, 1)],
+        [(0, 1), (1, 0), (1, 1), (1, 2)],
+    ], //T
+    [
+        [(0, 2), (1, 1), (1, 2), (2, 1)],
+        [(1, 0), (1, 1), (2, 1), (2, 2)],
+        [(0, 1), (1, 0), (1, 1), (2, 0)],
+        [(0, 0), (0, 1), (1, 1), (1, 2)],
+    ], //Z
+];
+
+const CLASSIC_ROTATION_TABLE: [[[(i8, i8); 4]; 4]; 7] = [
+    [
+        [(0, 2), (1, 2), (2, 2), (3, 2)],
+        [(2, 1), (2, 2), (2, 3), (2, 4)],
+        [(0, 2), (1, 2), (2, 2), (3, 2)],
+        [(2, 1), (2, 2), (2, 3), (2, 4)],
+    ], //I
+    [
+        [(1, 2), (2, 2), (3, 2), (3, 1)],
+        [(2, 3), (2, 2), (1, 1), (2, 1)],
+        [(1, 3), (1, 2), (2, 2), (3, 2)],
+        [(2, 3), (3, 3), (2, 2), (2, 1)],
+    ], //J
+    [
+        [(1, 2), (2, 2), (3, 2), (1, 1)],
+        [(1, 3), (2, 3), (2, 2), (2, 1)],
+        [(3, 3), (1, 2), (2, 2), (3, 2)],
+        [(2, 3), (2, 2), (2, 1), (3, 1)],
+    ], //L
+    [
+        [(1, 2), (2, 2), (1, 1), (2, 1)],
+        [(1, 2), (2, 2), (1, 1), (2, 1)],
+        [(1, 2), (2, 2), (1, 1), (2, 1)],
+        [(1, 2), (2, 2), (1, 1), (2, 1)],
+    ], //O
+    [
+        [(2, 2), (3, 2), (1, 1), (2, 1)],
+        [(2, 3), (2, 2), (3, 2), (3, 1)],
+        [(2, 2), (3, 2), (1, 1), (2, 1)],
+        [(2, 3), (2, 2), (3, 2), (3, 1)],
+    ], //S
+    [
+        [(1, 2), (2, 2), (3, 2), (2, 1)],
+        [(2, 3), (1, 2), (2, 2), (2, 1)],
+        [(1, 2), (2, 2), (3, 2), (2, 3)],
+        [(2, 3), (2, 2), (3, 2), (2, 1)],
+    ], //T
+    [
+        [(1, 2), (2, 2), (2, 1), (3, 1)],
+        [(3, 3), (2, 2), (3, 2), (2, 1)],
+        [(1, 2), (2, 2), (2, 1), (3, 1)],
+        [(3, 3), (2, 2), (3, 2), (2, 1)],
+    ], //Z
+];
+
+const ARS_ROTATION_TABLE: [[[(i8, i8); 4]; 4]; 7] = [
+    [
+        [(0, 2), (1, 2), (2, 2), (3, 2)],
+        [(2, 0), (2, 1), (2, 2), (2, 3)],
+        [(0, 2), (1, 2), (2, 2), (3, 2)],
+        [(2, 0), (2, 1), (2, 2), (2, 3)],
+    ], //I
+    [
+        [(0, 1), (1, 1), (2, 1), (2, 0)],
+        [(1, 2), (1, 1), (0, 0), (1, 0)],
+        [(0, 1), (0, 0), (1, 0), (2, 0)],
+        [(1, 2), (2, 2), (1, 1), (1, 0)],
+    ], //J
+    [
+        [(0, 1), (1, 1), (2, 1), (0, 0)],
+        [(0, 2), (1, 2), (1, 1), (1, 0)],
+        [(2, 1), (0, 0), (1, 0), (2, 0)],
+        [(1, 2), (1, 1), (1, 0), (2, 0)],
+    ], //L
+    [
+        [(1, 0), (1, 1), (2, 0), (2, 1)],
+        [(1, 0), (1, 1), (2, 0), (2, 1)],
+        [(1, 0), (1, 1), (2, 0), (2, 1)],
+        [(1, 0), (1, 1), (2, 0), (2, 1)],
+    ], //O
+    [
+        [(1, 1), (2, 1), (0, 0), (1, 0)],
+        [(0, 2), (0, 1), (1, 1), (1, 0)],
+        [(1, 1), (2, 1), (0, 0), (1, 0)],
+        [(0, 2), (0, 1), (1, 1), (1, 0)],
+    ], //S
+    [
+        [(0, 1), (1, 1), (2, 1), (1, 0)],
+        [(1, 2), (0, 1), (1, 1), (1, 0)],
+        [(1, 1), (0, 0), (1, 0), (2, 0)],
+        [(1, 2), (1, 1), (2, 1), (1, 0)],
+    ], //T
+    [
+        [(0, 1), (1, 1), (1, 0), (2, 0)],
+        [(2, 2), (1, 1), (2, 1), (1, 0)],
+        [(0, 1), (1, 1), (1, 0), (2, 0)],
+        [(2, 2), (1, 1), (2, 1), (1, 0)],
+    ], //Z
+];
+
+const SRS_WALL_KICK_TABLE_JLSTZ: [[(i8, i8); 5]; 8] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+];
+
+const SRS_WALL_KICK_TABLE_I: [[(i8, i8); 5]; 8] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+];
+
+// SRS+ 180 kicks as used by TETR.IO, indexed by the starting rotation
+const SRS_WALL_KICK_TABLE_180: [[(i8, i8); 6]; 4] = [
+    [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+    [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+    [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+    [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+];
+
+const ARS_WALL_KICK_TABLE: [(i8, i8); 3] = [(0, 0), (1, 0), (-1, 0)];
+
+const NO_WALL_KICK_TABLE: [(i8, i8); 1] = [(0, 0)];
+
+/// Piece shapes for each orientation and the offsets tried when rotating between them.
+/// Rotation 0 is the spawn orientation and rotating right adds 1.
+pub trait RotationSystem: Send + Sync {
+    fn cells(&self, tetromino: Tetromino, rotation: u8) -> &[(i8, i8)];
+
+    /// Empty when the tetromino can't rotate at all.
+    fn kicks(&self, tetromino: Tetromino, from: u8, to: u8) -> &[(i8, i8)];
+
+    fn piece_count(&self) -> u8 {
+        7
+    }
+
+    /// Added to the ruleset spawn position.
+    fn spawn_offset(&self, _tetromino: Tetromino) -> (i8, i8) {
+        (0, 0)
+    }
+
+    /// The kick at `index` of the turn makes a T-spin full whatever corners are in front.
+    fn kick_full(&self, _tetromino: Tetromino, _from: u8, _to: u8, _index: usize) -> bool {
+        false
+    }
+}
+
+/// Super Rotation System from the guideline games, with SRS+ kicks for 180 rotations.
+pub struct Srs;
+
+impl RotationSystem for Srs {
+    fn cells(&self, tetromino: Tetromino, rotation: u8) -> &[(i8, i8)] {
+        &SRS_ROTATION_TABLE[tetromino.0 as usize][rotation as usize]
+    }
+
+    fn kicks(&self, tetromino: Tetromino, from: u8, to: u8) -> &[(i8, i8)] {
+        let table_idx = if (from + 1) % 4 == to {
+            (from * 2) as usize
+        } else {
+            (to * 2 + 1) as usize
+        };
+        match tetromino {
+            Tetromino::O => &NO_WALL_KICK_TABLE,
+            Tetromino::G => &[],
+            _ if (from + 2) % 4 == to => &SRS_WALL_KICK_TABLE_180[from as usize],
+            Tetromino::I => &SRS_WALL_KICK_TABLE_I[table_idx],
+            _ => &SRS_WALL_KICK_TABLE_JLSTZ[table_idx],
+        }
+    }
+
+    // the last JLSTZ kick of a quarter turn, the one a T-spin triple takes
+    fn kick_full(&self, tetromino: Tetromino, from: u8, to: u8, index: usize) -> bool {
+        let quarter = (from + 2) % 4 != to;
+        let jlstz = !matches!(tetromino, Tetromino::I | Tetromino::O | Tetromino::G);
+        quarter && jlstz && index == 4
+    }
+}
+
+/// Arika Rotation System from TGM, without the centre column exception.
+pub struct Ars;
+
+impl RotationSystem for Ars {
+    fn cells(&self, tetromino: Tetromino, rotation: u8) -> &[(i8, i8)] {
+        &ARS_ROTATION_TABLE[tetromino.0 as usize][rotation as usize]
+    }
+
+    fn kicks(&self, tetromino: Tetromino, _from: u8, _to: u8) -> &[(i8, i8)] {
+        match tetromino {
+            Tetromino::I | Tetromino::O => &NO_WALL_KICK_TABLE,
+            Tetromino::G => &[],
+            _ => &ARS_WALL_KICK_TABLE,
+        }
+    }
+}
+
+/// Nintendo rotation from the NES and Game Boy games, which never kicks.
+pub struct Classic;
+
+impl RotationSystem for Classic {
+    fn cells(&self, tetromino: Tetromino, rotation: u8) -> &[(i8, i8)] {
+        &CLASSIC_ROTATION_TABLE[tetromino.0 as usize][rotation as usize]
+    }
+
+    fn kicks(&self, tetromino: Tetromino, _from: u8, _to: u8) -> &[(i8, i8)] {
+        match tetromino {
+            Tetromino::G => &[],
+            _ => &NO_WALL_KICK_TABLE,
+        }
+    }
+
+    // turned pieces reach a row above the spawn orientation, the I two, and without kicks or
+    // hidden rows they would be stuck flat at the top
+    fn spawn_offset(&self, tetromino: Tetromino) -> (i8, i8) {
+        match tetromino {
+            Tetromino::I => (0, -2),
+            _ => (0, -1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resource::*;
+    use crate::ruleset::*;
+
+    #[test]
+    fn classic_pieces_turn_at_spawn() {
+        let ruleset = Ruleset::nes();
+        for tetromino in [
+            Tetromino::I,
+            Tetromino::J,
+            Tetromino::L,
+            Tetromino::S,
+            Tetromino::T,
+            Tetromino::Z,
+        ] {
+            for dir in [RotateDirection::Left, RotateDirection::Right] {
+                let mut board_map = BoardMap::new(&ruleset);
+                board_map.player_spawn(tetromino);
+                assert!(board_map.player_rotate(dir), "{tetromino:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::args::arg_get;
+use crate::bitboard::CollisionMasks;
+use crate::piece::PieceSetFile;
+use crate::resource::Tetromino;
+use crate::rotation::*;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+// frames per row for each NES level, level 29 and above is 1
+const NES_GRAVITY_TABLE: [u32; 29] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+];
+
+const NES_SCORE_TABLE: [u32; 4] = [40, 100, 300, 1200];
+
+pub const FRAME_RATE: f32 = 60.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RotationKind {
+    Srs,
+    Ars,
+    Classic,
+}
+
+impl RotationKind {
+    pub fn system(self) -> &'static dyn RotationSystem {
+        match self {
+            RotationKind::Srs => &Srs,
+            RotationKind::Ars => &Ars,
+            RotationKind::Classic => &Classic,
+        }
+    }
+
+    pub fn collision_masks(self) -> &'static CollisionMasks {
+        static MASKS: [OnceLock<CollisionMasks>; 3] = [const { OnceLock::new() }; 3];
+        MASKS[self as usize].get_or_init(|| CollisionMasks::new(self.system()))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Randomizer {
+    Random,
+    Bag,
+    Nes,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gravity {
+    Fixed(u32),
+    NesTable,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scoring {
+    Lines,
+    Nes,
+}
+
+/// Every rule the board, supplier and input handling follow. Timings are in frames at 60 fps.
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ruleset {
+    pub rotation: RotationKind,
+    pub wall_kick: bool,
+    pub rotate_180: bool,
+    pub randomizer: Randomizer,
+    pub preview_count: usize,
+    pub hold: bool,
+    pub ghost: bool,
+    pub hard_drop: bool,
+    pub lock_delay: Option<u32>,
+    pub gravity: Gravity,
+    pub scoring: Scoring,
+    pub spawn: (i8, i8),
+    /// Frames incoming versus garbage waits before it can rise.
+    pub garbage_delay: u32,
+    /// Piece set file replacing the standard tetrominoes and `rotation`.
+    pub pieces: Option<PieceSetFile>,
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Self {
+            rotation: RotationKind::Srs,
+            wall_kick: true,
+            rotate_180: true,
+            randomizer: Randomizer::Random,
+            preview_count: 5,
+            hold: false,
+            ghost: false,
+            hard_drop: true,
+            lock_delay: None,
+            gravity: Gravity::Fixed(60),
+            scoring: Scoring::Lines,
+            spawn: (3, 17),
+            garbage_delay: 60,
+            pieces: None,
+        }
+    }
+}
+
+impl Ruleset {
+    pub fn nes() -> Self {
+        Self {
+            rotation: RotationKind::Classic,
+            wall_kick: false,
+            rotate_180: false,
+            randomizer: Randomizer::Nes,
+            preview_count: 1,
+            hold: false,
+            ghost: false,
+            hard_drop: false,
+            lock_delay: None,
+            gravity: Gravity::NesTable,
+            scoring: Scoring::Nes,
+            spawn: (3, 17),
+            garbage_delay: 60,
+            pieces: None,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    pub fn rotation_system(&self) -> &dyn RotationSystem {
+        match &self.pieces {
+            Some(pieces) => pieces.piece_set.as_ref(),
+            None => self.rotation.system(),
+        }
+    }
+
+    pub fn collision_masks(&self) -> &CollisionMasks {
+        match &self.pieces {
+            Some(pieces) => pieces.piece_set.collision_masks(),
+            None => self.rotation.collision_masks(),
+        }
+    }
+
+    pub fn tile(&self, tetromino: Tetromino) -> &str {
+        match &self.pieces {
+            Some(pieces) => &pieces.piece_set.pieces[tetromino.0 as usize].tile,
+            None => match tetromino {
+                Tetromino::I => "blocks/LightBlue.png",
+                Tetromino::J => "blocks/Blue.png",
+                Tetromino::L => "blocks/Orange.png",
+                Tetromino::O => "blocks/Yellow.png",
+                Tetromino::S => "blocks/Green.png",
+                Tetromino::T => "blocks/Purple.png",
+                Tetromino::Z => "blocks/Red.png",
+                _ => "blocks/Ghost.png",
+            },
+        }
+    }
+
+    /// `--ruleset <path>` loads a file, `--nes` picks the NES rules, the default otherwise.
+    pub fn args_parse(args: &[String]) -> Result<Self, String> {
+        if let Some(path) = arg_get(args, "--ruleset") {
+            return Self::load(path).map_err(|e| format!("failed to load ruleset {path}: {e}"));
+        }
+        if args.iter().any(|arg| arg == "--nes") {
+            Ok(Self::nes())
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn gravity_frames(&self, level: u32) -> u32 {
+        match self.gravity {
+            Gravity::Fixed(frames) => frames,
+            Gravity::NesTable => NES_GRAVITY_TABLE
+                .get(level as usize)
+                .copied()
+                .unwrap_or(1),
+        }
+    }
+
+    pub fn line_score(&self, lines: u32, level: u32) -> u32 {
+        match self.scoring {
+            Scoring::Lines => lines,
+            Scoring::Nes => match lines {
+                1..=4 => NES_SCORE_TABLE[lines as usize - 1] * (level + 1),
+                _ => 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pieces_load_with_the_ruleset() {
+        let ruleset = Ruleset::load("assets/rulesets/pentomino.ron").expect("ruleset");
+        assert_eq!(ruleset.rotation_system().cells(Tetromino(0), 0).len(), 5);
+        // rulesets read back from a replay or the network bring their pieces along
+        let text = ron::to_string(&ruleset).expect("ruleset");
+        let read: Ruleset = ron::from_str(&text).expect("ruleset");
+        assert_eq!(
+            read.rotation_system().piece_count(),
+            ruleset.rotation_system().piece_count()
+        );
+        assert_eq!(read.tile(Tetromino(0)), ruleset.tile(Tetromino(0)));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::input::Bindings;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const SETTINGS_PATH: &str = "settings.ron";
+
+/// Player settings persisted between runs, `das` and `arr` are in frames at 60 fps.
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub bindings: Bindings<KeyCode>,
+    /// Keyboard halves for local versus, one per player.
+    pub versus_bindings: [Bindings<KeyCode>; 2],
+    pub gamepad_bindings: Bindings<GamepadButtonType>,
+    pub das: u32,
+    pub arr: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            bindings: Bindings::default(),
+            versus_bindings: [Bindings::versus_left(), Bindings::versus_right()],
+            gamepad_bindings: Bindings::default(),
+            das: 10,
+            arr: 2,
+        }
+    }
+}
+
+impl Settings {
+    /// Keyboard table of a local player, solo play uses the whole keyboard.
+    pub fn keyboard_bindings(&self, players: usize, player: usize) -> Option<&Bindings<KeyCode>> {
+        match players {
+            1 => Some(&self.bindings),
+            _ => self.versus_bindings.get(player),
+        }
+    }
+
+    pub fn load(path: &str) -> Self {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return Self::default();
+        };
+        ron::from_str(&text).unwrap_or_else(|e| {
+            warn!("failed to read {path}: {e}");
+            Self::default()
+        })
+    }
+
+    // written aside and renamed, so an interrupted write never loses the bindings
+    pub fn save(&self, path: &str) {
+        let text = match ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("failed to write {path}: {e}");
+                return;
+            }
+        };
+        let partial = format!("{path}.partial");
+        let written = std::fs::write(&partial, text).and_then(|()| std::fs::rename(&partial, path));
+        if let Err(e) = written {
+            warn!("failed to write {path}: {e}");
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::game::board_event_update;
+use crate::resource::*;
+use crate::TetrisSet;
+use bevy::audio::Volume;
+use bevy::prelude::*;
+
+/// Sound effects for the board events.
+pub struct TetrisAudioPlugin;
+
+impl Plugin for TetrisAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            board_sound_update
+                .in_set(TetrisSet::Events)
+                .before(board_event_update),
+        );
+    }
+}
+
+fn board_sound_update(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    boards: Query<&BoardMap>,
+) {
+    // one sound per kind and tick, however many boards it happened on
+    let event_any = |event| boards.iter().any(|board_map| board_map.event_get(event));
+    if event_any(BoardEvent::LineCompleted) {
+        commands.spawn(AudioBundle {
+            source: asset_server.load::<AudioSource>("sounds/Line.wav"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: Volume::new(0.2),
+                ..default()
+            },
+        });
+    }
+    if event_any(BoardEvent::TetrominoMoved) {
+        commands.spawn(AudioBundle {
+            source: asset_server.load::<AudioSource>("sounds/Move.wav"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: Volume::new(0.1),
+                ..default()
+            },
+        });
+    }
+    if event_any(BoardEvent::TetrominoPut) {
+        commands.spawn(AudioBundle {
+            source: asset_server.load::<AudioSource>("sounds/Put.wav"),
+            settings: PlaybackSettings {
+                mode: bevy::audio::PlaybackMode::Despawn,
+                volume: Volume::new(0.2),
+                ..default()
+            },
+        });
+    }
+}
@@ -0,0 +1,948 @@
+use crate::args::arg_get;
+use crate::attack::Garbage;
+use crate::bot::{piece_ticks, BotSettings, Plan};
+use crate::game::{board_event_update, replay_playing, Player};
+use crate::input::*;
+use crate::movegen::{landings, Landing};
+use crate::resource::*;
+use crate::ruleset::*;
+use crate::TetrisSet;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Rows of a TBP board, the ones above the visible 20 are always empty here.
+pub const BOARD_ROWS: usize = 40;
+/// How long a bot may take to say hello and to get ready.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Piece {
+    I,
+    O,
+    T,
+    L,
+    J,
+    S,
+    Z,
+}
+
+impl Piece {
+    /// `None` for the pieces of custom piece sets, TBP only knows the seven tetrominoes.
+    pub fn from_tetromino(tetromino: Tetromino) -> Option<Self> {
+        match tetromino {
+            Tetromino::I => Some(Piece::I),
+            Tetromino::O => Some(Piece::O),
+            Tetromino::T => Some(Piece::T),
+            Tetromino::L => Some(Piece::L),
+            Tetromino::J => Some(Piece::J),
+            Tetromino::S => Some(Piece::S),
+            Tetromino::Z => Some(Piece::Z),
+            _ => None,
+        }
+    }
+
+    pub fn tetromino(self) -> Tetromino {
+        match self {
+            Piece::I => Tetromino::I,
+            Piece::O => Tetromino::O,
+            Piece::T => Tetromino::T,
+            Piece::L => Tetromino::L,
+            Piece::J => Tetromino::J,
+            Piece::S => Tetromino::S,
+            Piece::Z => Tetromino::Z,
+        }
+    }
+
+    pub fn letter(self) -> char {
+        match self {
+            Piece::I => 'I',
+            Piece::O => 'O',
+            Piece::T => 'T',
+            Piece::L => 'L',
+            Piece::J => 'J',
+            Piece::S => 'S',
+            Piece::Z => 'Z',
+        }
+    }
+
+    pub fn from_letter(letter: char) -> Option<Self> {
+        match letter {
+            'I' => Some(Piece::I),
+            'O' => Some(Piece::O),
+            'T' => Some(Piece::T),
+            'L' => Some(Piece::L),
+            'J' => Some(Piece::J),
+            'S' => Some(Piece::S),
+            'Z' => Some(Piece::Z),
+            _ => None,
+        }
+    }
+
+    // cells around the center facing north, TBP turns them about it like SRS true rotation
+    fn cells(self) -> [(i8, i8); 4] {
+        match self {
+            Piece::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+            Piece::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            Piece::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+            Piece::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+            Piece::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+            Piece::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+            Piece::Z => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 4] = [
+        Orientation::North,
+        Orientation::East,
+        Orientation::South,
+        Orientation::West,
+    ];
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+impl From<TSpin> for Spin {
+    fn from(tspin: TSpin) -> Self {
+        match tspin {
+            TSpin::None => Spin::None,
+            TSpin::Mini => Spin::Mini,
+            TSpin::Full => Spin::Full,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct PieceLocation {
+    #[serde(rename = "type")]
+    pub piece: Piece,
+    pub orientation: Orientation,
+    pub x: i8,
+    pub y: i8,
+}
+
+impl PieceLocation {
+    /// Board cells as `(col, row)`, sorted like `Landing::cells`.
+    pub fn cells(&self) -> Vec<(i8, i8)> {
+        let mut cells: Vec<(i8, i8)> = self
+            .piece
+            .cells()
+            .iter()
+            .map(|&(x, y)| match self.orientation {
+                Orientation::North => (x, y),
+                Orientation::East => (y, -x),
+                Orientation::South => (-x, -y),
+                Orientation::West => (-y, x),
+            })
+            .map(|(x, y)| (self.x + x, self.y + y))
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    /// Where `landing` puts its piece, in its own orientation when the rotation system agrees
+    /// with SRS on it.
+    pub fn from_landing(landing: &Landing) -> Option<Self> {
+        let piece = Piece::from_tetromino(landing.tetromino)?;
+        let first = Orientation::ALL[landing.rotation as usize % 4];
+        std::iter::once(first)
+            .chain(Orientation::ALL)
+            .find_map(|orientation| {
+                // lined up on the lowest cell, both lists are sorted
+                let at_origin = PieceLocation {
+                    piece,
+                    orientation,
+                    x: 0,
+                    y: 0,
+                };
+                let (x, y) = at_origin.cells()[0];
+                let location = PieceLocation {
+                    x: landing.cells.first()?.0 - x,
+                    y: landing.cells.first()?.1 - y,
+                    ..at_origin
+                };
+                (location.cells() == landing.cells).then_some(location)
+            })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Move {
+    pub location: PieceLocation,
+    pub spin: Spin,
+}
+
+impl Move {
+    pub fn from_landing(landing: &Landing) -> Option<Self> {
+        Some(Self {
+            location: PieceLocation::from_landing(landing)?,
+            spin: landing.tspin.into(),
+        })
+    }
+
+    /// The landing putting the piece on the same cells, with the same spin if there is one.
+    pub fn landing_find<'a>(&self, landings: &'a [Landing]) -> Option<&'a Landing> {
+        let cells = self.location.cells();
+        let tetromino = self.location.piece.tetromino();
+        let mut found = landings
+            .iter()
+            .filter(|landing| landing.tetromino == tetromino && landing.cells == cells);
+        let first = found.clone().next();
+        found
+            .find(|landing| Spin::from(landing.tspin) == self.spin)
+            .or(first)
+    }
+}
+
+/// The state a bot starts thinking from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Start {
+    pub hold: Option<Piece>,
+    /// The falling piece first, then the previews.
+    pub queue: Vec<Piece>,
+    pub combo: u32,
+    pub back_to_back: bool,
+    /// `BOARD_ROWS` rows from the bottom, each cell empty or the letter of its piece, `G` for
+    /// garbage.
+    pub board: Vec<[Option<char>; 10]>,
+}
+
+impl Start {
+    /// `None` when a piece is not one of the seven tetrominoes.
+    pub fn new(
+        board_map: &BoardMap,
+        tetromino_supplier: &TetrominoSupplier,
+        ruleset: &Ruleset,
+        garbage: Option<&Garbage>,
+    ) -> Option<Self> {
+        let hold = match board_map.hold_get() {
+            Some(hold) => Some(Piece::from_tetromino(hold)?),
+            None => None,
+        };
+        let queue = queue_get(board_map, tetromino_supplier, ruleset)
+            .into_iter()
+            .map(Piece::from_tetromino)
+            .collect::<Option<Vec<Piece>>>()?;
+        let mut board_map = board_map.clone();
+        board_map.player_erase();
+        let board = (0..BOARD_ROWS)
+            .map(|row| {
+                std::array::from_fn(|col| {
+                    let tile = (row < 20).then(|| board_map.tile_get(row as u8, col as u8));
+                    tile.flatten()
+                        .map(|tile| Piece::from_tetromino(tile).map_or('G', Piece::letter))
+                })
+            })
+            .collect();
+        Some(Self {
+            hold,
+            queue,
+            combo: garbage
+                .and_then(Garbage::combo_get)
+                .map_or(0, |combo| combo + 1),
+            back_to_back: garbage.is_some_and(Garbage::b2b_get),
+            board,
+        })
+    }
+}
+
+/// The game as a bot sees it, kept up to date from the frontend's messages.
+pub struct BotState {
+    pub hold: Option<Piece>,
+    /// The falling piece first, then the previews.
+    pub queue: Vec<Piece>,
+    /// `BOARD_ROWS` rows from the bottom.
+    pub board: Vec<[Option<char>; 10]>,
+}
+
+impl BotState {
+    pub fn new(start: Start) -> Self {
+        let mut board = start.board;
+        board.resize(BOARD_ROWS, [None; 10]);
+        Self {
+            hold: start.hold,
+            queue: start.queue,
+            board,
+        }
+    }
+
+    pub fn fits(&self, location: &PieceLocation) -> bool {
+        location.cells().iter().all(|&(x, y)| {
+            (0..10).contains(&x)
+                && (0..BOARD_ROWS as i8).contains(&y)
+                && self.board[y as usize][x as usize].is_none()
+        })
+    }
+
+    /// Locks the piece where `mv` puts it and clears the full rows, taking it from hold when
+    /// it is not the falling piece.
+    pub fn play(&mut self, mv: &Move) {
+        let piece = mv.location.piece;
+        if !self.queue.is_empty() {
+            let current = self.queue.remove(0);
+            // with an empty hold the next piece comes out instead
+            if current != piece && self.hold.replace(current).is_none() && !self.queue.is_empty() {
+                self.queue.remove(0);
+            }
+        }
+        for (x, y) in mv.location.cells() {
+            if let Some(row) = self.board.get_mut(y as usize) {
+                row[x as usize] = Some(piece.letter());
+            }
+        }
+        self.board.retain(|row| row.iter().any(Option::is_none));
+        self.board.resize(BOARD_ROWS, [None; 10]);
+    }
+
+    /// The board with the falling piece spawned, `None` without one or with a block above the
+    /// 20 rows of the game, which it can't hold.
+    pub fn board_map(&self, ruleset: &Ruleset) -> Option<BoardMap> {
+        if self.board[20..].iter().flatten().any(Option::is_some) {
+            return None;
+        }
+        let mut board_map = BoardMap::new(ruleset);
+        for (row, cells) in self.board.iter().take(20).enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                if let Some(letter) = cell {
+                    let tile = Piece::from_letter(*letter).map_or(Tetromino::X, Piece::tetromino);
+                    board_map.tile_set(row as u8, col as u8, Some(tile));
+                }
+            }
+        }
+        board_map.hold_set(self.hold.map(Piece::tetromino));
+        board_map.player_spawn(self.queue.first()?.tetromino());
+        Some(board_map)
+    }
+}
+
+fn bot_send(message: &BotMessage) {
+    let mut stdout = std::io::stdout().lock();
+    let line = serde_json::to_string(message).expect("bot message");
+    let _ = writeln!(stdout, "{line}").and_then(|_| stdout.flush());
+}
+
+/// Runs a TBP bot program named `name` on stdin and stdout until the frontend quits, answering
+/// each `suggest` with the moves `suggest` picks, best first.
+pub fn bot_run(name: &str, mut suggest: impl FnMut(&BotState) -> Vec<Move>) {
+    bot_send(&BotMessage::Info {
+        name: name.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        author: "tetris_bevy".to_string(),
+        features: Vec::new(),
+    });
+    let mut state: Option<BotState> = None;
+    for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+        // messages it does not know are skipped, like the protocol asks
+        let Ok(message) = serde_json::from_str::<FrontendMessage>(&line) else {
+            continue;
+        };
+        match message {
+            FrontendMessage::Rules {} => bot_send(&BotMessage::Ready),
+            FrontendMessage::Start(start) => state = Some(BotState::new(start)),
+            FrontendMessage::Suggest => {
+                let moves = state.as_ref().map(&mut suggest).unwrap_or_default();
+                bot_send(&BotMessage::Suggestion { moves });
+            }
+            FrontendMessage::Play { mv } => {
+                if let Some(state) = &mut state {
+                    state.play(&mv);
+                }
+            }
+            FrontendMessage::NewPiece { piece } => {
+                if let Some(state) = &mut state {
+                    state.queue.push(piece);
+                }
+            }
+            FrontendMessage::Stop => state = None,
+            FrontendMessage::Quit => break,
+        }
+    }
+}
+
+/// Falling piece and the previews the player can see.
+pub fn queue_get(
+    board_map: &BoardMap,
+    tetromino_supplier: &TetrominoSupplier,
+    ruleset: &Ruleset,
+) -> Vec<Tetromino> {
+    let previews = ruleset.preview_count.min(tetromino_supplier.queue_len());
+    board_map
+        .piece_get()
+        .into_iter()
+        .chain((0..previews).map(|i| tetromino_supplier.get(i)))
+        .collect()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules {},
+    Start(Start),
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: Move,
+    },
+    NewPiece {
+        piece: Piece,
+    },
+    Stop,
+    Quit,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        #[serde(default)]
+        features: Vec<String>,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<Move>,
+    },
+}
+
+/// A bot program speaking TBP, a JSON message a line on its stdin and stdout.
+pub struct TbpProcess {
+    child: Child,
+    stdin: ChildStdin,
+    received: Mutex<Receiver<BotMessage>>,
+    /// From the bot's `info`.
+    pub name: String,
+}
+
+impl TbpProcess {
+    /// Runs `command` and waits for the bot to be ready for the standard rules.
+    pub fn start(command: &[String]) -> Result<Self, String> {
+        let (program, args) = command.split_first().ok_or("no bot command")?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{program}: {e}"))?;
+        let stdin = child.stdin.take().ok_or("no stdin")?;
+        let stdout = child.stdout.take().ok_or("no stdout")?;
+        let (sender, received) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // messages from newer versions of the protocol are skipped
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if sender.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("bot message ignored, {e}: {line}"),
+                }
+            }
+        });
+        let mut process = Self {
+            child,
+            stdin,
+            received: Mutex::new(received),
+            name: String::new(),
+        };
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        match process.receive_until(deadline)? {
+            BotMessage::Info {
+                name,
+                version,
+                author,
+                ..
+            } => {
+                info!("bot {name} {version} by {author}");
+                process.name = name;
+            }
+            message => return Err(format!("expected info, got {message:?}")),
+        }
+        process.send(&FrontendMessage::Rules {})?;
+        match process.receive_until(deadline)? {
+            BotMessage::Ready => Ok(process),
+            BotMessage::Error { reason } => Err(reason),
+            message => Err(format!("expected ready, got {message:?}")),
+        }
+    }
+
+    pub fn send(&mut self, message: &FrontendMessage) -> Result<(), String> {
+        let line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+        writeln!(self.stdin, "{line}")
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("bot gone: {e}"))
+    }
+
+    /// The next message if one came in.
+    pub fn receive(&self) -> Option<BotMessage> {
+        self.received.lock().ok()?.try_recv().ok()
+    }
+
+    pub fn receive_until(&self, deadline: Instant) -> Result<BotMessage, String> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let received = self.received.lock().map_err(|e| e.to_string())?;
+        received.recv_timeout(timeout).map_err(|e| match e {
+            RecvTimeoutError::Timeout => "bot timed out".to_string(),
+            RecvTimeoutError::Disconnected => "bot gone".to_string(),
+        })
+    }
+}
+
+impl Drop for TbpProcess {
+    fn drop(&mut self) {
+        let _ = self.send(&FrontendMessage::Quit);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// TBP bot programs for the bot boards, started before the app is built so a command that
+/// doesn't run is an error for the caller instead of a panic. The first app built takes them.
+#[derive(Clone)]
+pub struct TbpBots(Arc<Mutex<HashMap<u8, TbpProcess>>>);
+
+impl TbpBots {
+    /// Runs `command` once for each of `players`.
+    pub fn start(command: &[String], players: &[u8]) -> Result<Self, String> {
+        let processes = players
+            .iter()
+            .map(|&player| {
+                let process = TbpProcess::start(command)
+                    .map_err(|e| format!("failed to start bot {}: {e}", command.join(" ")))?;
+                Ok((player, process))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self(Arc::new(Mutex::new(processes))))
+    }
+
+    /// `--tbp "<command>"` runs the program for each of `players`, `None` without the flag.
+    pub fn args_parse(args: &[String], players: &[u8]) -> Result<Option<Self>, String> {
+        let Some(command) = arg_get(args, "--tbp") else {
+            return Ok(None);
+        };
+        let command: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+        Self::start(&command, players).map(Some)
+    }
+}
+
+// one process for each bot board, kept over versus rounds
+#[derive(Resource)]
+struct TbpClients(HashMap<u8, TbpClient>);
+
+struct TbpClient {
+    process: TbpProcess,
+    // whether the bot has a game to stop before the next start
+    started: bool,
+    failed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum TbpState {
+    #[default]
+    Idle,
+    Thinking,
+    Playing,
+}
+
+#[derive(Component, Default)]
+struct TbpBoard {
+    state: TbpState,
+    plan: Plan,
+    // suggestions asked for and not answered yet, all but the last are stale
+    requests: u32,
+    // the bot's queue, falling piece first
+    queue: Vec<Tetromino>,
+    // the stack the last move leaves, anything else means the bot has to start over
+    expected: Option<[u16; 20]>,
+}
+
+/// Hands the bot boards to the TBP programs of `bots`.
+pub(crate) fn frontend_add(app: &mut App, bots: &TbpBots) {
+    let processes = std::mem::take(&mut *bots.0.lock().unwrap());
+    let clients = processes
+        .into_iter()
+        .map(|(player, process)| {
+            let client = TbpClient {
+                process,
+                started: false,
+                failed: false,
+            };
+            (player, client)
+        })
+        .collect();
+    app.insert_resource(TbpClients(clients))
+        .add_systems(FixedUpdate, tbp_attach.in_set(TetrisSet::Input))
+        .add_systems(
+            FixedUpdate,
+            tbp_update
+                .in_set(TetrisSet::Events)
+                .before(board_event_update)
+                .run_if(not(replay_playing)),
+        );
+}
+
+fn tbp_attach(
+    mut commands: Commands,
+    boards: Query<(Entity, &Player), Added<Player>>,
+    settings: Res<BotSettings>,
+) {
+    for (entity, player) in &boards {
+        if settings.players.contains(&player.0) {
+            commands.entity(entity).insert(TbpBoard::default());
+        }
+    }
+}
+
+type TbpQuery<'a> = (
+    &'a Player,
+    &'a BoardMap,
+    &'a TetrominoSupplier,
+    Option<&'a Garbage>,
+    &'a mut TbpBoard,
+);
+
+fn tbp_update(
+    tick: Res<Tick>,
+    ruleset: Res<Ruleset>,
+    settings: Res<BotSettings>,
+    mut clients: ResMut<TbpClients>,
+    mut boards: Query<TbpQuery>,
+    mut game_actions: EventWriter<GameAction>,
+) {
+    let piece_ticks = piece_ticks(settings.pps);
+    let next_tick = tick.0 + 1;
+    for (player, board_map, tetromino_supplier, garbage, mut board) in &mut boards {
+        let Some(client) = clients.0.get_mut(&player.0) else {
+            continue;
+        };
+        if client.failed {
+            continue;
+        }
+        let game = TbpGame {
+            board_map,
+            tetromino_supplier,
+            garbage,
+            ruleset: &ruleset,
+        };
+        if let Err(e) = game.update(client, &mut board, next_tick, piece_ticks) {
+            error!(
+                "bot {} for player {}: {e}",
+                client.process.name,
+                player.0 + 1
+            );
+            client.failed = true;
+            continue;
+        }
+        if board.state == TbpState::Playing {
+            board.plan.send(
+                player.0,
+                next_tick,
+                piece_ticks,
+                &ruleset,
+                &mut game_actions,
+            );
+        }
+    }
+}
+
+struct TbpGame<'a> {
+    board_map: &'a BoardMap,
+    tetromino_supplier: &'a TetrominoSupplier,
+    garbage: Option<&'a Garbage>,
+    ruleset: &'a Ruleset,
+}
+
+impl TbpGame<'_> {
+    fn update(
+        &self,
+        client: &mut TbpClient,
+        board: &mut TbpBoard,
+        next_tick: u64,
+        piece_ticks: u64,
+    ) -> Result<(), String> {
+        while let Some(message) = client.process.receive() {
+            match message {
+                BotMessage::Error { reason } => return Err(reason),
+                BotMessage::Suggestion { moves } => {
+                    board.requests = board.requests.saturating_sub(1);
+                    if board.requests == 0 && board.state == TbpState::Thinking {
+                        self.play(client, board, &moves, next_tick, piece_ticks)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if self.board_map.event_get(BoardEvent::TetrominoPut) {
+            if board.state != TbpState::Playing {
+                // locked before the bot made up its mind
+                board.expected = None;
+            }
+            board.state = TbpState::Idle;
+        }
+        if board.state != TbpState::Idle || self.board_map.piece_get().is_none() {
+            return Ok(());
+        }
+
+        let queue = queue_get(self.board_map, self.tetromino_supplier, self.ruleset);
+        let mut rows = self.board_map.clone();
+        rows.player_erase();
+        let in_sync = client.started
+            && board.expected == Some(rows.rows_get())
+            && queue.starts_with(&board.queue);
+        if in_sync {
+            for &tetromino in &queue[board.queue.len()..] {
+                let piece = Piece::from_tetromino(tetromino).ok_or("not a tetromino")?;
+                client.process.send(&FrontendMessage::NewPiece { piece })?;
+            }
+        } else {
+            // garbage came up, or a new round
+            if client.started {
+                client.process.send(&FrontendMessage::Stop)?;
+            }
+            let start = Start::new(
+                self.board_map,
+                self.tetromino_supplier,
+                self.ruleset,
+                self.garbage,
+            )
+            .ok_or("TBP only has the seven tetrominoes")?;
+            client.process.send(&FrontendMessage::Start(start))?;
+            client.started = true;
+        }
+        board.queue = queue;
+        client.process.send(&FrontendMessage::Suggest)?;
+        board.requests += 1;
+        board.state = TbpState::Thinking;
+        Ok(())
+    }
+
+    // the first suggested move that can be reached, a hard drop when none can
+    fn play(
+        &self,
+        client: &mut TbpClient,
+        board: &mut TbpBoard,
+        moves: &[Move],
+        next_tick: u64,
+        piece_ticks: u64,
+    ) -> Result<(), String> {
+        let Some(current) = self.board_map.piece_get() else {
+            return Ok(());
+        };
+        let mut held = self.board_map.clone();
+        held.event_reset();
+        let mut held_consumed = 1;
+        if self.board_map.hold_available() {
+            held.player_hold();
+            if held.piece_get().is_none() {
+                held.player_spawn(self.tetromino_supplier.get(0));
+                held_consumed = 2;
+            }
+        }
+        let found = moves.iter().find_map(|mv| {
+            let piece = mv.location.piece.tetromino();
+            if piece == current {
+                let mut board_map = self.board_map.clone();
+                board_map.event_reset();
+                let landing = mv.landing_find(&landings(&board_map))?.clone();
+                Some((*mv, Vec::new(), board_map, landing, 1))
+            } else if held.piece_get() == Some(piece) && self.board_map.hold_available() {
+                let landing = mv.landing_find(&landings(&held))?.clone();
+                Some((
+                    *mv,
+                    vec![Action::Hold],
+                    held.clone(),
+                    landing,
+                    held_consumed,
+                ))
+            } else {
+                None
+            }
+        });
+        let (mv, mut actions, mut placed, landing, consumed) = match found {
+            Some(found) => found,
+            None => {
+                warn!("bot {} suggested no reachable move", client.process.name);
+                let mut board_map = self.board_map.clone();
+                board_map.event_reset();
+                // breadth first, the first landing is the plain hard drop
+                let landing = landings(&board_map)
+                    .into_iter()
+                    .next()
+                    .ok_or("no landing")?;
+                let mv = Move::from_landing(&landing).ok_or("not a tetromino")?;
+                (mv, Vec::new(), board_map, landing, 1)
+            }
+        };
+        landing.put(&mut placed);
+        actions.extend(&landing.actions);
+        board.expected = Some(placed.rows_get());
+        board.queue.drain(..consumed.min(board.queue.len()));
+        board
+            .plan
+            .set(actions, next_tick, piece_ticks, self.ruleset);
+        board.state = TbpState::Playing;
+        client.process.send(&FrontendMessage::Play { mv })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIECES: [Piece; 7] = [
+        Piece::I,
+        Piece::O,
+        Piece::T,
+        Piece::L,
+        Piece::J,
+        Piece::S,
+        Piece::Z,
+    ];
+
+    // an empty board and one with a T-spin slot on the left
+    fn boards() -> Vec<BoardMap> {
+        let ruleset = Ruleset {
+            hold: true,
+            ..Ruleset::default()
+        };
+        let empty = BoardMap::new(&ruleset);
+        let mut stack = empty.clone();
+        for col in 0..10 {
+            if col != 1 {
+                stack.tile_set(0, col, Some(Tetromino::X));
+            }
+            if !(0..3).contains(&col) {
+                stack.tile_set(1, col, Some(Tetromino::X));
+            }
+        }
+        stack.tile_set(2, 0, Some(Tetromino::X));
+        vec![empty, stack]
+    }
+
+    fn shape(cells: impl IntoIterator<Item = (i8, i8)>) -> Vec<(i8, i8)> {
+        let mut cells: Vec<(i8, i8)> = cells.into_iter().collect();
+        let left = cells.iter().map(|&(x, _)| x).min().unwrap_or(0);
+        let bottom = cells.iter().map(|&(_, y)| y).min().unwrap_or(0);
+        for cell in &mut cells {
+            *cell = (cell.0 - left, cell.1 - bottom);
+        }
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn orientations_turn_like_the_rotation_system() {
+        let ruleset = Ruleset::default();
+        let system = ruleset.rotation_system();
+        for piece in PIECES {
+            for (rotation, orientation) in Orientation::ALL.into_iter().enumerate() {
+                let location = PieceLocation {
+                    piece,
+                    orientation,
+                    x: 4,
+                    y: 10,
+                };
+                let cells = system.cells(piece.tetromino(), rotation as u8);
+                assert_eq!(
+                    shape(location.cells()),
+                    shape(cells.iter().copied()),
+                    "{location:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn locations_round_trip_through_landings() {
+        for piece in PIECES {
+            for mut board_map in boards() {
+                board_map.player_spawn(piece.tetromino());
+                let landings = landings(&board_map);
+                assert!(!landings.is_empty(), "{piece:?}");
+                for landing in &landings {
+                    let location = PieceLocation::from_landing(landing).expect("location");
+                    assert_eq!(location.piece, piece);
+                    assert_eq!(location.cells(), landing.cells, "{location:?}");
+                    // the standard rules rotate like SRS
+                    let orientation = Orientation::ALL[landing.rotation as usize % 4];
+                    assert_eq!(location.orientation, orientation, "{location:?}");
+
+                    let mv = Move::from_landing(landing).expect("move");
+                    let found = mv.landing_find(&landings).expect("landing");
+                    assert_eq!(found.cells, landing.cells, "{mv:?}");
+                    assert_eq!(Spin::from(found.tspin), mv.spin, "{mv:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn board_map_refuses_blocks_above_the_game() {
+        let ruleset = Ruleset::default();
+        let mut state = BotState::new(Start {
+            hold: None,
+            queue: vec![Piece::T],
+            combo: 0,
+            back_to_back: false,
+            board: Vec::new(),
+        });
+        state.board[19][0] = Some('G');
+        assert!(state.board_map(&ruleset).is_some());
+        state.board[20][0] = Some('G');
+        assert!(state.board_map(&ruleset).is_none());
+    }
+
+    #[test]
+    fn t_spins_survive_the_round_trip() {
+        let mut board_map = boards().pop().expect("board");
+        board_map.player_spawn(Tetromino::T);
+        let landings = landings(&board_map);
+        let spins: Vec<Move> = landings
+            .iter()
+            .filter(|landing| landing.tspin != TSpin::None)
+            .filter_map(Move::from_landing)
+            .collect();
+        assert!(!spins.is_empty());
+        for mv in spins {
+            let found = mv.landing_find(&landings).expect("landing");
+            assert_eq!(Spin::from(found.tspin), mv.spin);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! The genetic algorithm behind `tetris_tune`, which evolves the bot's evaluation weights.
+//! Every candidate plays the same seeds and scores the lines it clears.
+
+use crate::args::arg_parse_some;
+use crate::bot::{best_landing, Weights};
+use crate::env::{ActionSpace, Env};
+use crate::ruleset::Ruleset;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// share of each generation kept as it is
+const ELITE: f32 = 0.25;
+/// Candidates drawn for each parent, and the smallest population.
+pub const TOURNAMENT: usize = 4;
+const MUTATION_RATE: f64 = 0.3;
+const MUTATION: f32 = 0.2;
+
+fn genes_get(weights: &Weights) -> [f32; 5] {
+    [
+        weights.aggregate_height,
+        weights.holes,
+        weights.bumpiness,
+        weights.wells,
+        weights.lines,
+    ]
+}
+
+// the score is linear in the weights, so only their direction matters
+fn weights_get(genes: [f32; 5]) -> Weights {
+    let length = genes.iter().map(|gene| gene * gene).sum::<f32>().sqrt();
+    let [aggregate_height, holes, bumpiness, wells, lines] =
+        genes.map(|gene| gene / length.max(f32::EPSILON));
+    Weights {
+        aggregate_height,
+        holes,
+        bumpiness,
+        wells,
+        lines,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Candidate {
+    pub weights: Weights,
+    /// Mean lines over the seeds, `None` until played.
+    pub fitness: Option<f32>,
+}
+
+/// Everything a run needs to go on where it stopped.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Generations done.
+    pub generation: u32,
+    pub seeds: u64,
+    pub pieces: u32,
+    pub population: Vec<Candidate>,
+    pub best: Candidate,
+}
+
+impl Checkpoint {
+    /// A first generation of `size` candidates, at least [`TOURNAMENT`], to play `seeds` games
+    /// of `pieces` pieces each: the default weights and random ones from `run_seed`.
+    pub fn new(size: usize, seeds: u64, pieces: u32, run_seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(run_seed);
+        let population: Vec<Candidate> = std::iter::once(Weights::default())
+            .chain((1..size.max(TOURNAMENT)).map(|_| weights_get(random_genes(&mut rng))))
+            .map(|weights| Candidate {
+                weights,
+                fitness: None,
+            })
+            .collect();
+        Self {
+            generation: 0,
+            seeds,
+            pieces,
+            best: population[0].clone(),
+            population,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// A resumed run goes on with the population and games it started with, so
+    /// `--population`, `--seeds` and `--pieces` may only repeat them.
+    pub fn args_check(&self, args: &[String]) -> Result<(), String> {
+        let population = arg_parse_some::<usize>(args, "--population")?;
+        let pieces = arg_parse_some::<u32>(args, "--pieces")?;
+        let given = [
+            (
+                "--population",
+                population.map(|size| size.max(TOURNAMENT) as u64),
+                self.population.len() as u64,
+            ),
+            ("--seeds", arg_parse_some(args, "--seeds")?, self.seeds),
+            ("--pieces", pieces.map(u64::from), self.pieces as u64),
+        ];
+        for (name, value, saved) in given {
+            if value.is_some_and(|value| value != saved) {
+                return Err(format!(
+                    "{name} differs from the checkpoint's {saved}, leave it out to resume or \
+                     pick another --checkpoint"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // written aside and renamed, so stopping the run never leaves half a file
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        let partial = format!("{path}.partial");
+        std::fs::write(&partial, text).map_err(|e| e.to_string())?;
+        std::fs::rename(&partial, path).map_err(|e| e.to_string())
+    }
+
+    /// Plays the candidates without a fitness, in parallel, and sorts the population fittest
+    /// first. True when the fittest beats the best so far, which it then becomes.
+    pub fn generation_play(&mut self, ruleset: &Ruleset) -> bool {
+        let (seeds, pieces) = (self.seeds, self.pieces);
+        // every game of the generation on its own, the seeds are the same for all
+        let games: Vec<(usize, u64)> = self
+            .population
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.fitness.is_none())
+            .flat_map(|(i, _)| (0..seeds).map(move |seed| (i, seed)))
+            .collect();
+        let lines: Vec<(usize, u32)> = games
+            .par_iter()
+            .map(|&(i, seed)| {
+                let weights = &self.population[i].weights;
+                (i, game_lines(ruleset, weights, seed, pieces))
+            })
+            .collect();
+        for (i, candidate) in self.population.iter_mut().enumerate() {
+            if candidate.fitness.is_none() {
+                let sum: u32 = lines.iter().filter(|(j, _)| *j == i).map(|(_, l)| l).sum();
+                candidate.fitness = Some(sum as f32 / seeds.max(1) as f32);
+            }
+        }
+        self.population
+            .sort_by(|a, b| b.fitness.partial_cmp(&a.fitness).expect("fitness"));
+        let fittest = &self.population[0];
+        let improved = self
+            .best
+            .fitness
+            .is_none_or(|best| fittest.fitness > Some(best));
+        if improved {
+            self.best = fittest.clone();
+        }
+        improved
+    }
+
+    /// Counts the played generation and breeds the next: the elite stays, the rest are
+    /// children of tournament winners, from a generator seeded by the generation so a resumed
+    /// run breeds like an uninterrupted one.
+    pub fn generation_next(&mut self, run_seed: u64) {
+        self.generation += 1;
+        let mut rng = StdRng::seed_from_u64(run_seed ^ ((self.generation as u64) << 32));
+        let elite = ((self.population.len() as f32 * ELITE) as usize).max(1);
+        let children: Vec<Candidate> = (elite..self.population.len())
+            .map(|_| {
+                let a = tournament(&self.population, &mut rng);
+                let b = tournament(&self.population, &mut rng);
+                child(a, b, &mut rng)
+            })
+            .collect();
+        self.population.truncate(elite);
+        self.population.extend(children);
+    }
+}
+
+// lines the bot clears in `pieces` pieces dealt from `seed`, fewer if it tops out
+fn game_lines(ruleset: &Ruleset, weights: &Weights, seed: u64, pieces: u32) -> u32 {
+    let mut env = Env::new(ruleset, ActionSpace::Placement);
+    let mut observation = env.reset(seed);
+    for _ in 0..pieces {
+        let Some((hold, landing)) = best_landing(env.board_map(), &observation.queue, weights)
+        else {
+            break;
+        };
+        let Some(action) = env.placements().iter().position(|placement| {
+            placement.hold == hold
+                && placement.landing.cells == landing.cells
+                && placement.landing.tspin == landing.tspin
+        }) else {
+            break;
+        };
+        let (next, _, done, _) = env.step(action);
+        if done {
+            break;
+        }
+        observation = next;
+    }
+    env.board_map().lines_get()
+}
+
+fn random_genes(rng: &mut StdRng) -> [f32; 5] {
+    std::array::from_fn(|_| rng.gen_range(-1.0..1.0))
+}
+
+// picks the fittest of a few at random
+fn tournament<'a>(population: &'a [Candidate], rng: &mut StdRng) -> &'a Candidate {
+    population
+        .choose_multiple(rng, TOURNAMENT)
+        .max_by(|a, b| a.fitness.partial_cmp(&b.fitness).expect("fitness"))
+        .expect("empty population")
+}
+
+// the parents averaged by fitness, sometimes with one gene pushed a little
+fn child(a: &Candidate, b: &Candidate, rng: &mut StdRng) -> Candidate {
+    let (a_share, b_share) = match (a.fitness.unwrap_or(0.), b.fitness.unwrap_or(0.)) {
+        (0., 0.) => (1., 1.),
+        shares => shares,
+    };
+    let (a_genes, b_genes) = (genes_get(&a.weights), genes_get(&b.weights));
+    let mut genes: [f32; 5] = std::array::from_fn(|i| {
+        (a_genes[i] * a_share + b_genes[i] * b_share) / (a_share + b_share)
+    });
+    if rng.gen_bool(MUTATION_RATE) {
+        let i = rng.gen_range(0..genes.len());
+        genes[i] += rng.gen_range(-MUTATION..MUTATION);
+    }
+    Candidate {
+        weights: weights_get(genes),
+        fitness: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tune::*;
+
+    const RUN_SEED: u64 = 3;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn genes(candidates: &[Candidate]) -> Vec<[f32; 5]> {
+        candidates
+            .iter()
+            .map(|candidate| genes_get(&candidate.weights))
+            .collect()
+    }
+
+    #[test]
+    fn resuming_refuses_other_params() {
+        let checkpoint = Checkpoint::new(6, 2, 50, RUN_SEED);
+        for same in ["tune", "tune --population 6 --seeds 2 --pieces 50 --generations 9"] {
+            assert!(checkpoint.args_check(&args(same)).is_ok(), "{same}");
+        }
+        for other in ["--population 8", "--seeds 3", "--pieces 40"] {
+            let e = checkpoint
+                .args_check(&args(&format!("tune {other}")))
+                .expect_err(other);
+            assert!(e.starts_with(other.split(' ').next().unwrap()), "{e}");
+        }
+        // a population below the tournament size was raised to it
+        let small = Checkpoint::new(2, 2, 50, RUN_SEED);
+        assert_eq!(small.population.len(), TOURNAMENT);
+        assert!(small.args_check(&args("tune --population 2")).is_ok());
+    }
+
+    #[test]
+    fn children_mix_the_parents_and_stay_normalized() {
+        let mut rng = StdRng::seed_from_u64(RUN_SEED);
+        let a = Candidate {
+            weights: weights_get([1., 0., 0., 0., 0.]),
+            fitness: Some(3.),
+        };
+        let b = Candidate {
+            weights: weights_get([0., 1., 0., 0., 0.]),
+            fitness: Some(1.),
+        };
+        for _ in 0..20 {
+            let child = child(&a, &b, &mut rng);
+            let genes = genes_get(&child.weights);
+            let length: f32 = genes.iter().map(|gene| gene * gene).sum::<f32>().sqrt();
+            assert!((length - 1.).abs() < 1e-4, "{genes:?}");
+            // the fitter parent counts more
+            assert!(genes[0] > genes[1], "{genes:?}");
+            assert!(child.fitness.is_none());
+        }
+    }
+
+    #[test]
+    fn resumed_runs_go_on_like_uninterrupted_ones() {
+        let ruleset = Ruleset::default();
+        let path = std::env::temp_dir().join(format!("tetris_tune_{}.ron", std::process::id()));
+        let path = path.to_str().expect("temp path");
+        let mut uninterrupted = Checkpoint::new(TOURNAMENT, 1, 8, RUN_SEED);
+        let mut stopped = Checkpoint::new(TOURNAMENT, 1, 8, RUN_SEED);
+        for checkpoint in [&mut uninterrupted, &mut stopped] {
+            checkpoint.generation_play(&ruleset);
+            checkpoint.generation_next(RUN_SEED);
+        }
+        stopped.save(path).expect("save");
+        let mut resumed = Checkpoint::load(path).expect("load");
+        std::fs::remove_file(path).expect("remove");
+        assert_eq!(resumed.generation, 1);
+        assert_eq!(genes(&resumed.population), genes(&stopped.population));
+        for checkpoint in [&mut uninterrupted, &mut resumed] {
+            checkpoint.generation_play(&ruleset);
+            checkpoint.generation_next(RUN_SEED);
+        }
+        assert_eq!(resumed.generation, 2);
+        assert_eq!(genes(&resumed.population), genes(&uninterrupted.population));
+        assert_eq!(resumed.best.fitness, uninterrupted.best.fitness);
+    }
+}
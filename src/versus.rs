@@ -0,0 +1,556 @@
+use crate::attack::Garbage;
+use crate::game::{tick_update, BoardBundle, Confirmed, PendingActions, Player, Stall};
+use crate::input::Tick;
+use crate::net::NetClient;
+use crate::render::{BOARD_HEIGHT, BOARD_WIDTH, TILE_SIZE};
+use crate::replay::Replay;
+use crate::resource::*;
+use crate::ruleset::Ruleset;
+use crate::{Screen, TetrisSet};
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+
+const COUNTDOWN_TICKS: u32 = 180;
+const METER_WIDTH: f32 = 24.;
+const METER_READY_COLOR: Color = Color::srgb(0.9, 0.1, 0.1);
+const METER_WAITING_COLOR: Color = Color::srgb(0.9, 0.8, 0.1);
+
+/// Rounds won by each player of a local versus match.
+#[derive(Resource, Clone)]
+pub struct Match {
+    pub best_of: u8,
+    pub wins: Vec<u8>,
+    pub round: u64,
+    /// Boards still standing this round.
+    pub alive: Vec<bool>,
+    /// Winner of the last round, `None` for a draw.
+    pub round_winner: Option<u8>,
+    /// Tick the round ended on, the result shows once it is confirmed.
+    pub end_tick: Option<u64>,
+    /// The round is counted and the result showing.
+    pub over: bool,
+}
+
+impl Match {
+    pub fn new(best_of: u8, players: u8) -> Self {
+        Self {
+            best_of,
+            wins: vec![0; players as usize],
+            round: 0,
+            alive: vec![true; players as usize],
+            round_winner: None,
+            end_tick: None,
+            over: false,
+        }
+    }
+
+    pub fn winner_get(&self) -> Option<u8> {
+        let wins_needed = self.best_of / 2 + 1;
+        self.wins
+            .iter()
+            .position(|&wins| wins >= wins_needed)
+            .map(|player| player as u8)
+    }
+
+    /// Every round deals new pieces, the same for every board.
+    pub fn round_seed(&self, seed: u64) -> u64 {
+        seed.wrapping_add(self.round)
+    }
+}
+
+/// At most one board is left standing.
+pub(crate) fn round_over(alive: &[bool]) -> bool {
+    alive.iter().filter(|&&alive| alive).count() <= 1
+}
+
+/// Sent when Enter or Start is pressed on the result screen.
+#[derive(Event)]
+pub(crate) struct RoundReady;
+
+/// Starts the next round, right after `RoundReady` locally or once the server says so online.
+#[derive(Event)]
+pub(crate) struct RoundStart;
+
+#[derive(Resource, Default)]
+struct Countdown(u32);
+
+#[derive(Component)]
+struct CountdownText;
+
+// bar left of a board with the incoming garbage, ready lines drawn over the waiting ones
+#[derive(Component)]
+struct GarbageMeter {
+    ready: bool,
+}
+
+/// Local versus: a countdown before each round, line clears attack the other boards with
+/// garbage, the last board standing wins the round, best of `best_of` rounds wins the match.
+pub struct TetrisVersusPlugin {
+    pub best_of: u8,
+    pub players: u8,
+}
+
+impl Plugin for TetrisVersusPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(mut replay) = app.world_mut().get_resource_mut::<Replay>() {
+            replay.best_of = Some(self.best_of);
+        }
+        app.insert_resource(Match::new(self.best_of, self.players))
+            .init_resource::<Countdown>()
+            .add_event::<RoundReady>()
+            .add_event::<RoundStart>()
+            .add_systems(Startup, versus_startup)
+            .add_systems(FixedUpdate, garbage_setup.in_set(TetrisSet::Input))
+            .add_systems(
+                FixedUpdate,
+                round_end
+                    .in_set(TetrisSet::Input)
+                    .before(tick_update)
+                    .run_if(in_state(Screen::Game)),
+            )
+            .add_systems(
+                FixedUpdate,
+                countdown_update
+                    .in_set(TetrisSet::Input)
+                    .run_if(in_state(Screen::Countdown)),
+            )
+            .add_systems(
+                Update,
+                (garbage_meter_setup, garbage_meter_update)
+                    .chain()
+                    .in_set(TetrisSet::Render),
+            )
+            .add_systems(OnEnter(Screen::Countdown), countdown_setup)
+            .add_systems(OnEnter(Screen::Result), result_setup)
+            .add_systems(
+                Update,
+                (
+                    result_update,
+                    round_ready_local.run_if(not(resource_exists::<NetClient>)),
+                    round_start,
+                    round_input_reset,
+                )
+                    .chain()
+                    .run_if(in_state(Screen::Result)),
+            );
+    }
+}
+
+fn versus_startup(mut next_screen: ResMut<NextState<Screen>>) {
+    next_screen.set(Screen::Countdown);
+}
+
+pub(crate) fn garbage_setup(
+    mut commands: Commands,
+    boards: Query<(Entity, &Player), Without<Garbage>>,
+    versus_match: Res<Match>,
+    replay: Res<Replay>,
+) {
+    for (board, player) in &boards {
+        let seed = versus_match.round_seed(replay.seed) ^ ((player.0 as u64 + 1) << 32);
+        commands.entity(board).insert(Garbage::new(seed));
+    }
+}
+
+/// Raises ready garbage under the pieces put this tick that cleared nothing and queues the
+/// attacks on the other boards.
+pub(crate) fn garbage_exchange(
+    tick: u64,
+    ruleset: &Ruleset,
+    boards: &mut [(u8, &mut BoardMap, &mut Garbage)],
+) {
+    // attacks on one board queue up in player order, however the boards are stored
+    boards.sort_by_key(|(player, _, _)| *player);
+    let mut attacks = Vec::new();
+    for (player, board_map, garbage) in boards.iter_mut() {
+        if !board_map.event_get(BoardEvent::TetrominoPut) {
+            continue;
+        }
+        let Some(line_clear) = board_map.clear_get() else {
+            continue;
+        };
+        // garbage only rises under a piece that cleared nothing
+        if line_clear.lines == 0 {
+            for (lines, hole) in garbage.ready_take(tick) {
+                board_map.garbage_add(lines, hole);
+            }
+        }
+        let lines = garbage.clear_apply(line_clear);
+        if lines > 0 {
+            attacks.push((*player, lines));
+        }
+    }
+    let ready_tick = tick + ruleset.garbage_delay as u64;
+    for (attacker, lines) in attacks {
+        for (player, _, garbage) in boards.iter_mut() {
+            if *player != attacker {
+                garbage.receive(lines, ready_tick);
+            }
+        }
+    }
+}
+
+fn garbage_meter_setup(mut commands: Commands, boards: Query<Entity, Added<Garbage>>) {
+    for board in &boards {
+        commands.entity(board).with_children(|parent| {
+            for (ready, color) in [(false, METER_WAITING_COLOR), (true, METER_READY_COLOR)] {
+                parent.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color,
+                            anchor: Anchor::BottomCenter,
+                            custom_size: Some(Vec2::new(METER_WIDTH, 0.)),
+                            ..default()
+                        },
+                        transform: Transform::from_xyz(
+                            -BOARD_WIDTH * 0.5 - METER_WIDTH * 0.5,
+                            -BOARD_HEIGHT * 0.5,
+                            if ready { 3. } else { 2. },
+                        ),
+                        ..default()
+                    },
+                    GarbageMeter { ready },
+                ));
+            }
+        });
+    }
+}
+
+fn garbage_meter_update(
+    mut query: Query<(&Parent, &GarbageMeter, &mut Sprite)>,
+    boards: Query<&Garbage>,
+    tick: Res<Tick>,
+) {
+    for (parent, garbage_meter, mut sprite) in &mut query {
+        let Ok(garbage) = boards.get(parent.get()) else {
+            continue;
+        };
+        let (ready, total) = garbage.pending_get(tick.0);
+        let lines = if garbage_meter.ready { ready } else { total };
+        let height = lines.min(20) as f32 * TILE_SIZE;
+        sprite.custom_size = Some(Vec2::new(METER_WIDTH, height));
+    }
+}
+
+// no tick runs past the end of a round, online the result waits until the end is confirmed
+pub(crate) fn round_end(
+    confirmed: Res<Confirmed>,
+    mut versus_match: ResMut<Match>,
+    mut stall: ResMut<Stall>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let Some(end_tick) = versus_match.end_tick else {
+        return;
+    };
+    stall.0 = true;
+    if versus_match.over || end_tick > confirmed.0 {
+        return;
+    }
+    let winner = versus_match
+        .alive
+        .iter()
+        .position(|&alive| alive)
+        .map(|player| player as u8);
+    if let Some(winner) = winner {
+        versus_match.wins[winner as usize] += 1;
+    }
+    versus_match.round_winner = winner;
+    versus_match.over = true;
+    next_screen.set(Screen::Result);
+}
+
+fn countdown_setup(mut commands: Commands, mut countdown: ResMut<Countdown>) {
+    countdown.0 = COUNTDOWN_TICKS;
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 240.0,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(40.0),
+            left: Val::Percent(48.0),
+            ..default()
+        }),
+        CountdownText,
+        StateScoped(Screen::Countdown),
+    ));
+}
+
+fn countdown_update(
+    mut countdown: ResMut<Countdown>,
+    mut query: Query<&mut Text, With<CountdownText>>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    countdown.0 = countdown.0.saturating_sub(1);
+    if let Ok(mut text) = query.get_single_mut() {
+        text.sections[0].value = countdown.0.div_ceil(60).to_string();
+    }
+    if countdown.0 == 0 {
+        next_screen.set(Screen::Game);
+    }
+}
+
+fn result_setup(mut commands: Commands, versus_match: Res<Match>) {
+    let wins: Vec<String> = versus_match.wins.iter().map(u8::to_string).collect();
+    let title = match (versus_match.winner_get(), versus_match.round_winner) {
+        (Some(winner), _) => format!("PLAYER {} WINS THE MATCH", winner + 1),
+        (None, Some(winner)) => format!("PLAYER {} WINS THE ROUND", winner + 1),
+        (None, None) => "DRAW".to_string(),
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::srgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            StateScoped(Screen::Result),
+        ))
+        .with_children(|parent| {
+            parent.spawn(
+                TextBundle::from_section(
+                    format!(
+                        "{}\n\n{}  (best of {})\n\nEnter / Start: {}",
+                        title,
+                        wins.join(" - "),
+                        versus_match.best_of,
+                        if versus_match.winner_get().is_some() {
+                            "Rematch"
+                        } else {
+                            "Next Round"
+                        }
+                    ),
+                    TextStyle {
+                        font_size: 120.0,
+                        ..default()
+                    },
+                )
+                .with_text_justify(JustifyText::Center),
+            );
+        });
+}
+
+fn result_update(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut round_ready: EventWriter<RoundReady>,
+) {
+    let start = keyboard_input.just_pressed(KeyCode::Enter)
+        || gamepad_buttons
+            .get_just_pressed()
+            .any(|button| button.button_type == GamepadButtonType::Start);
+    if start {
+        round_ready.send(RoundReady);
+    }
+}
+
+fn round_ready_local(
+    mut round_ready: EventReader<RoundReady>,
+    mut round_start: EventWriter<RoundStart>,
+) {
+    if round_ready.read().count() > 0 {
+        round_start.send(RoundStart);
+    }
+}
+
+fn round_start(
+    mut commands: Commands,
+    mut round_start: EventReader<RoundStart>,
+    boards: Query<Entity, With<Player>>,
+    mut versus_match: ResMut<Match>,
+    replay: Res<Replay>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    if round_start.read().count() == 0 {
+        return;
+    }
+    if versus_match.winner_get().is_some() {
+        versus_match.wins.fill(0);
+    }
+    versus_match.round += 1;
+    versus_match.alive.fill(true);
+    versus_match.end_tick = None;
+    versus_match.over = false;
+    for board in &boards {
+        commands.entity(board).despawn_recursive();
+    }
+    let seed = versus_match.round_seed(replay.seed);
+    for player in 0..versus_match.alive.len() as u8 {
+        commands.spawn(BoardBundle::new(player, &replay.ruleset, seed));
+    }
+    next_screen.set(Screen::Countdown);
+}
+
+// actions meant for the last round are dropped
+fn round_input_reset(
+    mut round_start: EventReader<RoundStart>,
+    mut pending_actions: ResMut<PendingActions>,
+    mut stall: ResMut<Stall>,
+) {
+    if round_start.read().count() > 0 {
+        pending_actions.0.clear();
+        stall.0 = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::versus::*;
+    use crate::TetrisPlugin;
+    use bevy::input::keyboard::{Key, KeyboardInput};
+    use bevy::input::ButtonState;
+
+    fn screen_get(app: &App) -> Screen {
+        app.world().resource::<State<Screen>>().get().clone()
+    }
+
+    fn key_send(app: &mut App, key_code: KeyCode, logical_key: Key, state: ButtonState) {
+        app.world_mut().send_event(KeyboardInput {
+            key_code,
+            logical_key,
+            state,
+            window: Entity::PLACEHOLDER,
+        });
+    }
+
+    fn board_dumps(app: &mut App) -> Vec<String> {
+        let mut boards = app.world_mut().query::<(&Player, &BoardMap)>();
+        let mut dumps: Vec<(u8, String)> = boards
+            .iter(app.world())
+            .map(|(player, board_map)| (player.0, board_map.dump()))
+            .collect();
+        dumps.sort();
+        dumps.into_iter().map(|(_, dump)| dump).collect()
+    }
+
+    // plays the countdown out, checking that nothing moves before it ends
+    fn countdown_play(app: &mut App) {
+        assert_eq!(screen_get(app), Screen::Countdown);
+        let tick = app.world().resource::<Tick>().0;
+        let boards = board_dumps(app);
+        // the first player's move left, held the whole countdown
+        key_send(app, KeyCode::KeyA, Key::Character("a".into()), ButtonState::Pressed);
+        let mut updates = 0;
+        while screen_get(app) == Screen::Countdown {
+            assert_eq!(app.world().resource::<Tick>().0, tick);
+            assert_eq!(board_dumps(app), boards);
+            assert!(updates <= COUNTDOWN_TICKS + 1, "the countdown never ends");
+            app.update();
+            updates += 1;
+        }
+        // the screen changes on the update after the last countdown tick
+        assert!(updates >= COUNTDOWN_TICKS, "{updates} updates");
+        assert_eq!(screen_get(app), Screen::Game);
+        key_send(app, KeyCode::KeyA, Key::Character("a".into()), ButtonState::Released);
+    }
+
+    // fills the board of `player` but for one column, so its next piece has no room
+    fn top_out(app: &mut App, player: u8) {
+        let mut boards = app.world_mut().query::<(&Player, &mut BoardMap)>();
+        for (board_player, mut board_map) in boards.iter_mut(app.world_mut()) {
+            if board_player.0 != player {
+                continue;
+            }
+            for row in 0..20 {
+                for col in 1..10 {
+                    if board_map.tile_get(row, col).is_none() {
+                        board_map.tile_set(row, col, Some(Tetromino::X));
+                    }
+                }
+            }
+        }
+    }
+
+    // tops the second player out and plays until the result shows
+    fn round_lose(app: &mut App) {
+        top_out(app, 1);
+        for _ in 0..600 {
+            if screen_get(app) == Screen::Result {
+                break;
+            }
+            app.update();
+        }
+        assert_eq!(screen_get(app), Screen::Result);
+        let versus_match = app.world().resource::<Match>();
+        assert_eq!(versus_match.alive, [true, false]);
+        assert_eq!(versus_match.round_winner, Some(0));
+        assert!(versus_match.over);
+    }
+
+    fn round_ready(app: &mut App) {
+        key_send(app, KeyCode::Enter, Key::Enter, ButtonState::Pressed);
+        app.update();
+        key_send(app, KeyCode::Enter, Key::Enter, ButtonState::Released);
+        app.update();
+    }
+
+    #[test]
+    fn rounds_count_until_the_best_of_is_won() {
+        let mut app = TetrisPlugin {
+            best_of: Some(3),
+            players: 2,
+            ..default()
+        }
+        .headless();
+        app.update();
+        countdown_play(&mut app);
+
+        round_lose(&mut app);
+        let versus_match = app.world().resource::<Match>();
+        assert_eq!(versus_match.wins, [1, 0]);
+        assert_eq!(versus_match.winner_get(), None);
+        // the round is over, no tick runs until the next one starts
+        let tick = app.world().resource::<Tick>().0;
+        app.update();
+        assert_eq!(app.world().resource::<Tick>().0, tick);
+
+        round_ready(&mut app);
+        let versus_match = app.world().resource::<Match>();
+        assert_eq!(versus_match.round, 1);
+        assert_eq!(versus_match.alive, [true, true]);
+        assert_eq!(versus_match.wins, [1, 0]);
+        let fresh = BoardMap::new(&Ruleset::default());
+        let mut boards = app.world_mut().query::<&BoardMap>();
+        assert_eq!(boards.iter(app.world()).count(), 2);
+        for board_map in boards.iter(app.world()) {
+            assert_eq!(board_map.stack_get(), fresh.stack_get());
+        }
+        countdown_play(&mut app);
+
+        // two wins of three end the match
+        round_lose(&mut app);
+        let versus_match = app.world().resource::<Match>();
+        assert_eq!(versus_match.wins, [2, 0]);
+        assert_eq!(versus_match.winner_get(), Some(0));
+
+        // and the next round is a rematch
+        round_ready(&mut app);
+        let versus_match = app.world().resource::<Match>();
+        assert_eq!(versus_match.wins, [0, 0]);
+        assert_eq!(versus_match.round, 2);
+        countdown_play(&mut app);
+    }
+
+    #[test]
+    fn a_round_is_over_with_one_board_left() {
+        assert!(!round_over(&[true, true]));
+        assert!(round_over(&[true, false]));
+        assert!(round_over(&[false, false]));
+        assert!(!round_over(&[true, false, true]));
+        let mut versus_match = Match::new(5, 2);
+        versus_match.wins = vec![2, 2];
+        assert_eq!(versus_match.winner_get(), None);
+        versus_match.wins[1] = 3;
+        assert_eq!(versus_match.winner_get(), Some(1));
+    }
+}
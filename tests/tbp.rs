@@ -0,0 +1,89 @@
+//! The TBP frontend against `tetris_tbp_stub`, run as a separate process like any bot program.
+
+use bevy::prelude::*;
+use std::time::Instant;
+use tetris_bevy::bot::{BotSettings, Weights};
+use tetris_bevy::input::Tick;
+use tetris_bevy::resource::BoardMap;
+use tetris_bevy::ruleset::{Ruleset, FRAME_RATE};
+use tetris_bevy::tbp::*;
+use tetris_bevy::TetrisPlugin;
+
+fn stub_command() -> Vec<String> {
+    vec![env!("CARGO_BIN_EXE_tetris_tbp_stub").to_string()]
+}
+
+#[test]
+fn stub_shakes_hands_and_suggests() {
+    let mut process = TbpProcess::start(&stub_command()).expect("stub");
+    assert_eq!(process.name, "tetris_tbp_stub");
+    let start = Start {
+        hold: None,
+        queue: vec![Piece::T, Piece::I],
+        combo: 0,
+        back_to_back: false,
+        board: Vec::new(),
+    };
+    process
+        .send(&FrontendMessage::Start(start.clone()))
+        .expect("start");
+    process.send(&FrontendMessage::Suggest).expect("suggest");
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let BotMessage::Suggestion { moves } = process.receive_until(deadline).expect("suggestion")
+    else {
+        panic!("expected a suggestion");
+    };
+    let state = BotState::new(start);
+    let first = moves.first().expect("a move");
+    assert_eq!(first.location.piece, Piece::T);
+    assert!(state.fits(&first.location));
+    // dropped to the floor
+    assert_eq!(
+        first.location.cells().iter().map(|&(_, y)| y).min(),
+        Some(0)
+    );
+}
+
+#[test]
+fn stub_plays_pieces_through_the_frontend() {
+    let mut app = TetrisPlugin {
+        ruleset: Ruleset::default(),
+        seed: 1,
+        players: 1,
+        bot: Some(BotSettings {
+            players: vec![0],
+            pps: 10.,
+            tbp: Some(TbpBots::start(&stub_command(), &[0]).expect("stub")),
+            weights: Weights::default(),
+        }),
+        ..default()
+    }
+    .headless();
+    let pieces = loop {
+        app.update();
+        let world = app.world_mut();
+        let tick = world.resource::<Tick>().0;
+        let board_map = world
+            .query::<&BoardMap>()
+            .iter(world)
+            .next()
+            .expect("no board");
+        assert!(board_map.top_out_get().is_none(), "topped out");
+        if board_map.pieces_get() >= 5 || tick >= 10 * FRAME_RATE as u64 {
+            break board_map.pieces_get();
+        }
+    };
+    assert!(pieces >= 5, "{pieces} pieces");
+}
+
+#[test]
+fn missing_programs_are_errors() {
+    let args: Vec<String> = ["tetris", "--tbp", "no_such_tbp_bot --flag"]
+        .map(str::to_string)
+        .into();
+    let Err(e) = TbpBots::args_parse(&args, &[0]) else {
+        panic!("started a missing program");
+    };
+    assert!(e.starts_with("failed to start bot no_such_tbp_bot --flag"), "{e}");
+    assert!(TbpBots::args_parse(&args[..1], &[0]).expect("no flag").is_none());
+}